@@ -0,0 +1,81 @@
+// Compare hashing throughput across ROM storage backings (heap vs huge pages).
+//
+//   cargo bench --bench rom_backing
+//
+// ROM_BENCH_SIZE_MB (default 1024) and ROM_BENCH_HASHES (default 20000) control the run.
+// Huge pages only help when the ROM is much larger than what the TLB covers with 4 KiB
+// pages, so keep the default 1 GiB for numbers comparable to production.
+
+use rayon::prelude::*;
+use std::time::Instant;
+
+// Shared with the library; unused_imports covers the #[cfg(test)] modules, which have no
+// tests to run in a harness-less bench
+#[allow(dead_code, unused_imports)]
+mod error {
+    include!("../src/error.rs");
+}
+#[allow(dead_code, unused_imports)]
+mod hashengine {
+    include!("../src/hashengine.rs");
+}
+#[allow(dead_code, unused_imports)]
+mod rom {
+    include!("../src/rom.rs");
+}
+#[allow(dead_code, unused_imports)]
+mod rom_storage {
+    include!("../src/rom_storage.rs");
+}
+
+use hashengine::{Hasher, Preimage};
+use rom::{Rom, RomGenerationType};
+use rom_storage::RomBacking;
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+fn main() {
+    let size = env_or("ROM_BENCH_SIZE_MB", 1024) * 1024 * 1024;
+    let nb_hashes = env_or("ROM_BENCH_HASHES", 20_000) as u64;
+    let gen_type = RomGenerationType::TwoStep {
+        pre_size: 16 * 1024 * 1024,
+        mixing_numbers: 4,
+    };
+
+    println!("ROM size: {} MiB, hashes per backing: {}", size / (1024 * 1024), nb_hashes);
+
+    for backing in [RomBacking::Heap, RomBacking::HugePages] {
+        let start = Instant::now();
+        let rom = Rom::with_backing(b"rom-backing-benchmark", gen_type, size, backing).unwrap();
+        let init = start.elapsed();
+
+        let start = Instant::now();
+        let winners = (0..nb_hashes)
+            .into_par_iter()
+            .map_init(
+                || {
+                    let preimage = Preimage::new("addr_bench", "**BENCH1", "000FFFFF", "00", "2025-01-01T00:00:00.000Z", "0");
+                    (Hasher::default(), preimage)
+                },
+                |(hasher, preimage), nonce| {
+                    preimage.set_nonce(nonce);
+                    hasher.hash(preimage.as_bytes(), &rom)[0] == 0
+                },
+            )
+            .filter(|&zero| zero)
+            .count();
+        let hashing = start.elapsed();
+
+        println!(
+            "{:<14} init {:>6.2}s  hashing {:>6.2}s  {:>9.0} H/s  (requested {:?}, {} zero-prefixed)",
+            rom.backing().as_str(),
+            init.as_secs_f64(),
+            hashing.as_secs_f64(),
+            nb_hashes as f64 / hashing.as_secs_f64(),
+            backing,
+            winners,
+        );
+    }
+}
@@ -0,0 +1,273 @@
+// Test miner for hash-server's pool mode: connects to POOL_PORT, subscribes, authorizes,
+// builds the job's ROM locally and mines its extranonce range, submitting shares and
+// solutions as it finds them.
+//
+//   POOL_PORT=3333 cargo run --release --bin hash-server
+//   cargo run --release --example pool_miner -- --pool 127.0.0.1:3333 --worker rig1
+//
+// Options: --pool HOST:PORT (default 127.0.0.1:3333), --worker NAME (default test-miner),
+// --password PASSWORD (for POOL_PASSWORD), --shares N (exit once N shares are accepted,
+// for scripted local tests; default: mine until the pool disconnects).
+
+use rayon::prelude::*;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+// Shared with the library and hash-server
+#[allow(dead_code, unused_imports)]
+mod error {
+    include!("../src/error.rs");
+}
+#[allow(dead_code, unused_imports)]
+mod hashengine {
+    include!("../src/hashengine.rs");
+}
+#[allow(dead_code, unused_imports)]
+mod rom {
+    include!("../src/rom.rs");
+}
+#[allow(dead_code, unused_imports)]
+mod rom_storage {
+    include!("../src/rom_storage.rs");
+}
+#[allow(dead_code, unused_imports)]
+mod stratum {
+    include!("../src/stratum.rs");
+}
+
+use hashengine::{hash_structure_good, Difficulty, Hasher, Preimage};
+use rom::{Rom, RomGenerationType};
+use stratum::{JobNotify, PoolMessage, Request, Response, RomParams, SubmitResult, SubscribeResult};
+
+// Nonces hashed between checks for new jobs and submit results
+const CHUNK_NONCES: u64 = 16 * 1024;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Options {
+    pool: String,
+    worker: String,
+    password: Option<String>,
+    shares: Option<u64>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options { pool: "127.0.0.1:3333".to_string(), worker: "test-miner".to_string(), password: None, shares: None };
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+        match flag.as_str() {
+            "--pool" => options.pool = value,
+            "--worker" => options.worker = value,
+            "--password" => options.password = Some(value),
+            "--shares" => options.shares = Some(value.parse().map_err(|_| format!("invalid --shares {:?}", value))?),
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    Ok(options)
+}
+
+#[derive(Default)]
+struct Counters {
+    hashes: u64,
+    accepted: u64,
+    rejected: u64,
+    solutions: u64,
+}
+
+struct Connection {
+    stream: TcpStream,
+    messages: Receiver<PoolMessage>,
+    next_id: u64,
+    /// Latest job received while waiting for something else
+    pending_job: Option<JobNotify>,
+}
+
+impl Connection {
+    fn open(pool: &str) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(pool)?;
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (tx, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else { break };
+                match serde_json::from_str::<PoolMessage>(&line) {
+                    Ok(message) => {
+                        if tx.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("Ignoring unexpected line from the pool ({}): {}", e, line),
+                }
+            }
+        });
+        Ok(Self { stream, messages, next_id: 1, pending_job: None })
+    }
+
+    fn send(&mut self, method: &str, params: serde_json::Value) -> std::io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        writeln!(self.stream, "{}", stratum::to_line(&Request::new(id, method, params)))?;
+        Ok(id)
+    }
+
+    /// Send a request and wait for its response
+    fn call(&mut self, method: &str, params: serde_json::Value) -> Result<serde_json::Value, String> {
+        let id = self.send(method, params).map_err(|e| e.to_string())?;
+        loop {
+            match self.messages.recv().map_err(|_| "pool closed the connection".to_string())? {
+                PoolMessage::Response(Response { id: Some(got), result, error }) if got == id => {
+                    return match error {
+                        Some(e) => Err(format!("{} failed: {} (code {})", method, e.message, e.code)),
+                        None => Ok(result),
+                    };
+                }
+                message => self.pending_job = job_of(message).or(self.pending_job.take()),
+            }
+        }
+    }
+
+    /// Wait for the next job
+    fn next_job(&mut self) -> Option<JobNotify> {
+        if let Some(job) = self.pending_job.take() {
+            return Some(job);
+        }
+        loop {
+            if let Some(job) = job_of(self.messages.recv().ok()?) {
+                return Some(job);
+            }
+        }
+    }
+
+    /// Handle what arrived while mining: count submit results and return a newer job, if any.
+    /// Err when the pool closed the connection.
+    fn poll(&mut self, counters: &mut Counters) -> Result<Option<JobNotify>, ()> {
+        loop {
+            match self.messages.try_recv() {
+                Ok(PoolMessage::Response(response)) => match response.error {
+                    Some(e) => {
+                        counters.rejected += 1;
+                        eprintln!("Submit {:?} rejected: {} (code {})", response.id, e.message, e.code);
+                    }
+                    None => {
+                        let result: SubmitResult = serde_json::from_value(response.result).unwrap_or(SubmitResult { accepted: false, solution: false });
+                        counters.accepted += u64::from(result.accepted);
+                        counters.solutions += u64::from(result.solution);
+                    }
+                },
+                Ok(message) => self.pending_job = job_of(message).or(self.pending_job.take()),
+                Err(TryRecvError::Empty) => return Ok(self.pending_job.take()),
+                Err(TryRecvError::Disconnected) => return Err(()),
+            }
+        }
+    }
+}
+
+fn job_of(message: PoolMessage) -> Option<JobNotify> {
+    match message {
+        PoolMessage::Notification(n) if n.method == stratum::NOTIFY => serde_json::from_value(n.params).ok(),
+        _ => None,
+    }
+}
+
+fn build_rom(job: &JobNotify) -> Result<Rom, String> {
+    let params = &job.rom;
+    let gen_type = RomGenerationType::from_config(&params.generation, params.pre_size, params.mixing_numbers).map_err(|e| e.to_string())?;
+    println!("Building {} MiB ROM for no_pre_mine {}...", params.rom_size / (1024 * 1024), &job.no_pre_mine);
+    let start = Instant::now();
+    let rom = Rom::new(job.no_pre_mine.as_bytes(), gen_type, params.rom_size).map_err(|e| e.to_string())?;
+    println!("ROM ready in {:.1?}", start.elapsed());
+    Ok(rom)
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let mut pool = Connection::open(&options.pool).map_err(|e| format!("cannot connect to {}: {}", options.pool, e))?;
+    let subscribed: SubscribeResult =
+        serde_json::from_value(pool.call(stratum::SUBSCRIBE, serde_json::json!(["pool_miner/0.1"]))?).map_err(|e| e.to_string())?;
+    pool.call(stratum::AUTHORIZE, serde_json::json!([options.worker, options.password]))?;
+    println!("Connected to {} as {} (extranonce {})", options.pool, options.worker, subscribed.extranonce);
+
+    let mut counters = Counters::default();
+    let mut rom: Option<(String, RomParams, Rom)> = None;
+    let mut job = pool.next_job().ok_or("pool closed the connection")?;
+    let started = Instant::now();
+    let mut last_report = Instant::now();
+
+    'jobs: loop {
+        println!("Job {}: challenge {} for {}, shares at {} zero bits", job.job_id, job.challenge_id, job.address, job.share_zero_bits);
+        if !matches!(&rom, Some((key, params, _)) if *key == job.no_pre_mine && *params == job.rom) {
+            rom = Some((job.no_pre_mine.clone(), job.rom.clone(), build_rom(&job)?));
+        }
+        let rom_data = &rom.as_ref().unwrap().2;
+        let difficulty = Difficulty::parse(&job.difficulty).map_err(|e| e.to_string())?;
+        let extranonce = u32::from_str_radix(&job.extranonce, 16).map_err(|_| format!("invalid extranonce {:?}", job.extranonce))?;
+        let base = (extranonce as u64) << stratum::MINER_NONCE_BITS;
+        let new_preimage =
+            || Preimage::new(&job.address, &job.challenge_id, &job.difficulty, &job.no_pre_mine, &job.latest_submission, &job.no_pre_mine_hour);
+
+        let mut offset = 0u64;
+        while offset < 1 << stratum::MINER_NONCE_BITS {
+            let chunk = base + offset..base + offset + CHUNK_NONCES;
+            let hits: Vec<(u64, bool)> = chunk
+                .into_par_iter()
+                .map_init(
+                    || (Hasher::default(), new_preimage()),
+                    |(hasher, preimage), nonce| {
+                        preimage.set_nonce(nonce);
+                        let hash = hasher.hash(preimage.as_bytes(), rom_data);
+                        (nonce, hash_structure_good(&hash, job.share_zero_bits), difficulty.accepts(&hash))
+                    },
+                )
+                .filter(|&(_, share, _)| share)
+                .map(|(nonce, _, solution)| (nonce, solution))
+                .collect();
+            counters.hashes += CHUNK_NONCES;
+            offset += CHUNK_NONCES;
+
+            for (nonce, solution) in hits {
+                let method = if solution { stratum::SUBMIT_SOLUTION } else { stratum::SUBMIT };
+                if solution {
+                    println!("Solution for {}: nonce {:016x}", job.address, nonce);
+                }
+                pool.send(method, serde_json::json!([options.worker, job.job_id, format!("{:016x}", nonce)]))
+                    .map_err(|e| format!("submit failed: {}", e))?;
+            }
+
+            let newer = pool.poll(&mut counters).map_err(|_| "pool closed the connection")?;
+            if options.shares.is_some_and(|target| counters.accepted >= target) {
+                break 'jobs;
+            }
+            if last_report.elapsed() >= REPORT_INTERVAL {
+                last_report = Instant::now();
+                println!(
+                    "{:.0} H/s, {} accepted, {} rejected, {} solutions",
+                    counters.hashes as f64 / started.elapsed().as_secs_f64(),
+                    counters.accepted,
+                    counters.rejected,
+                    counters.solutions
+                );
+            }
+            if let Some(newer) = newer {
+                job = newer;
+                continue 'jobs;
+            }
+        }
+
+        println!("Extranonce range exhausted, waiting for the next job");
+        job = pool.next_job().ok_or("pool closed the connection")?;
+    }
+
+    println!("Done: {} hashes, {} accepted, {} rejected, {} solutions", counters.hashes, counters.accepted, counters.rejected, counters.solutions);
+    Ok(())
+}
+
+fn main() {
+    let result = parse_options().and_then(run);
+    if let Err(e) = result {
+        eprintln!("pool_miner: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::error::HashEngineError;
+
+// Binary framing for POST /hash-batch-bin (Content-Type: application/octet-stream).
+// All integers little endian.
+//
+// Request, version 1:
+//   magic          4 bytes  "HEBQ"
+//   version        1 byte   1
+//   key_len        2 bytes  length of no_pre_mine (0 = the default ROM)
+//   no_pre_mine    key_len bytes, UTF-8
+//   count          4 bytes  number of preimages
+//   count times:
+//     len          4 bytes
+//     preimage     len bytes, hashed as is
+//
+// Response, version 1:
+//   magic          4 bytes  "HEBS"
+//   version        1 byte   1
+//   count          4 bytes
+//   digests        count * 64 bytes, in request order
+//
+// A decoder rejects other versions, so the layout can change behind a version bump.
+const REQUEST_MAGIC: &[u8; 4] = b"HEBQ";
+const RESPONSE_MAGIC: &[u8; 4] = b"HEBS";
+
+/// Framing version written by the encoders and accepted by the decoders
+pub const VERSION: u8 = 1;
+
+/// Content type of both request and response frames
+pub const CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Size of one hash in a response frame
+pub const DIGEST_LEN: usize = 64;
+
+/// A decoded request frame, borrowing the preimages from the frame bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchRequest<'a> {
+    /// Target ROM, None for the default one
+    pub no_pre_mine: Option<&'a str>,
+    pub preimages: Vec<&'a [u8]>,
+}
+
+impl<'a> BatchRequest<'a> {
+    pub fn decode(frame: &'a [u8]) -> Result<Self, HashEngineError> {
+        let mut input = Reader(frame);
+        input.header(REQUEST_MAGIC)?;
+        let key_len = u16::from_le_bytes(input.array()?) as usize;
+        let no_pre_mine = match key_len {
+            0 => None,
+            n => Some(std::str::from_utf8(input.take(n)?).map_err(|_| invalid("no_pre_mine is not valid UTF-8"))?),
+        };
+
+        let count = u32::from_le_bytes(input.array()?) as usize;
+        // Each preimage takes at least its 4-byte length, so a bogus count cannot make
+        // us allocate more than the frame size
+        if count > input.0.len() / 4 {
+            return Err(invalid(&format!("count {} exceeds the frame size", count)));
+        }
+        let mut preimages = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = u32::from_le_bytes(input.array()?) as usize;
+            preimages.push(input.take(len)?);
+        }
+        input.finish()?;
+        Ok(Self { no_pre_mine, preimages })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, HashEngineError> {
+        encode_request(self.no_pre_mine, &self.preimages)
+    }
+}
+
+/// Encode a request frame. Fails if `no_pre_mine` is longer than 65535 bytes or a
+/// preimage or the count does not fit in 32 bits.
+pub fn encode_request<P: AsRef<[u8]>>(no_pre_mine: Option<&str>, preimages: &[P]) -> Result<Vec<u8>, HashEngineError> {
+    let key = no_pre_mine.unwrap_or("").as_bytes();
+    let key_len = u16::try_from(key.len()).map_err(|_| invalid("no_pre_mine is longer than 65535 bytes"))?;
+    let count = u32::try_from(preimages.len()).map_err(|_| invalid("too many preimages"))?;
+
+    let body: usize = preimages.iter().map(|p| 4 + p.as_ref().len()).sum();
+    let mut out = Vec::with_capacity(4 + 1 + 2 + key.len() + 4 + body);
+    out.extend_from_slice(REQUEST_MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&key_len.to_le_bytes());
+    out.extend_from_slice(key);
+    out.extend_from_slice(&count.to_le_bytes());
+    for preimage in preimages {
+        let preimage = preimage.as_ref();
+        let len = u32::try_from(preimage.len()).map_err(|_| invalid("preimage is longer than 4 GiB"))?;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(preimage);
+    }
+    Ok(out)
+}
+
+/// Encode a response frame
+pub fn encode_response(digests: &[[u8; DIGEST_LEN]]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + 1 + 4 + digests.len() * DIGEST_LEN);
+    out.extend_from_slice(RESPONSE_MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&(digests.len() as u32).to_le_bytes());
+    for digest in digests {
+        out.extend_from_slice(digest);
+    }
+    out
+}
+
+/// Decode a response frame into its digests, in request order
+pub fn decode_response(frame: &[u8]) -> Result<Vec<[u8; DIGEST_LEN]>, HashEngineError> {
+    let mut input = Reader(frame);
+    input.header(RESPONSE_MAGIC)?;
+    let count = u32::from_le_bytes(input.array()?) as usize;
+    if input.0.len() != count.saturating_mul(DIGEST_LEN) {
+        return Err(invalid(&format!("{} bytes of digests for a count of {}", input.0.len(), count)));
+    }
+    let digests = input.0.chunks_exact(DIGEST_LEN).map(|d| d.try_into().unwrap()).collect();
+    Ok(digests)
+}
+
+fn invalid(reason: &str) -> HashEngineError {
+    HashEngineError::BatchFrame(reason.to_string())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], HashEngineError> {
+        if self.0.len() < n {
+            return Err(invalid("frame is truncated"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], HashEngineError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn header(&mut self, magic: &[u8; 4]) -> Result<(), HashEngineError> {
+        if self.take(4)? != magic {
+            return Err(invalid("bad magic"));
+        }
+        match self.array::<1>()?[0] {
+            VERSION => Ok(()),
+            other => Err(invalid(&format!("unsupported version {} (expected {})", other, VERSION))),
+        }
+    }
+
+    fn finish(&self) -> Result<(), HashEngineError> {
+        match self.0.len() {
+            0 => Ok(()),
+            n => Err(invalid(&format!("{} trailing bytes", n))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip_and_reject_malformed_input() {
+        let preimages: Vec<&[u8]> = vec![b"0000000000000001addr", b"", &[0xff, 0x00, 0x80]];
+        let frame = encode_request(Some("e8a195800b"), &preimages).unwrap();
+        let decoded = BatchRequest::decode(&frame).unwrap();
+        assert_eq!(decoded, BatchRequest { no_pre_mine: Some("e8a195800b"), preimages: preimages.clone() });
+        assert_eq!(decoded.encode().unwrap(), frame);
+
+        let default_rom = encode_request::<&[u8]>(None, &[]).unwrap();
+        assert_eq!(default_rom, b"HEBQ\x01\x00\x00\x00\x00\x00\x00");
+        assert_eq!(BatchRequest::decode(&default_rom).unwrap().no_pre_mine, None);
+
+        let digests = [[1u8; DIGEST_LEN], [2u8; DIGEST_LEN]];
+        let response = encode_response(&digests);
+        assert_eq!(response.len(), 9 + 2 * DIGEST_LEN);
+        assert_eq!(decode_response(&response).unwrap(), digests);
+
+        // Truncation, trailing bytes, wrong magic/version and oversized counts
+        assert!(BatchRequest::decode(&frame[..frame.len() - 1]).is_err());
+        assert!(BatchRequest::decode(&[frame.as_slice(), b"x"].concat()).is_err());
+        assert!(decode_response(&response[..response.len() - 1]).is_err());
+        assert!(BatchRequest::decode(&response).is_err());
+        let mut future = frame.clone();
+        future[4] = 2;
+        assert_eq!(
+            BatchRequest::decode(&future),
+            Err(HashEngineError::BatchFrame("unsupported version 2 (expected 1)".to_string()))
+        );
+        let huge_count = [b"HEBQ\x01\x00\x00".as_slice(), &u32::MAX.to_le_bytes()].concat();
+        assert!(BatchRequest::decode(&huge_count).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::ErrorResponse;
+
+use actix_web::http::header;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// Admission control for requests that hash on the rayon pool. Up to `max_running` run at
+// once, up to `max_queued` more wait for a slot, and anything beyond that is turned away
+// with 429 so a burst cannot pile unbounded work and memory onto the pool.
+
+pub struct Admission {
+    slots: Semaphore,
+    max_running: usize,
+    max_queued: usize,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+    retry_after_secs: u64,
+}
+
+/// Holds a running slot until dropped
+pub struct Admitted<'a> {
+    _permit: SemaphorePermit<'a>,
+}
+
+/// Leaves the queue when dropped, including when the client goes away while waiting
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Admission state for /health
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionStatus {
+    pub running: usize,
+    pub queued: usize,
+    pub max_running: usize,
+    pub max_queued: usize,
+    /// Requests turned away with 429 since startup
+    pub rejected: u64,
+    /// Every slot is busy and the queue is full, so new batches are being rejected
+    pub saturated: bool,
+}
+
+impl Admission {
+    pub fn new(max_running: usize, max_queued: usize, retry_after_secs: u64) -> Self {
+        let max_running = max_running.max(1);
+        Self {
+            slots: Semaphore::new(max_running),
+            max_running,
+            max_queued,
+            queued: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            retry_after_secs,
+        }
+    }
+
+    /// Wait for a running slot, or Err with a 429 response when the queue is full
+    pub async fn admit(&self) -> Result<Admitted<'_>, HttpResponse> {
+        if let Ok(permit) = self.slots.try_acquire() {
+            return Ok(Admitted { _permit: permit });
+        }
+        if self.queued.fetch_add(1, Ordering::Relaxed) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(self.too_busy());
+        }
+        let _queued = Queued(&self.queued);
+        let permit = self.slots.acquire().await.expect("admission semaphore is never closed");
+        Ok(Admitted { _permit: permit })
+    }
+
+    /// Seconds clients are told to wait before retrying a 429
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after_secs
+    }
+
+    fn too_busy(&self) -> HttpResponse {
+        HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, self.retry_after_secs.to_string()))
+            .json(ErrorResponse {
+                error: format!("Server is busy: {} batches running and {} queued; retry later", self.max_running, self.max_queued),
+            })
+    }
+
+    pub fn status(&self) -> AdmissionStatus {
+        let queued = self.queued.load(Ordering::Relaxed);
+        AdmissionStatus {
+            running: self.max_running - self.slots.available_permits(),
+            queued,
+            max_running: self.max_running,
+            max_queued: self.max_queued,
+            rejected: self.rejected.load(Ordering::Relaxed),
+            saturated: queued >= self.max_queued && self.slots.available_permits() == 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn requests_beyond_the_queue_are_rejected() {
+        let admission = Admission::new(1, 1, 3);
+        let running = admission.admit().await.unwrap();
+        assert_eq!((admission.status().running, admission.status().queued), (1, 0));
+
+        // The second request waits for the slot, the third finds the queue full
+        let mut waiting = Box::pin(admission.admit());
+        assert!(futures_poll(&mut waiting).is_none());
+        assert_eq!(admission.status().queued, 1);
+        assert!(admission.status().saturated);
+
+        let rejected = admission.admit().await.err().unwrap();
+        assert_eq!(rejected.status(), actix_web::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rejected.headers().get(header::RETRY_AFTER).unwrap(), "3");
+        assert_eq!(admission.status().rejected, 1);
+
+        drop(running);
+        let admitted = waiting.await.unwrap();
+        let status = admission.status();
+        assert_eq!((status.running, status.queued, status.saturated), (1, 0, false));
+        drop(admitted);
+        assert_eq!(admission.status().running, 0);
+    }
+
+    /// Poll a future once without waiting
+    fn futures_poll<F: std::future::Future + Unpin>(future: &mut F) -> Option<F::Output> {
+        let waker = std::task::Waker::noop();
+        match std::pin::Pin::new(future).poll(&mut std::task::Context::from_waker(waker)) {
+            std::task::Poll::Ready(output) => Some(output),
+            std::task::Poll::Pending => None,
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{ErrorResponse, AUTH};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::HttpResponse;
+use log::{error, warn};
+
+use std::fmt;
+
+// Optional bearer-token authentication. Tokens come from AUTH_TOKENS (comma-separated
+// `scope:token` pairs) and/or AUTH_TOKENS_FILE (one `scope token` pair per line, # for
+// comments). With neither set every request is allowed, as before.
+
+/// What a token may do. `Admin` includes everything `Hash` allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Hashing, searching, jobs and read-only status
+    Hash,
+    /// Loading and managing ROMs and pool jobs
+    Admin,
+}
+
+impl Scope {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "hash" | "read" => Ok(Scope::Hash),
+            "admin" => Ok(Scope::Admin),
+            other => Err(format!("unknown scope {:?} (expected hash or admin)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Hash => "hash",
+            Scope::Admin => "admin",
+        })
+    }
+}
+
+/// Why a request was refused
+#[derive(Debug, PartialEq, Eq)]
+pub enum Denied {
+    /// No token, or not a known one (401)
+    Unauthenticated,
+    /// A valid token without the scope the endpoint needs (403)
+    Forbidden { needed: Scope },
+}
+
+pub struct Auth {
+    tokens: Vec<(String, Scope)>,
+}
+
+impl Auth {
+    /// Tokens from AUTH_TOKENS and AUTH_TOKENS_FILE; None when neither is set.
+    /// A broken configuration gives an `Auth` without tokens, so every request is
+    /// refused rather than the server running unprotected.
+    pub fn from_env() -> Option<Self> {
+        let inline = std::env::var("AUTH_TOKENS").ok().filter(|v| !v.trim().is_empty());
+        let file = std::env::var("AUTH_TOKENS_FILE").ok().filter(|v| !v.trim().is_empty());
+        if inline.is_none() && file.is_none() {
+            return None;
+        }
+
+        let mut auth = Auth::new([]);
+        let loaded = (|| {
+            if let Some(inline) = &inline {
+                auth.add_all(inline.split(',').map(|entry| entry.split_once(':')))?;
+            }
+            if let Some(path) = &file {
+                let contents = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+                let lines = contents.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#'));
+                auth.add_all(lines.map(|line| line.split_once(char::is_whitespace)))?;
+            }
+            Ok::<_, String>(())
+        })();
+        if let Err(e) = loaded {
+            error!("Invalid auth configuration, refusing every request: {}", e);
+            auth.tokens.clear();
+        }
+        Some(auth)
+    }
+
+    pub fn new(tokens: impl IntoIterator<Item = (String, Scope)>) -> Self {
+        Self { tokens: tokens.into_iter().collect() }
+    }
+
+    fn add_all<'a>(&mut self, entries: impl Iterator<Item = Option<(&'a str, &'a str)>>) -> Result<(), String> {
+        for entry in entries {
+            let (scope, token) = entry.ok_or("expected scope and token")?;
+            let token = token.trim();
+            if token.is_empty() {
+                return Err(format!("empty token for scope {}", scope.trim()));
+            }
+            self.tokens.push((token.to_string(), Scope::parse(scope)?));
+        }
+        Ok(())
+    }
+
+    /// Number of configured tokens per scope, for the startup log
+    pub fn counts(&self) -> (usize, usize) {
+        let admin = self.tokens.iter().filter(|(_, scope)| *scope == Scope::Admin).count();
+        (self.tokens.len() - admin, admin)
+    }
+
+    /// Check an Authorization header value against the scope an endpoint needs
+    pub fn check(&self, authorization: Option<&str>, needed: Scope) -> Result<(), Denied> {
+        let presented = authorization
+            .and_then(|value| value.strip_prefix("Bearer ").or_else(|| value.strip_prefix("bearer ")))
+            .map(str::trim)
+            .ok_or(Denied::Unauthenticated)?;
+        // Compare against every token so the time taken does not tell which one matched
+        let mut granted = None;
+        for (token, scope) in &self.tokens {
+            if constant_time_eq(token.as_bytes(), presented.as_bytes()) {
+                granted = granted.max(Some(*scope));
+            }
+        }
+        match granted {
+            None => Err(Denied::Unauthenticated),
+            Some(scope) if scope >= needed => Ok(()),
+            Some(_) => Err(Denied::Forbidden { needed }),
+        }
+    }
+}
+
+/// Compare secrets without an early exit that would leak how much of them matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Scope an endpoint needs, None for the ones open to everyone (liveness checks)
+pub fn required_scope(method: &Method, pattern: &str) -> Option<Scope> {
+    match (method, pattern) {
+        (&Method::GET, "/health") => None,
+        (&Method::POST, "/init" | "/rom/prepare" | "/rom/activate" | "/pool/job") => Some(Scope::Admin),
+        // Verification regenerates ROMs that fail it
+        (&Method::GET, "/rom/verify") => Some(Scope::Admin),
+        _ => Some(Scope::Hash),
+    }
+}
+
+/// Middleware enforcing the configured tokens; rejected requests are logged
+pub async fn require_token(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let Some(auth) = AUTH.as_ref() else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    let pattern = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+    let Some(needed) = required_scope(req.method(), &pattern) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+
+    let authorization = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    let denied = match auth.check(authorization, needed) {
+        Ok(()) => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+        Err(denied) => denied,
+    };
+
+    let peer = req.peer_addr().map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+    let response = match denied {
+        Denied::Unauthenticated => {
+            warn!("Rejected {} {} from {}: missing or unknown token", req.method(), req.path(), peer);
+            HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .json(ErrorResponse { error: "A valid bearer token is required".to_string() })
+        }
+        Denied::Forbidden { needed } => {
+            warn!("Rejected {} {} from {}: token lacks the {} scope", req.method(), req.path(), peer, needed);
+            HttpResponse::Forbidden().json(ErrorResponse { error: format!("This endpoint needs a token with the {} scope", needed) })
+        }
+    };
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_checked_against_endpoint_scopes() {
+        let auth = Auth::new([("h-token".to_string(), Scope::Hash), ("a-token".to_string(), Scope::Admin)]);
+        assert_eq!(auth.counts(), (1, 1));
+
+        let init = required_scope(&Method::POST, "/init").unwrap();
+        let hash = required_scope(&Method::POST, "/hash-batch").unwrap();
+        assert_eq!((init, hash), (Scope::Admin, Scope::Hash));
+        assert_eq!(required_scope(&Method::GET, "/jobs/{id}"), Some(Scope::Hash));
+        assert_eq!(required_scope(&Method::GET, "/health"), None);
+
+        assert_eq!(auth.check(Some("Bearer h-token"), hash), Ok(()));
+        assert_eq!(auth.check(Some("Bearer a-token"), hash), Ok(()));
+        assert_eq!(auth.check(Some("Bearer a-token"), init), Ok(()));
+        assert_eq!(auth.check(Some("Bearer h-token"), init), Err(Denied::Forbidden { needed: Scope::Admin }));
+        assert_eq!(auth.check(Some("Bearer h-token2"), hash), Err(Denied::Unauthenticated));
+        assert_eq!(auth.check(Some("h-token"), hash), Err(Denied::Unauthenticated));
+        assert_eq!(auth.check(None, hash), Err(Denied::Unauthenticated));
+
+        // Both config formats, and a broken one
+        let mut parsed = Auth::new([]);
+        parsed.add_all("hash:one, admin:two".split(',').map(|e| e.split_once(':'))).unwrap();
+        parsed.add_all("read three".lines().map(|l| l.split_once(char::is_whitespace))).unwrap();
+        assert_eq!(parsed.counts(), (2, 1));
+        assert!(parsed.add_all(["root:x"].into_iter().map(|e| e.split_once(':'))).is_err());
+        assert!(parsed.add_all(["admin"].into_iter().map(|e| e.split_once(':'))).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::hashengine::{Difficulty, Hasher, Preimage};
+use crate::numa::RomReplicas;
+use crate::{key_prefix, lookup_rom, parse_nonce_range, ErrorResponse, ADMISSION, JOBS, METRICS};
+
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Hashes a worker does between updates of the job's counter (same as `spin`)
+const REPORT_EVERY: u64 = 0xff;
+
+// Finished jobs kept for GET /jobs/{id}, oldest dropped first
+const MAX_FINISHED_JOBS: usize = 1024;
+
+/// POST /jobs - Mine a nonce range for one address in the background
+#[derive(Debug, Deserialize)]
+pub struct JobRequest {
+    address: String,
+    challenge_id: String,
+    difficulty: String,
+    no_pre_mine: String,
+    latest_submission: String,
+    no_pre_mine_hour: String,
+    /// First nonce to try, as 16 hex chars (same encoding as /search)
+    start_nonce: String,
+    nonce_count: u64,
+    /// Hashing pool threads to use (default and maximum: `job_thread_cap`)
+    #[serde(default)]
+    threads: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    /// A nonce meeting the difficulty was found
+    Found,
+    /// The whole range was hashed without a solution
+    Exhausted,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobStatus {
+    job_id: String,
+    state: JobState,
+    address: String,
+    challenge_id: String,
+    threads: usize,
+    start_nonce: String,
+    nonce_count: u64,
+    hashes: u64,
+    hash_rate: f64,
+    elapsed_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+}
+
+struct Outcome {
+    state: JobState,
+    found: Option<(u64, [u8; 64])>,
+    finished_at: Option<Instant>,
+}
+
+/// A job and the stop signal its workers poll
+pub struct MiningJob {
+    id: String,
+    request: JobRequest,
+    difficulty: Difficulty,
+    start: u64,
+    end: u64,
+    threads: usize,
+    started: Instant,
+    hashes: AtomicU64,
+    stop_signal: AtomicBool,
+    cancelled: AtomicBool,
+    workers_left: AtomicUsize,
+    outcome: Mutex<Outcome>,
+}
+
+impl MiningJob {
+    /// Record a solution unless another worker got there first, and stop the others
+    fn found(&self, nonce: u64, hash: [u8; 64]) {
+        let mut outcome = self.outcome.lock().unwrap();
+        if outcome.found.is_none() {
+            outcome.found = Some((nonce, hash));
+        }
+        self.stop_signal.store(true, Ordering::Relaxed);
+    }
+
+    /// Called by each worker on exit; the last one settles the final state
+    fn worker_done(&self) {
+        if self.workers_left.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        let mut outcome = self.outcome.lock().unwrap();
+        outcome.state = match outcome.found {
+            Some(_) => JobState::Found,
+            None if self.cancelled.load(Ordering::Relaxed) => JobState::Cancelled,
+            None => JobState::Exhausted,
+        };
+        outcome.finished_at = Some(Instant::now());
+        info!("Job {} {:?} after {} hashes", self.id, outcome.state, self.hashes.load(Ordering::Relaxed));
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.stop_signal.store(true, Ordering::Relaxed);
+    }
+
+    fn status(&self) -> JobStatus {
+        let outcome = self.outcome.lock().unwrap();
+        let elapsed = outcome.finished_at.unwrap_or_else(Instant::now).duration_since(self.started);
+        let hashes = self.hashes.load(Ordering::Relaxed);
+        JobStatus {
+            job_id: self.id.clone(),
+            state: outcome.state,
+            address: self.request.address.clone(),
+            challenge_id: self.request.challenge_id.clone(),
+            threads: self.threads,
+            start_nonce: self.request.start_nonce.clone(),
+            nonce_count: self.request.nonce_count,
+            hashes,
+            hash_rate: hashes as f64 / elapsed.as_secs_f64().max(1e-9),
+            elapsed_secs: elapsed.as_secs_f64(),
+            nonce: outcome.found.map(|(nonce, _)| format!("{:016x}", nonce)),
+            hash: outcome.found.map(|(_, hash)| hex::encode(hash)),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.outcome.lock().unwrap().state != JobState::Running
+    }
+}
+
+/// Jobs started with POST /jobs, running and recently finished
+pub struct MiningJobs {
+    jobs: Mutex<HashMap<String, Arc<MiningJob>>>,
+    next_id: AtomicU64,
+    max_running: usize,
+}
+
+impl MiningJobs {
+    pub fn new(max_running: usize) -> Self {
+        Self { jobs: Mutex::new(HashMap::new()), next_id: AtomicU64::new(0), max_running: max_running.max(1) }
+    }
+
+    /// Start `request` on `threads` workers of the hashing pool, or None when `max_running`
+    /// jobs are already running
+    fn start(&self, request: JobRequest, difficulty: Difficulty, (start, end): (u64, u64), threads: usize, rom: RomReplicas) -> Option<Arc<MiningJob>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.values().filter(|job| !job.is_finished()).count() >= self.max_running {
+            return None;
+        }
+        let id = format!("job-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let job = Arc::new(MiningJob {
+            id: id.clone(),
+            request,
+            difficulty,
+            start,
+            end,
+            threads,
+            started: Instant::now(),
+            hashes: AtomicU64::new(0),
+            stop_signal: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            workers_left: AtomicUsize::new(threads),
+            outcome: Mutex::new(Outcome { state: JobState::Running, found: None, finished_at: None }),
+        });
+
+        prune_finished(&mut jobs);
+        jobs.insert(id, Arc::clone(&job));
+        drop(jobs);
+        for worker in 0..threads as u64 {
+            let job = Arc::clone(&job);
+            let rom = rom.clone();
+            let task = METRICS.rayon_task();
+            rayon::spawn(move || {
+                spin_range(&job, &rom, worker);
+                drop(task);
+            });
+        }
+        Some(job)
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<MiningJob>> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    /// Number of jobs still running
+    pub fn running(&self) -> usize {
+        self.jobs.lock().unwrap().values().filter(|job| !job.is_finished()).count()
+    }
+}
+
+fn prune_finished(jobs: &mut HashMap<String, Arc<MiningJob>>) {
+    let mut finished: Vec<(Instant, String)> = jobs
+        .values()
+        .filter_map(|job| job.outcome.lock().unwrap().finished_at.map(|at| (at, job.id.clone())))
+        .collect();
+    if finished.len() < MAX_FINISHED_JOBS {
+        return;
+    }
+    finished.sort();
+    for (_, id) in finished.drain(..=finished.len() - MAX_FINISHED_JOBS) {
+        jobs.remove(&id);
+    }
+}
+
+/// Most threads one job may use: the pool minus one admission slot's share of it, so a job
+/// never takes every thread batches and searches hash on
+fn job_thread_cap(pool_threads: usize, max_concurrent_batches: usize) -> usize {
+    (pool_threads - pool_threads / max_concurrent_batches.max(1)).max(1)
+}
+
+/// Worker `index` of a job: like `hashengine::spin`, it strides through the nonces by the
+/// job's thread count until the stop signal is set, but also stops at the end of the range
+fn spin_range(job: &MiningJob, rom: &RomReplicas, index: u64) {
+    let request = &job.request;
+    let mut preimage = Preimage::new(
+        &request.address,
+        &request.challenge_id,
+        &request.difficulty,
+        &request.no_pre_mine,
+        &request.latest_submission,
+        &request.no_pre_mine_hour,
+    );
+    let mut hasher = Hasher::default();
+    let rom = rom.local();
+    let step = job.threads as u64;
+    let mut nonce = job.start.checked_add(index).filter(|&n| n < job.end);
+    let mut hashes_since_report = 0;
+
+    while let Some(nonce_value) = nonce {
+        if job.stop_signal.load(Ordering::Relaxed) {
+            break;
+        }
+        preimage.set_nonce(nonce_value);
+        let hash = hasher.hash(preimage.as_bytes(), rom);
+        hashes_since_report += 1;
+
+        if job.difficulty.accepts(&hash) {
+            job.found(nonce_value, hash);
+            break;
+        }
+        if hashes_since_report == REPORT_EVERY {
+            job.hashes.fetch_add(hashes_since_report, Ordering::Relaxed);
+            METRICS.hashes.inc_by(hashes_since_report);
+            hashes_since_report = 0;
+        }
+        nonce = nonce_value.checked_add(step).filter(|&n| n < job.end);
+    }
+
+    job.hashes.fetch_add(hashes_since_report, Ordering::Relaxed);
+    METRICS.hashes.inc_by(hashes_since_report);
+    job.worker_done();
+}
+
+fn job_not_found(id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(ErrorResponse { error: format!("No job {:?}", id) })
+}
+
+/// POST /jobs - Start mining in the background; poll GET /jobs/{id} for the result
+pub async fn create_job_handler(req: web::Json<JobRequest>) -> HttpResponse {
+    let req = req.into_inner();
+    let rom = match lookup_rom(Some(&req.no_pre_mine)) {
+        Ok(rom) => rom,
+        Err(resp) => return resp,
+    };
+    let difficulty = match Difficulty::parse(&req.difficulty) {
+        Ok(d) => d,
+        Err(e) => return HttpResponse::BadRequest().json(ErrorResponse { error: e.to_string() }),
+    };
+    let range = match parse_nonce_range(&req.start_nonce, req.nonce_count) {
+        Ok(range) => range,
+        Err(error) => return HttpResponse::BadRequest().json(ErrorResponse { error }),
+    };
+
+    // Never more workers than the job share of the pool or nonces
+    let max_threads = job_thread_cap(rayon::current_num_threads(), ADMISSION.status().max_running);
+    let threads = match req.threads {
+        Some(0) => return HttpResponse::BadRequest().json(ErrorResponse { error: "threads must be at least 1".to_string() }),
+        Some(n) => n.min(max_threads),
+        None => max_threads,
+    };
+    let threads = threads.min(usize::try_from(req.nonce_count).unwrap_or(usize::MAX));
+
+    debug!("Starting job for {} on {} (ROM {})", req.address, req.challenge_id, key_prefix(&req.no_pre_mine));
+    let Some(job) = JOBS.start(req, difficulty, range, threads, rom) else {
+        return HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, ADMISSION.retry_after_secs().to_string()))
+            .json(ErrorResponse { error: format!("{} jobs are already running; retry later or cancel one", JOBS.max_running) });
+    };
+    info!("Job {} started: {} nonces on {} thread(s)", job.id, job.request.nonce_count, threads);
+    HttpResponse::Accepted().json(job.status())
+}
+
+/// GET /jobs/{id} - State, hashes done, hash rate and the solution once found
+pub async fn job_status_handler(id: web::Path<String>) -> HttpResponse {
+    match JOBS.get(&id) {
+        Some(job) => HttpResponse::Ok().json(job.status()),
+        None => job_not_found(&id),
+    }
+}
+
+/// DELETE /jobs/{id} - Cancel a running job. Its workers stop within a few hashes;
+/// the job reports `cancelled` once they have. Finished jobs are left as they are.
+pub async fn cancel_job_handler(id: web::Path<String>) -> HttpResponse {
+    let Some(job) = JOBS.get(&id) else {
+        return job_not_found(&id);
+    };
+    if !job.is_finished() {
+        job.cancel();
+        // Workers check the stop signal between hashes, so this is brief
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !job.is_finished() && Instant::now() < deadline {
+            actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+        }
+        info!("Job {} cancelled", job.id);
+    }
+    HttpResponse::Ok().json(job.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::{Rom, RomGenerationType};
+    use crate::ROMS;
+    use actix_web::{test, App};
+
+    const NO_PRE_MINE: &str = "7a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d";
+
+    fn job_body(difficulty: &str, nonce_count: u64, threads: usize) -> serde_json::Value {
+        serde_json::json!({
+            "address": "addr_test1qq",
+            "challenge_id": "**D07C20",
+            "difficulty": difficulty,
+            "no_pre_mine": NO_PRE_MINE,
+            "latest_submission": "2025-11-01T00:00:00.000Z",
+            "no_pre_mine_hour": "123456",
+            "start_nonce": "0000000000000000",
+            "nonce_count": nonce_count,
+            "threads": threads,
+        })
+    }
+
+    #[actix_web::test]
+    async fn jobs_run_in_the_background_until_found_exhausted_or_cancelled() {
+        let _globals = crate::tests::GLOBALS.lock().await;
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let rom = Rom::new(NO_PRE_MINE.as_bytes(), gen_type, 256 * 1024).unwrap();
+        ROMS.insert(NO_PRE_MINE.to_string(), gen_type, Arc::new(rom), false);
+
+        let app = test::init_service(
+            App::new()
+                .route("/jobs", web::post().to(create_job_handler))
+                .route("/jobs/{id}", web::get().to(job_status_handler))
+                .route("/jobs/{id}", web::delete().to(cancel_job_handler)),
+        )
+        .await;
+
+        let wait_done = |id: String| {
+            let app = &app;
+            async move {
+                loop {
+                    let req = test::TestRequest::get().uri(&format!("/jobs/{}", id)).to_request();
+                    let status: serde_json::Value = test::call_and_read_body_json(app, req).await;
+                    if status["state"] != "running" {
+                        return status;
+                    }
+                    actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        };
+
+        // Easy difficulty: found, with a nonce in the range
+        let req = test::TestRequest::post().uri("/jobs").set_json(job_body("0FFFFFFF", 100_000, 2)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::ACCEPTED);
+        let started: serde_json::Value = test::read_body_json(resp).await;
+        let found = wait_done(started["job_id"].as_str().unwrap().to_string()).await;
+        assert_eq!(found["state"], "found");
+        let nonce = u64::from_str_radix(found["nonce"].as_str().unwrap(), 16).unwrap();
+        assert!(nonce < 100_000 && found["hashes"].as_u64().unwrap() > 0);
+
+        // Impossible difficulty over a small range: exhausted after every nonce
+        let req = test::TestRequest::post().uri("/jobs").set_json(job_body("00000000", 50, 3)).to_request();
+        let started: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let exhausted = wait_done(started["job_id"].as_str().unwrap().to_string()).await;
+        assert_eq!((exhausted["state"].as_str(), exhausted["hashes"].as_u64()), (Some("exhausted"), Some(50)));
+
+        // A long job is cancelled by DELETE; while it runs, another job is turned away
+        let req = test::TestRequest::post().uri("/jobs").set_json(job_body("00000000", u32::MAX as u64, 1)).to_request();
+        let started: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let id = started["job_id"].as_str().unwrap();
+        let req = test::TestRequest::post().uri("/jobs").set_json(job_body("00000000", 10, 1)).to_request();
+        let busy = test::call_service(&app, req).await;
+        assert_eq!(busy.status(), actix_web::http::StatusCode::TOO_MANY_REQUESTS);
+        assert!(busy.headers().contains_key(header::RETRY_AFTER));
+        let req = test::TestRequest::delete().uri(&format!("/jobs/{}", id)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::OK);
+        let cancelled = wait_done(id.to_string()).await;
+        assert_eq!(cancelled["state"], "cancelled");
+        assert!(cancelled["hashes"].as_u64().unwrap() < u32::MAX as u64);
+
+        let req = test::TestRequest::get().uri("/jobs/job-0").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let req = test::TestRequest::post().uri("/jobs").set_json(job_body("0FFFFFFF", 10, 0)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn jobs_leave_an_admission_slot_of_the_pool_free() {
+        assert_eq!(job_thread_cap(8, 2), 4);
+        assert_eq!(job_thread_cap(8, 3), 6);
+        assert_eq!(job_thread_cap(16, 64), 16);
+        assert_eq!(job_thread_cap(4, 1), 1);
+        assert_eq!(job_thread_cap(1, 2), 1);
+    }
+}
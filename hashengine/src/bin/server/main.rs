@@ -0,0 +1,1863 @@
+use actix_web::{web, App, HttpResponse, HttpServer};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use rayon::prelude::*;
+use log::{info, error, warn, debug};
+
+// Performance: Use mimalloc as global allocator for better performance
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+// Import HashEngine modules (shared with the NAPI build, so not every item is used here)
+#[allow(dead_code)]
+mod batch_codec {
+    include!("../../batch_codec.rs");
+}
+#[allow(dead_code)]
+mod error {
+    include!("../../error.rs");
+}
+#[allow(dead_code)]
+mod hashengine {
+    include!("../../hashengine.rs");
+}
+#[allow(dead_code)]
+mod numa {
+    include!("../../numa.rs");
+}
+#[allow(dead_code)]
+mod rom {
+    include!("../../rom.rs");
+}
+#[allow(dead_code)]
+mod rom_cache {
+    include!("../../rom_cache.rs");
+}
+#[allow(dead_code)]
+mod rom_checkpoint {
+    include!("../../rom_checkpoint.rs");
+}
+#[allow(dead_code)]
+mod rom_shm {
+    include!("../../rom_shm.rs");
+}
+#[allow(dead_code)]
+mod rom_storage {
+    include!("../../rom_storage.rs");
+}
+#[allow(dead_code)]
+mod stratum {
+    include!("../../stratum.rs");
+}
+
+use batch_codec::BatchRequest;
+use error::HashEngineError;
+use hashengine::{Difficulty, Hasher, Preimage};
+use numa::{NumaTopology, RomReplicas};
+use rom::{RomGenerationType, Rom, RomPhase, RomProgressFn};
+use rom_cache::RomCache;
+use rom_storage::{RomBacking, RomBackingKind};
+
+mod admission;
+mod auth;
+mod jobs;
+mod metrics;
+mod pool;
+mod rom_integrity;
+mod rom_prepare;
+mod rom_registry;
+mod rom_state;
+mod shutdown;
+mod tls;
+mod ws_session;
+use admission::Admission;
+use auth::Auth;
+use jobs::MiningJobs;
+use metrics::Metrics;
+use pool::Pool;
+use rom_integrity::RomIntegrity;
+use rom_prepare::PendingRoms;
+use rom_registry::{RemovedRom, RomRegistry};
+use shutdown::Drain;
+use tls::{Tls, TlsFiles};
+
+// Loaded ROMs keyed by no_pre_mine. ROM_MEMORY_BUDGET_MB (default 4096) bounds the total
+// ROM size; least recently used ROMs are evicted beyond it. Size it as rom_size (1 GiB in
+// production) x ROMs to keep loaded x NUMA replicas: the default holds four ROMs, enough
+// for two overlapping challenges plus one prepared with /rom/prepare
+static ROMS: once_cell::sync::Lazy<RomRegistry> = once_cell::sync::Lazy::new(|| {
+    let budget_mb = std::env::var("ROM_MEMORY_BUDGET_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(4096);
+    RomRegistry::new(budget_mb * 1024 * 1024)
+});
+
+// In-flight ROM builds started by /init or /rom/prepare, with their progress for /rom/status
+static PENDING_ROMS: once_cell::sync::Lazy<PendingRoms> = once_cell::sync::Lazy::new(PendingRoms::default);
+
+// Results of the ROM digest checks; ROMs failing one are regenerated
+static ROM_INTEGRITY: once_cell::sync::Lazy<RomIntegrity> = once_cell::sync::Lazy::new(RomIntegrity::default);
+
+// Interval between background ROM digest checks, ROM_VERIFY_INTERVAL_MINS (default 30, 0 = off)
+static ROM_VERIFY_INTERVAL: once_cell::sync::Lazy<Option<std::time::Duration>> = once_cell::sync::Lazy::new(|| {
+    let mins = std::env::var("ROM_VERIFY_INTERVAL_MINS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(30);
+    (mins > 0).then(|| std::time::Duration::from_secs(mins * 60))
+});
+
+// Interval between checks of the TLS files for renewed certificates, TLS_RELOAD_SECS (default 60, 0 = off)
+static TLS_RELOAD_INTERVAL: once_cell::sync::Lazy<Option<std::time::Duration>> = once_cell::sync::Lazy::new(|| {
+    let secs = std::env::var("TLS_RELOAD_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(60);
+    (secs > 0).then(|| std::time::Duration::from_secs(secs))
+});
+
+// Low-priority pool for /rom/prepare builds, ROM_PREPARE_THREADS threads (default 1/4 of the CPUs)
+static PREPARE_POOL: once_cell::sync::Lazy<rayon::ThreadPool> = once_cell::sync::Lazy::new(|| {
+    let threads = std::env::var("ROM_PREPARE_THREADS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or_else(|| (num_cpus::get() / 4).max(1));
+    rom_prepare::low_priority_pool(threads)
+});
+
+// ROM storage backing, from ROM_BACKING=heap|hugepages (default heap)
+static ROM_BACKING: once_cell::sync::Lazy<RomBacking> = once_cell::sync::Lazy::new(|| {
+    match std::env::var("ROM_BACKING") {
+        Ok(v) => v.parse().unwrap_or_else(|e| {
+            warn!("{}, using heap", e);
+            RomBacking::Heap
+        }),
+        Err(_) => RomBacking::Heap,
+    }
+});
+
+// Share ROMs with other processes on this machine through POSIX shared memory,
+// from ROM_SHARED_MEMORY=1 (default off, Linux only)
+static ROM_SHARED_MEMORY: once_cell::sync::Lazy<bool> = once_cell::sync::Lazy::new(rom_shm::enabled_by_env);
+
+// Shared ROM segments nobody uses any more are removed once this old, ROM_SHM_MAX_AGE_HOURS
+// (default 24); checked at startup and hourly
+static ROM_SHM_MAX_AGE: once_cell::sync::Lazy<std::time::Duration> = once_cell::sync::Lazy::new(|| {
+    let hours = std::env::var("ROM_SHM_MAX_AGE_HOURS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(24);
+    std::time::Duration::from_secs(hours * 3600)
+});
+
+// Bearer tokens from AUTH_TOKENS / AUTH_TOKENS_FILE; None (the default) allows everything
+static AUTH: once_cell::sync::Lazy<Option<Auth>> = once_cell::sync::Lazy::new(Auth::from_env);
+
+// Counters and histograms for GET /metrics
+static METRICS: once_cell::sync::Lazy<Metrics> = once_cell::sync::Lazy::new(Metrics::default);
+
+// Background mining jobs from POST /jobs, running on the hashing (rayon) pool. At most
+// MAX_CONCURRENT_JOBS (default 1) run at once; more get 429
+static JOBS: once_cell::sync::Lazy<MiningJobs> = once_cell::sync::Lazy::new(|| {
+    let max_running = std::env::var("MAX_CONCURRENT_JOBS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1);
+    MiningJobs::new(max_running)
+});
+
+// Pool mode: stratum-like TCP service for remote miners on POOL_PORT (default off).
+// POOL_PASSWORD, when set, is required by mining.authorize
+static POOL_PORT: once_cell::sync::Lazy<Option<u16>> = once_cell::sync::Lazy::new(|| {
+    std::env::var("POOL_PORT").ok().and_then(|v| v.parse::<u16>().ok())
+});
+static POOL: once_cell::sync::Lazy<Pool> = once_cell::sync::Lazy::new(|| {
+    Pool::new(std::env::var("POOL_PASSWORD").ok().filter(|p| !p.is_empty()))
+});
+
+// Set once SIGTERM/SIGINT arrives; new work is refused while in-flight work finishes
+static DRAIN: once_cell::sync::Lazy<Drain> = once_cell::sync::Lazy::new(Drain::default);
+
+// Longest a shutdown waits for in-flight work, SHUTDOWN_TIMEOUT_SECS (default 30)
+static SHUTDOWN_TIMEOUT: once_cell::sync::Lazy<std::time::Duration> = once_cell::sync::Lazy::new(|| {
+    let secs = std::env::var("SHUTDOWN_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(30);
+    std::time::Duration::from_secs(secs)
+});
+
+// Where loaded ROMs are saved on shutdown and restored from at startup, ROM_STATE_DIR (default off)
+static ROM_STATE_DIR: once_cell::sync::Lazy<Option<std::path::PathBuf>> = once_cell::sync::Lazy::new(|| {
+    std::env::var("ROM_STATE_DIR").ok().filter(|d| !d.is_empty()).map(std::path::PathBuf::from)
+});
+
+// Largest request body accepted by the JSON and binary endpoints, MAX_BODY_MB (default 16)
+static MAX_BODY_BYTES: once_cell::sync::Lazy<usize> = once_cell::sync::Lazy::new(|| {
+    let mb = std::env::var("MAX_BODY_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(16);
+    mb.max(1) * 1024 * 1024
+});
+
+// Most preimages accepted in one batch request, MAX_BATCH_SIZE (default 65536)
+static MAX_BATCH_SIZE: once_cell::sync::Lazy<usize> = once_cell::sync::Lazy::new(|| {
+    std::env::var("MAX_BATCH_SIZE")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(65536)
+});
+
+// Most nonces one /search request may cover, MAX_SEARCH_NONCES (default 1000000). A search
+// holds an admission slot until it finishes and cannot be cancelled, unlike /jobs
+static MAX_SEARCH_NONCES: once_cell::sync::Lazy<u64> = once_cell::sync::Lazy::new(|| {
+    std::env::var("MAX_SEARCH_NONCES")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(1_000_000)
+});
+
+// Batch and search requests hashing at once, MAX_CONCURRENT_BATCHES (default 2), with up to
+// MAX_QUEUED_BATCHES (default 64) more waiting; beyond that they get 429 with a Retry-After
+// of RETRY_AFTER_SECS (default 1)
+static ADMISSION: once_cell::sync::Lazy<Admission> = once_cell::sync::Lazy::new(|| {
+    let env = |name: &str, default: usize| std::env::var(name).ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(default);
+    Admission::new(env("MAX_CONCURRENT_BATCHES", 2), env("MAX_QUEUED_BATCHES", 64), env("RETRY_AFTER_SECS", 1) as u64)
+});
+
+// NUMA nodes from /sys/devices/system/node (a single node when unavailable)
+static NUMA_TOPOLOGY: once_cell::sync::Lazy<NumaTopology> = once_cell::sync::Lazy::new(NumaTopology::detect);
+
+// One ROM copy per NUMA node with hashing threads pinned to their node, from
+// ROM_NUMA_REPLICAS=1 (default off; no effect on single-node hosts)
+static ROM_NUMA_REPLICAS: once_cell::sync::Lazy<bool> = once_cell::sync::Lazy::new(|| {
+    let enabled = std::env::var("ROM_NUMA_REPLICAS")
+        .is_ok_and(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"));
+    enabled && NUMA_TOPOLOGY.is_multi_node()
+});
+
+// Optional on-disk ROM cache so a restart mid-challenge skips regeneration.
+// Enabled by ROM_CACHE_DIR; ROM_CACHE_MAX_ENTRIES (default 2) and
+// ROM_CACHE_MAX_AGE_HOURS (default 48, 0 = no limit) control pruning.
+static ROM_CACHE: once_cell::sync::Lazy<Option<RomCache>> = once_cell::sync::Lazy::new(|| {
+    let dir = std::env::var("ROM_CACHE_DIR").ok().filter(|d| !d.is_empty())?;
+    let max_entries = std::env::var("ROM_CACHE_MAX_ENTRIES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(2);
+    let max_age_hours = std::env::var("ROM_CACHE_MAX_AGE_HOURS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(48);
+    let max_age = (max_age_hours > 0).then(|| std::time::Duration::from_secs(max_age_hours * 3600));
+
+    match RomCache::new(&dir, max_entries, max_age) {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            error!("ROM cache disabled, cannot use {}: {}", dir, e);
+            None
+        }
+    }
+});
+
+// Checkpoint ROM builds so a restart mid-build resumes, from ROM_CHECKPOINT=1 (default off).
+// Only used with the ROM cache, whose directory holds the checkpoints; each checkpoint
+// writes its share of the ROM to disk on the build's critical path.
+static ROM_CHECKPOINT: once_cell::sync::Lazy<bool> = once_cell::sync::Lazy::new(|| {
+    std::env::var("ROM_CHECKPOINT").is_ok_and(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+});
+
+// ROM data generated between build checkpoints, ROM_CHECKPOINT_INTERVAL_MB (default 128)
+static ROM_CHECKPOINT_INTERVAL_MB: once_cell::sync::Lazy<usize> = once_cell::sync::Lazy::new(|| {
+    std::env::var("ROM_CHECKPOINT_INTERVAL_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&mb| mb > 0)
+        .unwrap_or(128)
+});
+
+#[derive(Debug, Deserialize)]
+struct InitRequest {
+    no_pre_mine: String,
+    #[serde(rename = "ashConfig")]
+    ash_config: AshConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)] // nbLoops/nbInstrs are accepted for compatibility; hashing uses fixed AshMaze values
+struct AshConfig {
+    #[serde(rename = "nbLoops")]
+    nb_loops: u32,
+    #[serde(rename = "nbInstrs")]
+    nb_instrs: u32,
+    #[serde(default)]
+    pre_size: u32,
+    rom_size: u32,
+    #[serde(default)]
+    mixing_numbers: u32,
+    /// "TwoStep" (default, the AshMaze spec) or "FullRandom", which ignores
+    /// pre_size and mixing_numbers
+    #[serde(default = "default_generation")]
+    generation: String,
+}
+
+fn default_generation() -> String {
+    "TwoStep".to_string()
+}
+
+impl AshConfig {
+    /// Validated generation type for these parameters
+    fn gen_type(&self) -> Result<RomGenerationType, HashEngineError> {
+        let gen_type = RomGenerationType::from_config(&self.generation, self.pre_size as usize, self.mixing_numbers as usize)?;
+        gen_type.validate(self.rom_size as usize)?;
+        Ok(gen_type)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct InitResponse {
+    status: String,
+    worker_pid: u32,
+    no_pre_mine: String,
+    from_cache: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct RomStatusResponse {
+    status: String,
+    no_pre_mine: String,
+}
+
+/// Progress of one in-flight ROM build
+#[derive(Debug, Serialize)]
+struct RomBuildStatus {
+    no_pre_mine: String,
+    /// "loading" until generation reports progress, then mixing_buffer, offsets, mixing, digest
+    phase: String,
+    done: usize,
+    total: usize,
+    /// Completion of the current phase; mixing is by far the longest
+    percent: f64,
+    elapsed_secs: f64,
+    secs_since_update: f64,
+    stalled: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct RomBuildsResponse {
+    builds: Vec<RomBuildStatus>,
+    default_rom_ready: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct RomCheck {
+    no_pre_mine: String,
+    ok: bool,
+    secs: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct RomVerifyResponse {
+    healthy: bool,
+    roms: Vec<RomCheck>,
+    /// ROMs failing a check whose regenerated copy is not in place yet
+    corrupt: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ActivateRequest {
+    no_pre_mine: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HashRequest {
+    preimage: String,
+    /// ROM to hash against; defaults to the most recently initialized one
+    #[serde(default)]
+    no_pre_mine: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct HashResponse {
+    hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchHashRequest {
+    preimages: Vec<String>,
+    /// ROM to hash against; defaults to the most recently initialized one
+    #[serde(default)]
+    no_pre_mine: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchHashResponse {
+    hashes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    address: String,
+    challenge_id: String,
+    difficulty: String,
+    no_pre_mine: String,
+    latest_submission: String,
+    no_pre_mine_hour: String,
+    /// First nonce to try, as 16 hex chars (same encoding as in the preimage)
+    start_nonce: String,
+    /// Number of consecutive nonces to try from start_nonce
+    nonce_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResponse {
+    found: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    hashes_computed: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerifyRequest {
+    preimage: String,
+    difficulty: String,
+    /// ROM to hash against; defaults to the most recently initialized one
+    #[serde(default)]
+    no_pre_mine: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyResponse {
+    hash: String,
+    accepted: bool,
+    zero_bits: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct LoadedRom {
+    no_pre_mine: String,
+    rom_size: usize,
+    generation: String,
+    backing: String,
+    /// Copies held, one per NUMA node when replicated
+    replicas: usize,
+    default: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct NumaNodeInfo {
+    id: usize,
+    cpus: Vec<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct NumaInfo {
+    nodes: Vec<NumaNodeInfo>,
+    /// Whether ROMs are replicated per node and hashing threads pinned
+    replication: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    status: String,
+    draining: bool,
+    #[serde(rename = "romInitialized")]
+    rom_initialized: bool,
+    #[serde(rename = "nativeAvailable")]
+    native_available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_pre_mine_first8: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_pre_mine_last8: Option<String>,
+    #[serde(rename = "romBacking", skip_serializing_if = "Option::is_none")]
+    rom_backing: Option<String>,
+    roms: Vec<LoadedRom>,
+    #[serde(rename = "romsPreparing")]
+    roms_preparing: Vec<String>,
+    #[serde(rename = "romMemoryBytes")]
+    rom_memory_bytes: usize,
+    #[serde(rename = "romMemoryBudgetBytes")]
+    rom_memory_budget_bytes: usize,
+    #[serde(rename = "romsCorrupt")]
+    roms_corrupt: Vec<String>,
+    #[serde(rename = "romMismatches")]
+    rom_mismatches: u64,
+    #[serde(rename = "romLastVerifiedSecsAgo", skip_serializing_if = "Option::is_none")]
+    rom_last_verified_secs_ago: Option<f64>,
+    numa: NumaInfo,
+    /// Background jobs from POST /jobs still running
+    #[serde(rename = "jobsRunning")]
+    jobs_running: usize,
+    admission: admission::AdmissionStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// 413 for a batch over MAX_BATCH_SIZE preimages
+#[allow(clippy::result_large_err)]
+fn check_batch_size(count: usize) -> Result<(), HttpResponse> {
+    if count > *MAX_BATCH_SIZE {
+        return Err(HttpResponse::PayloadTooLarge().json(ErrorResponse {
+            error: format!("Batch of {} preimages exceeds the limit of {}", count, *MAX_BATCH_SIZE),
+        }));
+    }
+    Ok(())
+}
+
+/// First 16 characters of a no_pre_mine for logs and responses. Counts characters, not
+/// bytes, so a key with multi-byte characters cannot split one and panic.
+fn key_prefix(key: &str) -> String {
+    format!("{}...", key.chars().take(16).collect::<String>())
+}
+
+/// Find the ROM a request targets (the default ROM when `no_pre_mine` is None),
+/// or the error response to return. Hash with `local()` to read the calling thread's
+/// NUMA replica.
+// The error is returned as is by the handlers, so boxing it would only add a step
+#[allow(clippy::result_large_err)]
+fn lookup_rom(no_pre_mine: Option<&str>) -> Result<RomReplicas, HttpResponse> {
+    ROMS.get_replicas(no_pre_mine).ok_or_else(|| match no_pre_mine {
+        Some(key) => {
+            error!("ROM not loaded for no_pre_mine {}", key_prefix(key));
+            HttpResponse::NotFound().json(ErrorResponse {
+                error: "ROM not loaded for this no_pre_mine. Call /init first.".to_string(),
+            })
+        }
+        None => {
+            error!("ROM not initialized");
+            HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                error: "ROM not initialized. Call /init first.".to_string(),
+            })
+        }
+    })
+}
+
+/// POST /init - Initialize ROM with challenge parameters
+async fn init_handler(req: web::Json<InitRequest>) -> HttpResponse {
+    info!("POST /init request received");
+    info!("no_pre_mine: {}", key_prefix(&req.no_pre_mine));
+
+    let no_pre_mine_bytes = req.no_pre_mine.as_bytes();
+
+    let gen_type = match req.ash_config.gen_type() {
+        Ok(gen_type) => gen_type,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::BadRequest().json(ErrorResponse { error: e.to_string() });
+        }
+    };
+
+    let guard = loop {
+        // A ROM being built elsewhere (/rom/prepare or a concurrent /init) is swapped in
+        // once ready instead of rebuilt
+        if let Some(build) = PENDING_ROMS.get(&req.no_pre_mine) {
+            info!("Waiting for in-flight ROM build to finish...");
+            let _ = web::block(move || build.wait()).await;
+        }
+
+        // Re-initializing a ROM that is already loaded (or prepared) only makes it the default
+        if ROMS.contains(&req.no_pre_mine, gen_type, req.ash_config.rom_size as usize) {
+            activate_rom(&req.no_pre_mine);
+            info!("✓ ROM already loaded, now the default");
+            return HttpResponse::Ok().json(InitResponse {
+                status: "initialized".to_string(),
+                worker_pid: std::process::id(),
+                no_pre_mine: key_prefix(&req.no_pre_mine),
+                from_cache: false,
+            });
+        }
+
+        // Registered so the build shows up in /rom/status
+        if let Some(guard) = PENDING_ROMS.start(&req.no_pre_mine) {
+            break guard;
+        }
+    };
+
+    info!("Starting ROM initialization (this may take 5-10 seconds)...");
+    let start = std::time::Instant::now();
+
+    let (rom_arc, from_cache) = match load_or_generate_rom(
+        no_pre_mine_bytes,
+        gen_type,
+        req.ash_config.rom_size as usize,
+        &|p| guard.build().report(p),
+    ) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("ROM initialization failed: {}", e);
+            return HttpResponse::BadRequest().json(ErrorResponse { error: e.to_string() });
+        }
+    };
+
+    let backing = rom_arc.backing().as_str();
+    let rom = replicate_for_numa(rom_arc);
+    let elapsed = start.elapsed().as_secs_f64();
+
+    info!(
+        "✓ ROM initialized in {:.1}s{} [{}{}]",
+        elapsed,
+        if from_cache { " (from cache)" } else { "" },
+        backing,
+        if rom.copies() > 1 { format!(", {} NUMA replicas", rom.copies()) } else { String::new() }
+    );
+
+    // Register the ROM (replacing one with the same no_pre_mine) and make it the default
+    log_evictions(ROMS.insert(req.no_pre_mine.clone(), gen_type, rom, true));
+    drop(guard);
+
+    HttpResponse::Ok().json(InitResponse {
+        status: "initialized".to_string(),
+        worker_pid: std::process::id(),
+        no_pre_mine: key_prefix(&req.no_pre_mine),
+        from_cache,
+    })
+}
+
+/// Log ROMs dropped from the registry and unlink the shared-memory segments this process
+/// published for them, which would otherwise stay in /dev/shm until reboot
+fn log_evictions(removed: Vec<RemovedRom>) {
+    for removed in removed {
+        if removed.evicted {
+            warn!("Evicted ROM for no_pre_mine {} (memory budget)", key_prefix(&removed.no_pre_mine));
+        }
+        let rom = removed.rom.primary();
+        if rom.backing() == RomBackingKind::SharedMemory {
+            rom_shm::release(&rom_shm::segment_name(removed.no_pre_mine.as_bytes(), removed.gen_type, rom.size()));
+        }
+    }
+}
+
+/// Make a loaded ROM the default. Returns false if it is not loaded.
+fn activate_rom(no_pre_mine: &str) -> bool {
+    match ROMS.set_default(no_pre_mine) {
+        Some(evicted) => {
+            log_evictions(evicted);
+            true
+        }
+        None => false,
+    }
+}
+
+/// POST /rom/prepare - Build a ROM in the background without replacing the active one
+/// Same body as /init. The ROM becomes the default on a later /init or /rom/activate.
+async fn rom_prepare_handler(req: web::Json<InitRequest>) -> HttpResponse {
+    let req = req.into_inner();
+    let short_key = key_prefix(&req.no_pre_mine);
+    let gen_type = match req.ash_config.gen_type() {
+        Ok(gen_type) => gen_type,
+        Err(e) => return HttpResponse::BadRequest().json(ErrorResponse { error: e.to_string() }),
+    };
+    let size = req.ash_config.rom_size as usize;
+
+    if ROMS.contains(&req.no_pre_mine, gen_type, size) {
+        return HttpResponse::Ok().json(RomStatusResponse {
+            status: "ready".to_string(),
+            no_pre_mine: short_key,
+        });
+    }
+
+    let Some(guard) = PENDING_ROMS.start(&req.no_pre_mine) else {
+        return HttpResponse::Accepted().json(RomStatusResponse {
+            status: "preparing".to_string(),
+            no_pre_mine: short_key,
+        });
+    };
+
+    info!("Preparing ROM for no_pre_mine {} in the background", short_key);
+    let log_key = short_key.clone();
+    std::thread::spawn(move || {
+        let start = std::time::Instant::now();
+        let built = PREPARE_POOL.install(|| {
+            load_or_generate_rom(req.no_pre_mine.as_bytes(), gen_type, size, &|p| guard.build().report(p))
+        });
+        let (rom, from_cache) = match built {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Preparing ROM for {} failed: {}", log_key, e);
+                return;
+            }
+        };
+        log_evictions(ROMS.insert(req.no_pre_mine, gen_type, replicate_for_numa(rom), false));
+        info!(
+            "✓ ROM for {} prepared in {:.1}s{}",
+            log_key,
+            start.elapsed().as_secs_f64(),
+            if from_cache { " (from cache)" } else { "" }
+        );
+        drop(guard);
+    });
+
+    HttpResponse::Accepted().json(RomStatusResponse {
+        status: "preparing".to_string(),
+        no_pre_mine: short_key,
+    })
+}
+
+/// POST /rom/activate - Make a prepared (or otherwise loaded) ROM the default,
+/// waiting for its background preparation to finish if needed
+async fn rom_activate_handler(req: web::Json<ActivateRequest>) -> HttpResponse {
+    let short_key = key_prefix(&req.no_pre_mine);
+
+    if let Some(build) = PENDING_ROMS.get(&req.no_pre_mine) {
+        let _ = web::block(move || build.wait()).await;
+    }
+
+    if activate_rom(&req.no_pre_mine) {
+        info!("✓ ROM for {} activated", short_key);
+        HttpResponse::Ok().json(RomStatusResponse {
+            status: "activated".to_string(),
+            no_pre_mine: short_key,
+        })
+    } else {
+        HttpResponse::NotFound().json(ErrorResponse {
+            error: "ROM not loaded for this no_pre_mine. Call /rom/prepare or /init first.".to_string(),
+        })
+    }
+}
+
+/// Check every loaded ROM against its digest and start regenerating any that fail.
+/// Takes about a second per GiB of loaded ROM.
+fn verify_loaded_roms() -> Vec<RomCheck> {
+    let mut checks = Vec::new();
+    for (key, gen_type, rom) in ROMS.snapshot() {
+        let short_key = key_prefix(&key);
+        let start = std::time::Instant::now();
+        // Every NUMA replica is checked; one bad copy corrupts the hashes of its node
+        let ok = rom.verify();
+        let secs = start.elapsed().as_secs_f64();
+
+        if ok {
+            debug!("ROM {} verified in {:.2}s", short_key, secs);
+        } else {
+            error!("ROM {} failed its digest check (memory corruption?), regenerating", short_key);
+            ROM_INTEGRITY.mark_corrupt(&key);
+            regenerate_rom(key, gen_type, Arc::clone(rom.primary()));
+        }
+        checks.push(RomCheck { no_pre_mine: short_key, ok, secs });
+    }
+    ROM_INTEGRITY.record_check();
+    checks
+}
+
+/// Rebuild a corrupt ROM in the background and swap it in for `corrupt`. The corrupt
+/// ROM keeps serving until then (with /health reporting unhealthy).
+fn regenerate_rom(key: String, gen_type: RomGenerationType, corrupt: Arc<Rom>) {
+    // Already being rebuilt (or prepared); that build will not replace this ROM, but
+    // the next check picks it up again
+    let Some(guard) = PENDING_ROMS.start(&key) else {
+        return;
+    };
+
+    std::thread::spawn(move || {
+        let short_key = key_prefix(&key);
+        let start = std::time::Instant::now();
+        // Unlink the corrupt segment so this build publishes a fresh one; processes still
+        // attached to the old one regenerate when their own check fails
+        if corrupt.backing() == RomBackingKind::SharedMemory {
+            rom_shm::remove(&rom_shm::segment_name(key.as_bytes(), gen_type, corrupt.size()));
+        }
+        // The parameters already produced this ROM once, so they are valid
+        let rebuilt = load_or_generate_rom(key.as_bytes(), gen_type, corrupt.size(), &|p| guard.build().report(p));
+        let Ok((rom, from_cache)) = rebuilt else {
+            error!("Regenerating ROM {} failed", short_key);
+            return;
+        };
+        if ROMS.replace(&key, &corrupt, replicate_for_numa(rom)) {
+            info!(
+                "✓ ROM {} regenerated in {:.1}s{}",
+                short_key,
+                start.elapsed().as_secs_f64(),
+                if from_cache { " (from cache)" } else { "" }
+            );
+        } else {
+            info!("ROM {} was replaced or evicted while regenerating", short_key);
+        }
+        ROM_INTEGRITY.mark_repaired(&key);
+        drop(guard);
+    });
+}
+
+/// GET /rom/verify - Check every loaded ROM against its digest now
+async fn rom_verify_handler() -> HttpResponse {
+    match web::block(verify_loaded_roms).await {
+        Ok(roms) => HttpResponse::Ok().json(RomVerifyResponse {
+            healthy: roms.iter().all(|c| c.ok) && ROM_INTEGRITY.is_healthy(),
+            roms,
+            corrupt: ROM_INTEGRITY.corrupt(),
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(ErrorResponse {
+            error: format!("ROM verification failed: {}", e),
+        }),
+    }
+}
+
+// A build with no progress report for this long is flagged as stalled in /rom/status.
+// Mixing reports every 4096 chunks, so a healthy build updates many times per second.
+const ROM_BUILD_STALL_SECS: f64 = 30.0;
+
+/// GET /rom/status - Progress of in-flight ROM builds (from /init and /rom/prepare)
+async fn rom_status_handler() -> HttpResponse {
+    let builds = PENDING_ROMS
+        .list()
+        .into_iter()
+        .map(|(key, build)| {
+            let (progress, since_update) = build.progress();
+            let (phase, done, total) = match progress {
+                Some(p) => {
+                    let phase = match p.phase {
+                        RomPhase::MixingBuffer => "mixing_buffer",
+                        RomPhase::Offsets => "offsets",
+                        RomPhase::Mixing => "mixing",
+                        RomPhase::Digest => "digest",
+                    };
+                    (phase, p.done, p.total)
+                }
+                None => ("loading", 0, 0),
+            };
+            RomBuildStatus {
+                no_pre_mine: key_prefix(&key),
+                phase: phase.to_string(),
+                done,
+                total,
+                percent: if total == 0 { 0.0 } else { done as f64 * 100.0 / total as f64 },
+                elapsed_secs: build.elapsed().as_secs_f64(),
+                secs_since_update: since_update.as_secs_f64(),
+                stalled: since_update.as_secs_f64() > ROM_BUILD_STALL_SECS,
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(RomBuildsResponse {
+        builds,
+        default_rom_ready: ROMS.get(None).is_some(),
+    })
+}
+
+/// Load the ROM from the on-disk cache if enabled and present, otherwise generate it,
+/// reporting generation progress to `progress`.
+/// With the cache and ROM_CHECKPOINT enabled, generation checkpoints to the cache directory
+/// so a build interrupted by a restart resumes. Freshly generated ROMs are written to the
+/// cache on a background thread.
+/// Returns the ROM and whether it came from the cache.
+fn load_or_generate_rom(
+    key: &[u8],
+    gen_type: RomGenerationType,
+    size: usize,
+    progress: RomProgressFn,
+) -> Result<(Arc<Rom>, bool), HashEngineError> {
+    let start = std::time::Instant::now();
+    let result = load_or_generate_rom_untimed(key, gen_type, size, progress);
+    if let Ok((_, reused)) = &result {
+        METRICS.rom_init(start.elapsed(), *reused);
+    }
+    result
+}
+
+fn load_or_generate_rom_untimed(
+    key: &[u8],
+    gen_type: RomGenerationType,
+    size: usize,
+    progress: RomProgressFn,
+) -> Result<(Arc<Rom>, bool), HashEngineError> {
+    gen_type.validate(size)?;
+    if *ROM_SHARED_MEMORY {
+        match rom_shm::open_or_publish(key, gen_type, size, rom_shm::DEFAULT_WAIT, Some(progress)) {
+            Ok((rom, attached)) => {
+                if attached {
+                    info!("Attached to shared ROM {}", rom_shm::segment_name(key, gen_type, size));
+                }
+                return Ok((Arc::new(rom), attached));
+            }
+            Err(e) => warn!("Shared-memory ROM unavailable, using a private copy: {}", e),
+        }
+    }
+    let Some(cache) = ROM_CACHE.as_ref() else {
+        return Ok((Arc::new(Rom::with_progress(key, gen_type, size, *ROM_BACKING, progress)?), false));
+    };
+
+    match cache.load(key, gen_type, size, *ROM_BACKING) {
+        Ok(Some(rom)) => return Ok((Arc::new(rom), true)),
+        Ok(None) => {}
+        Err(e) => warn!("ROM cache read failed, regenerating: {}", e),
+    }
+
+    let rom = if *ROM_CHECKPOINT {
+        let checkpoint = cache.checkpoint_path(key, gen_type, size);
+        let interval_chunks = *ROM_CHECKPOINT_INTERVAL_MB * 1024 * 1024 / rom::DATASET_ACCESS_SIZE;
+        match rom_checkpoint::generate_resumable(key, gen_type, size, *ROM_BACKING, &checkpoint, interval_chunks, Some(progress)) {
+            Ok(rom) => Arc::new(rom),
+            Err(e) => {
+                warn!("ROM checkpointing failed, generating without it: {}", e);
+                rom_checkpoint::remove(&checkpoint);
+                Arc::new(Rom::with_progress(key, gen_type, size, *ROM_BACKING, progress)?)
+            }
+        }
+    } else {
+        Arc::new(Rom::with_progress(key, gen_type, size, *ROM_BACKING, progress)?)
+    };
+
+    let to_store = Arc::clone(&rom);
+    let key = key.to_vec();
+    std::thread::spawn(move || {
+        match cache.store(&key, gen_type, &to_store) {
+            Ok(path) => info!("ROM cached at {}", path.display()),
+            Err(e) => warn!("Failed to write ROM cache: {}", e),
+        }
+        match cache.prune() {
+            Ok(0) => {}
+            Ok(n) => info!("Pruned {} old ROM cache entr{}", n, if n == 1 { "y" } else { "ies" }),
+            Err(e) => warn!("Failed to prune ROM cache: {}", e),
+        }
+    });
+
+    Ok((rom, false))
+}
+
+/// Copy a freshly loaded ROM to every NUMA node when ROM_NUMA_REPLICAS is on
+fn replicate_for_numa(rom: Arc<Rom>) -> RomReplicas {
+    if !*ROM_NUMA_REPLICAS {
+        return rom.into();
+    }
+    let start = std::time::Instant::now();
+    let replicas = RomReplicas::replicate(rom, &NUMA_TOPOLOGY, *ROM_BACKING);
+    info!("ROM replicated to {} NUMA nodes in {:.1}s", replicas.copies(), start.elapsed().as_secs_f64());
+    replicas
+}
+
+/// POST /hash - Hash single preimage
+async fn hash_handler(req: web::Json<HashRequest>) -> HttpResponse {
+    let rom = match lookup_rom(req.no_pre_mine.as_deref()) {
+        Ok(rom) => rom,
+        Err(resp) => return resp,
+    };
+
+    let salt = req.preimage.as_bytes();
+    let hash_bytes = Hasher::default().hash(salt, rom.local());
+    let hash_hex = hex::encode(hash_bytes);
+    METRICS.hashes.inc_by(1);
+
+    HttpResponse::Ok().json(HashResponse {
+        hash: hash_hex,
+    })
+}
+
+/// POST /hash-batch - Hash multiple preimages in parallel
+async fn hash_batch_handler(req: web::Json<BatchHashRequest>) -> HttpResponse {
+    let batch_start = std::time::Instant::now();
+
+    let rom = match lookup_rom(req.no_pre_mine.as_deref()) {
+        Ok(rom) => rom,
+        Err(resp) => return resp,
+    };
+
+    if req.preimages.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            error: "preimages array is required".to_string(),
+        });
+    }
+
+    let preimage_count = req.preimages.len();
+    if let Err(resp) = check_batch_size(preimage_count) {
+        return resp;
+    }
+    let _admitted = match ADMISSION.admit().await {
+        Ok(admitted) => admitted,
+        Err(resp) => return resp,
+    };
+
+    // Parallel hash processing using rayon with pre-allocated result vector
+    // Each preimage is hashed on a separate thread
+    let task = METRICS.rayon_task();
+    let hashes: Vec<String> = req.preimages
+        .par_iter()
+        .map_init(|| (Hasher::default(), rom.local()), |(hasher, rom), preimage| {
+            let salt = preimage.as_bytes();
+            let hash_bytes = hasher.hash(salt, rom);
+            hex::encode(hash_bytes)
+        })
+        .collect();
+    drop(task);
+    METRICS.batch(preimage_count);
+
+    let total_duration = batch_start.elapsed();
+    let throughput = (preimage_count as f64 / total_duration.as_secs_f64()) as u64;
+
+    // Log performance metrics at debug level (only visible with RUST_LOG=debug)
+    if preimage_count >= 100 {
+        debug!(
+            "Batch processed: {} hashes in {:?} ({} H/s)",
+            preimage_count, total_duration, throughput
+        );
+    }
+
+    HttpResponse::Ok().json(BatchHashResponse { hashes })
+}
+
+/// POST /hash-batch-bin - Batch hashing with the binary framing from `batch_codec`:
+/// length-prefixed preimages in, raw 64-byte digests out, no JSON or hex on either side
+async fn hash_batch_bin_handler(body: web::Bytes) -> HttpResponse {
+    let batch_start = std::time::Instant::now();
+
+    let req = match BatchRequest::decode(&body) {
+        Ok(req) => req,
+        Err(e) => return HttpResponse::BadRequest().json(ErrorResponse { error: e.to_string() }),
+    };
+    let rom = match lookup_rom(req.no_pre_mine) {
+        Ok(rom) => rom,
+        Err(resp) => return resp,
+    };
+    if req.preimages.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            error: "preimages are required".to_string(),
+        });
+    }
+    if let Err(resp) = check_batch_size(req.preimages.len()) {
+        return resp;
+    }
+    let _admitted = match ADMISSION.admit().await {
+        Ok(admitted) => admitted,
+        Err(resp) => return resp,
+    };
+
+    let task = METRICS.rayon_task();
+    let digests: Vec<[u8; 64]> = req
+        .preimages
+        .par_iter()
+        .map_init(|| (Hasher::default(), rom.local()), |(hasher, rom), preimage| hasher.hash(preimage, rom))
+        .collect();
+    drop(task);
+    METRICS.batch(digests.len());
+
+    if digests.len() >= 100 {
+        let total_duration = batch_start.elapsed();
+        debug!(
+            "Binary batch processed: {} hashes in {:?} ({} H/s)",
+            digests.len(),
+            total_duration,
+            (digests.len() as f64 / total_duration.as_secs_f64()) as u64
+        );
+    }
+
+    HttpResponse::Ok()
+        .content_type(batch_codec::CONTENT_TYPE)
+        .body(batch_codec::encode_response(&digests))
+}
+
+/// POST /hash-batch-shared - Zero-copy batch hashing with SharedArrayBuffer
+/// Note: This is a compatibility endpoint - actual shared memory not used in Rust
+async fn hash_batch_shared_handler(req: web::Json<serde_json::Value>) -> HttpResponse {
+    // Extract preimages from request
+    let preimages = match req.get("preimages") {
+        Some(serde_json::Value::Array(arr)) => {
+            arr.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect::<Vec<String>>()
+        }
+        _ => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                error: "preimages array is required".to_string(),
+            });
+        }
+    };
+
+    let rom = match lookup_rom(req.get("no_pre_mine").and_then(|v| v.as_str())) {
+        Ok(rom) => rom,
+        Err(resp) => return resp,
+    };
+
+    if preimages.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            error: "preimages array is required".to_string(),
+        });
+    }
+
+    let preimage_count = preimages.len();
+    if let Err(resp) = check_batch_size(preimage_count) {
+        return resp;
+    }
+    let _admitted = match ADMISSION.admit().await {
+        Ok(admitted) => admitted,
+        Err(resp) => return resp,
+    };
+
+    // Parallel hash processing with pre-allocation
+    let batch_start = std::time::Instant::now();
+    let task = METRICS.rayon_task();
+    let hashes: Vec<String> = preimages
+        .par_iter()
+        .map_init(|| (Hasher::default(), rom.local()), |(hasher, rom), preimage| {
+            let salt = preimage.as_bytes();
+            let hash_bytes = hasher.hash(salt, rom);
+            hex::encode(hash_bytes)
+        })
+        .collect();
+    drop(task);
+    METRICS.batch(preimage_count);
+
+    let total_duration = batch_start.elapsed();
+    let throughput = (preimage_count as f64 / total_duration.as_secs_f64()) as u64;
+
+    // Log performance metrics at debug level (only visible with RUST_LOG=debug)
+    if preimage_count >= 100 {
+        debug!(
+            "Batch shared processed: {} hashes in {:?} ({} H/s)",
+            preimage_count, total_duration, throughput
+        );
+    }
+
+    // Return standard response (SharedArrayBuffer handled on Node.js side)
+    HttpResponse::Ok().json(BatchHashResponse { hashes })
+}
+
+/// Parse a `start_nonce` (16 hex chars) and `nonce_count` into a half-open nonce range
+fn parse_nonce_range(start_nonce: &str, nonce_count: u64) -> Result<(u64, u64), String> {
+    let start = match u64::from_str_radix(start_nonce, 16) {
+        Ok(n) if start_nonce.len() == 16 => n,
+        _ => return Err(format!("Invalid start_nonce: {:?} - must be exactly 16 hex characters", start_nonce)),
+    };
+    match start.checked_add(nonce_count) {
+        Some(end) if nonce_count > 0 => Ok((start, end)),
+        _ => Err("nonce_count must be non-zero and the range must not exceed 2^64".to_string()),
+    }
+}
+
+/// Hashes counted by one rayon split, added to `total` once when the split is dropped, so
+/// parallel hashing loops do not contend on the shared counter for every hash
+struct SplitHashes<'a> {
+    total: &'a AtomicU64,
+    count: u64,
+}
+
+impl<'a> SplitHashes<'a> {
+    fn new(total: &'a AtomicU64) -> Self {
+        Self { total, count: 0 }
+    }
+}
+
+impl Drop for SplitHashes<'_> {
+    fn drop(&mut self) {
+        self.total.fetch_add(self.count, Ordering::Relaxed);
+    }
+}
+
+/// POST /search - Build and hash preimages for a nonce range server-side
+/// Returns only the first winning nonce found (or that the range was exhausted),
+/// instead of shipping every preimage and hash over HTTP
+async fn search_handler(req: web::Json<SearchRequest>) -> HttpResponse {
+    // The preimage embeds no_pre_mine, so it also selects the ROM
+    let rom = match lookup_rom(Some(&req.no_pre_mine)) {
+        Ok(rom) => rom,
+        Err(resp) => return resp,
+    };
+
+    let difficulty = match Difficulty::parse(&req.difficulty) {
+        Ok(d) => d,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ErrorResponse { error: e.to_string() });
+        }
+    };
+
+    let (start_nonce, end_nonce) = match parse_nonce_range(&req.start_nonce, req.nonce_count) {
+        Ok(range) => range,
+        Err(error) => return HttpResponse::BadRequest().json(ErrorResponse { error }),
+    };
+    if req.nonce_count > *MAX_SEARCH_NONCES {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            error: format!(
+                "nonce_count {} exceeds the /search limit of {}; use /jobs for longer searches",
+                req.nonce_count, *MAX_SEARCH_NONCES
+            ),
+        });
+    }
+
+    let _admitted = match ADMISSION.admit().await {
+        Ok(admitted) => admitted,
+        Err(resp) => return resp,
+    };
+    let search_start = std::time::Instant::now();
+    let req = req.into_inner();
+
+    // The search may run for a long time: keep it off the actix worker threads
+    let result = web::block(move || {
+        let _task = METRICS.rayon_task();
+        let hashes_computed = AtomicU64::new(0);
+
+        let new_preimage = || Preimage::new(
+            &req.address,
+            &req.challenge_id,
+            &req.difficulty,
+            &req.no_pre_mine,
+            &req.latest_submission,
+            &req.no_pre_mine_hour,
+        );
+
+        let found = (start_nonce..end_nonce)
+            .into_par_iter()
+            .map_init(
+                || (Hasher::default(), new_preimage(), rom.local(), SplitHashes::new(&hashes_computed)),
+                |(hasher, preimage, rom, hashes), nonce| {
+                    preimage.set_nonce(nonce);
+                    hashes.count += 1;
+                    (nonce, hasher.hash(preimage.as_bytes(), rom))
+                },
+            )
+            .find_any(|(_, hash_bytes)| difficulty.accepts(hash_bytes));
+
+        (found, hashes_computed.into_inner())
+    })
+    .await;
+
+    let (found, hashes_computed) = match result {
+        Ok(r) => {
+            METRICS.hashes.inc_by(r.1);
+            r
+        }
+        Err(e) => {
+            error!("Search task failed: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "Search task failed".to_string(),
+            });
+        }
+    };
+
+    let total_duration = search_start.elapsed();
+    debug!(
+        "Search processed: {} hashes in {:?} ({} H/s), found={}",
+        hashes_computed,
+        total_duration,
+        (hashes_computed as f64 / total_duration.as_secs_f64()) as u64,
+        found.is_some()
+    );
+
+    HttpResponse::Ok().json(match found {
+        Some((nonce, hash_bytes)) => SearchResponse {
+            found: true,
+            nonce: Some(format!("{:016x}", nonce)),
+            hash: Some(hex::encode(hash_bytes)),
+            hashes_computed,
+        },
+        None => SearchResponse {
+            found: false,
+            nonce: None,
+            hash: None,
+            hashes_computed,
+        },
+    })
+}
+
+/// POST /verify - Recompute a preimage's hash and check it against the difficulty
+/// Uses the same dual (zero bits + mask) rule as /search, so a solution is only
+/// reported as accepted if the submission server will accept it too
+async fn verify_handler(req: web::Json<VerifyRequest>) -> HttpResponse {
+    let difficulty = match Difficulty::parse(&req.difficulty) {
+        Ok(d) => d,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ErrorResponse { error: e.to_string() });
+        }
+    };
+
+    let rom = match lookup_rom(req.no_pre_mine.as_deref()) {
+        Ok(rom) => rom,
+        Err(resp) => return resp,
+    };
+
+    let hash_bytes = Hasher::default().hash(req.preimage.as_bytes(), rom.local());
+    METRICS.hashes.inc_by(1);
+
+    HttpResponse::Ok().json(VerifyResponse {
+        hash: hex::encode(hash_bytes),
+        accepted: difficulty.accepts(&hash_bytes),
+        zero_bits: difficulty.zero_bits(),
+    })
+}
+
+/// GET /health - Health check endpoint
+async fn health_handler() -> HttpResponse {
+    let default_key = ROMS.default_key();
+    let roms: Vec<LoadedRom> = ROMS
+        .list()
+        .into_iter()
+        .map(|info| LoadedRom {
+            rom_size: info.size,
+            generation: format!("{:?}", info.gen_type),
+            backing: info.backing.to_string(),
+            replicas: info.copies,
+            default: info.is_default,
+            no_pre_mine: info.no_pre_mine,
+        })
+        .collect();
+    let rom_backing = roms.iter().find(|r| r.default).map(|r| r.backing.clone());
+
+    // A corrupt ROM makes every hash wrong, so report unhealthy until it is regenerated
+    let healthy = ROM_INTEGRITY.is_healthy();
+    // A draining server is still up, but should get no new work
+    let draining = DRAIN.is_draining();
+    let mut response = if healthy && !draining {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+
+    response.json(HealthResponse {
+        status: if draining { "draining" } else if healthy { "ok" } else { "unhealthy" }.to_string(),
+        draining,
+        rom_initialized: default_key.is_some(),
+        native_available: true,
+        config: None,
+        no_pre_mine_first8: default_key.as_ref().map(|k| k.chars().take(8).collect()),
+        no_pre_mine_last8: default_key.as_ref().map(|k| k.chars().skip(k.chars().count().saturating_sub(8)).collect()),
+        rom_backing,
+        rom_memory_bytes: ROMS.total_bytes(),
+        rom_memory_budget_bytes: ROMS.budget_bytes(),
+        roms,
+        roms_preparing: PENDING_ROMS.keys(),
+        roms_corrupt: ROM_INTEGRITY.corrupt(),
+        rom_mismatches: ROM_INTEGRITY.mismatches(),
+        rom_last_verified_secs_ago: ROM_INTEGRITY.secs_since_check(),
+        numa: NumaInfo {
+            nodes: NUMA_TOPOLOGY
+                .nodes
+                .iter()
+                .map(|node| NumaNodeInfo { id: node.id, cpus: node.cpus.clone() })
+                .collect(),
+            replication: *ROM_NUMA_REPLICAS,
+        },
+        jobs_running: JOBS.running(),
+        admission: ADMISSION.status(),
+    })
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    // Initialize logger
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let host = std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+    let port = std::env::var("PORT").unwrap_or_else(|_| "9001".to_string());
+
+    // HTTPS when TLS_CERT/TLS_KEY are set; refuse to start on a broken configuration
+    // rather than falling back to plain HTTP
+    let tls = TlsFiles::from_env()
+        .and_then(|files| files.map(Tls::load).transpose())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("TLS: {}", e)))?
+        .map(Arc::new);
+
+    // Get system CPU information
+    let physical_cores = num_cpus::get_physical();
+
+    // HTTP workers: configurable via WORKERS env var (default 16 for good concurrency)
+    let workers = std::env::var("WORKERS")
+            .unwrap_or_else(|_| num_cpus::get().to_string())
+            .parse::<usize>()
+            .unwrap_or(num_cpus::get());
+
+    // Rayon threads: use physical cores (avoids hyperthreading overhead for CPU-intensive work)
+    // rayon::ThreadPoolBuilder::new()
+    //     .num_threads(physical_cores)
+    //     .build_global()
+    //     .ok();
+
+    // With NUMA replicas, pin each hashing thread to a node so it reads the local copy
+    if *ROM_NUMA_REPLICAS {
+        let built = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_cpus::get())
+            .start_handler(|i| {
+                let node = NUMA_TOPOLOGY.node_for_thread(i);
+                if let Err(e) = NUMA_TOPOLOGY.pin_current_thread(node) {
+                    warn!("Could not pin hashing thread {} to NUMA node {}: {}", i, NUMA_TOPOLOGY.nodes[node].id, e);
+                }
+            })
+            .build_global();
+        if let Err(e) = built {
+            warn!("Could not configure NUMA-pinned hashing threads: {}", e);
+        }
+    }
+
+    info!("═══════════════════════════════════════════════════════════");
+    info!("HashEngine Native Hash Service (Rust)");
+    info!("═══════════════════════════════════════════════════════════");
+    info!("Listening: {}://{}:{}", if tls.is_some() { "https" } else { "http" }, host, port);
+    info!("HTTP Workers: {} (actix-web server threads)", workers);
+    info!("Rayon Threads: {} (physical cores for hashing)", physical_cores);
+    info!("ROM Backing: {:?} (requested)", *ROM_BACKING);
+    info!("ROM Memory Budget: {} MiB", ROMS.budget_bytes() / (1024 * 1024));
+    match ROM_CACHE.as_ref() {
+        Some(cache) => info!("ROM Cache: {}", cache.dir().display()),
+        None => info!("ROM Cache: disabled (set ROM_CACHE_DIR to enable)"),
+    }
+    info!(
+        "NUMA: {} node(s){}",
+        NUMA_TOPOLOGY.nodes.len(),
+        if *ROM_NUMA_REPLICAS { ", ROM replicated per node" } else { "" }
+    );
+    if *ROM_SHARED_MEMORY {
+        info!("ROM Shared Memory: enabled (ROMs are shared with other processes via /dev/shm)");
+    }
+    let limits = ADMISSION.status();
+    info!(
+        "Limits: {} MiB bodies, {} preimages per batch, {} batches at once, {} queued",
+        *MAX_BODY_BYTES / (1024 * 1024),
+        *MAX_BATCH_SIZE,
+        limits.max_running,
+        limits.max_queued
+    );
+    match AUTH.as_ref() {
+        Some(auth) => {
+            let (hash, admin) = auth.counts();
+            info!("Auth: bearer tokens required ({} hash, {} admin)", hash, admin);
+        }
+        None if host == "127.0.0.1" || host == "localhost" || host == "::1" => {
+            info!("Auth: disabled (set AUTH_TOKENS or AUTH_TOKENS_FILE to enable)")
+        }
+        None => warn!("Auth: disabled while listening on {}; anyone who can reach it may replace the ROM", host),
+    }
+    match &tls {
+        Some(tls) => {
+            let files = tls.files();
+            info!("TLS: {} (key {})", files.cert.display(), files.key.display());
+            match &files.client_ca {
+                Some(ca) => info!("TLS: client certificates required, issued by {}", ca.display()),
+                None => info!("TLS: client certificates not required (set TLS_CLIENT_CA to require them)"),
+            }
+        }
+        None => info!("TLS: disabled (set TLS_CERT and TLS_KEY to enable)"),
+    }
+    match ROM_STATE_DIR.as_ref() {
+        Some(dir) => info!("ROM State: saved to {} on shutdown (drain timeout {}s)", dir.display(), SHUTDOWN_TIMEOUT.as_secs()),
+        None => info!("ROM State: not saved (set ROM_STATE_DIR to keep loaded ROMs across restarts)"),
+    }
+    match *POOL_PORT {
+        Some(pool_port) => info!("Pool: miners connect to {}:{}", host, pool_port),
+        None => info!("Pool: disabled (set POOL_PORT to enable)"),
+    }
+    match *ROM_VERIFY_INTERVAL {
+        Some(interval) => info!("ROM Verify: every {} min", interval.as_secs() / 60),
+        None => info!("ROM Verify: on demand only (ROM_VERIFY_INTERVAL_MINS=0)"),
+    }
+    info!("═══════════════════════════════════════════════════════════");
+
+    if let Some(interval) = *ROM_VERIFY_INTERVAL {
+        std::thread::Builder::new()
+            .name("rom-verify".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                // A regeneration started now would race the ROM state save
+                if !DRAIN.is_draining() {
+                    verify_loaded_roms();
+                }
+            })?;
+    }
+
+    if *ROM_SHARED_MEMORY {
+        std::thread::Builder::new()
+            .name("rom-shm-sweep".to_string())
+            .spawn(|| loop {
+                match rom_shm::sweep(*ROM_SHM_MAX_AGE) {
+                    Ok(0) => {}
+                    Ok(n) => info!("Removed {} stale shared ROM segment(s)", n),
+                    Err(e) => warn!("Cannot sweep stale shared ROM segments: {}", e),
+                }
+                std::thread::sleep(std::time::Duration::from_secs(3600));
+            })?;
+    }
+
+    if let Some(dir) = ROM_STATE_DIR.clone() {
+        if let Err(e) = rom_state::restore(dir) {
+            warn!("Cannot restore saved ROMs: {}", e);
+        }
+    }
+
+    if let (Some(tls), Some(interval)) = (&tls, *TLS_RELOAD_INTERVAL) {
+        tls.watch(interval)?;
+    }
+
+    if let Some(pool_port) = *POOL_PORT {
+        let listener = tokio::net::TcpListener::bind((host.as_str(), pool_port)).await?;
+        actix_web::rt::spawn(pool::serve(&POOL, listener));
+    }
+
+    let server = HttpServer::new(|| {
+        App::new()
+            // Logger middleware removed - only log important events via RUST_LOG
+            .app_data(web::JsonConfig::default().limit(*MAX_BODY_BYTES))
+            .wrap(actix_web::middleware::from_fn(shutdown::refuse_while_draining))
+            .wrap(actix_web::middleware::from_fn(auth::require_token))
+            .wrap(actix_web::middleware::from_fn(metrics::track_requests))
+            .route("/init", web::post().to(init_handler))
+            .route("/hash", web::post().to(hash_handler))
+            .route("/hash-batch", web::post().to(hash_batch_handler))
+            .route("/hash-batch-shared", web::post().to(hash_batch_shared_handler))
+            .service(
+                web::resource("/hash-batch-bin")
+                    .app_data(web::PayloadConfig::new(*MAX_BODY_BYTES))
+                    .route(web::post().to(hash_batch_bin_handler)),
+            )
+            .route("/search", web::post().to(search_handler))
+            .route("/verify", web::post().to(verify_handler))
+            .route("/rom/prepare", web::post().to(rom_prepare_handler))
+            .route("/rom/activate", web::post().to(rom_activate_handler))
+            .route("/rom/status", web::get().to(rom_status_handler))
+            .route("/rom/verify", web::get().to(rom_verify_handler))
+            .route("/jobs", web::post().to(jobs::create_job_handler))
+            .route("/jobs/{id}", web::get().to(jobs::job_status_handler))
+            .route("/jobs/{id}", web::delete().to(jobs::cancel_job_handler))
+            .route("/ws", web::get().to(ws_session::ws_handler))
+            .route("/pool/job", web::post().to(pool::pool_job_handler))
+            .route("/pool/stats", web::get().to(pool::pool_stats_handler))
+            .route("/health", web::get().to(health_handler))
+            .route("/metrics", web::get().to(metrics::metrics_handler))
+    })
+    .workers(workers)
+    // SIGTERM/SIGINT are handled by `shutdown`, which drains before stopping the server;
+    // by then only responses being written remain
+    .disable_signals()
+    .shutdown_timeout(5);
+    let server = match &tls {
+        Some(tls) => {
+            let config = tls.server_config().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("TLS: {}", e)))?;
+            server.bind_rustls_0_23(format!("{}:{}", host, port), config)?
+        }
+        None => server.bind(format!("{}:{}", host, port))?,
+    };
+    let server = server.run();
+    actix_web::rt::spawn(shutdown::on_signal(server.handle()));
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use hashengine::hash as sh_hash;
+
+    const TEST_NO_PRE_MINE: &str = "e8a195800b0fd6a2ba9ee4c8f9a5b8b2";
+
+    /// Held by every test that loads ROMs or checks builds, jobs or counters in the
+    /// server-wide statics (ROMS, PENDING_ROMS, JOBS, METRICS, ...), so the parallel test
+    /// runner never interleaves two of them
+    pub(crate) static GLOBALS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    fn init_test_rom() {
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let rom = Rom::new(TEST_NO_PRE_MINE.as_bytes(), gen_type, 256 * 1024).unwrap();
+        ROMS.insert(TEST_NO_PRE_MINE.to_string(), gen_type, Arc::new(rom), true);
+    }
+
+    fn search_body(difficulty: &str, start_nonce: &str, nonce_count: u64) -> serde_json::Value {
+        serde_json::json!({
+            "address": "addr_test1qq",
+            "challenge_id": "**D07C10",
+            "difficulty": difficulty,
+            "no_pre_mine": TEST_NO_PRE_MINE,
+            "latest_submission": "2025-11-01T00:00:00.000Z",
+            "no_pre_mine_hour": "123456",
+            "start_nonce": start_nonce,
+            "nonce_count": nonce_count,
+        })
+    }
+
+    #[actix_web::test]
+    async fn search_returns_winning_nonce_or_exhausted_range() {
+        let _globals = GLOBALS.lock().await;
+        init_test_rom();
+        let app = test::init_service(App::new().route("/search", web::post().to(search_handler))).await;
+
+        // 4 leading zero bits: a winner is expected within a few dozen nonces
+        let req = test::TestRequest::post()
+            .uri("/search")
+            .set_json(search_body("0fffffff", "0000000000000000", 4096))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["found"], true);
+
+        let nonce = u64::from_str_radix(resp["nonce"].as_str().unwrap(), 16).unwrap();
+        let preimage = hashengine::build_preimage(
+            nonce,
+            "addr_test1qq",
+            "**D07C10",
+            "0fffffff",
+            TEST_NO_PRE_MINE,
+            "2025-11-01T00:00:00.000Z",
+            "123456",
+        );
+        let rom = ROMS.get(Some(TEST_NO_PRE_MINE)).unwrap();
+        let expected = sh_hash(preimage.as_bytes(), &rom, 8, 256).unwrap();
+        assert_eq!(resp["hash"], hex::encode(expected));
+        assert!(Difficulty::parse("0fffffff").unwrap().accepts(&expected));
+
+        // 32 leading zero bits cannot realistically be met in 8 nonces
+        let req = test::TestRequest::post()
+            .uri("/search")
+            .set_json(search_body("00000000", "00000000000000ff", 8))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["found"], false);
+        assert_eq!(resp["hashes_computed"], 8);
+
+        let req = test::TestRequest::post()
+            .uri("/search")
+            .set_json(search_body("0fffffff", "xyz", 8))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+        // Longer searches belong on /jobs
+        let req = test::TestRequest::post()
+            .uri("/search")
+            .set_json(search_body("0fffffff", "0000000000000000", *MAX_SEARCH_NONCES + 1))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["error"].as_str().unwrap().contains("/jobs"));
+    }
+
+    #[actix_web::test]
+    async fn binary_batch_matches_json_batch() {
+        let _globals = GLOBALS.lock().await;
+        init_test_rom();
+        let app = test::init_service(
+            App::new()
+                .route("/hash-batch", web::post().to(hash_batch_handler))
+                .route("/hash-batch-bin", web::post().to(hash_batch_bin_handler)),
+        )
+        .await;
+        let preimages = ["0000000000000001addr_test1qq", "0000000000000002addr_test1qq", ""];
+
+        let req = test::TestRequest::post()
+            .uri("/hash-batch")
+            .set_json(serde_json::json!({ "preimages": preimages, "no_pre_mine": TEST_NO_PRE_MINE }))
+            .to_request();
+        let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        let frame = batch_codec::encode_request(Some(TEST_NO_PRE_MINE), &preimages).unwrap();
+        let req = test::TestRequest::post()
+            .uri("/hash-batch-bin")
+            .insert_header(("content-type", batch_codec::CONTENT_TYPE))
+            .set_payload(frame)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(resp.headers().get("content-type").unwrap(), batch_codec::CONTENT_TYPE);
+        let digests = batch_codec::decode_response(&test::read_body(resp).await).unwrap();
+        let hex_digests: Vec<String> = digests.iter().map(hex::encode).collect();
+        assert_eq!(serde_json::json!(hex_digests), json["hashes"]);
+
+        // Malformed frames and unknown ROMs are rejected before hashing
+        let req = test::TestRequest::post().uri("/hash-batch-bin").set_payload(&b"HEBQ\x02"[..]).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let frame = batch_codec::encode_request(Some("not-loaded"), &preimages).unwrap();
+        let req = test::TestRequest::post().uri("/hash-batch-bin").set_payload(frame).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn oversized_batches_are_rejected_before_hashing() {
+        let _globals = GLOBALS.lock().await;
+        init_test_rom();
+        let app = test::init_service(
+            App::new()
+                .route("/hash-batch", web::post().to(hash_batch_handler))
+                .route("/hash-batch-bin", web::post().to(hash_batch_bin_handler))
+                .app_data(web::PayloadConfig::new(*MAX_BODY_BYTES)),
+        )
+        .await;
+        let preimages = vec![""; *MAX_BATCH_SIZE + 1];
+
+        let req = test::TestRequest::post()
+            .uri("/hash-batch")
+            .set_json(serde_json::json!({ "preimages": preimages, "no_pre_mine": TEST_NO_PRE_MINE }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        let frame = batch_codec::encode_request(Some(TEST_NO_PRE_MINE), &preimages).unwrap();
+        let req = test::TestRequest::post().uri("/hash-batch-bin").set_payload(frame).to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert!(resp["error"].as_str().unwrap().contains("exceeds the limit"), "{}", resp);
+    }
+
+    #[actix_web::test]
+    async fn rom_status_reports_in_flight_build_progress() {
+        let _globals = GLOBALS.lock().await;
+        let app = test::init_service(App::new().route("/rom/status", web::get().to(rom_status_handler))).await;
+        let key = "status-test-0123456789abcdef";
+
+        let guard = PENDING_ROMS.start(key).unwrap();
+        guard.build().report(rom::RomProgress { phase: RomPhase::Mixing, done: 1024, total: 4096 });
+
+        let req = test::TestRequest::get().uri("/rom/status").to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let build = resp["builds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|b| b["no_pre_mine"] == "status-test-0123...")
+            .expect("build listed");
+        assert_eq!(build["phase"], "mixing");
+        assert_eq!(build["done"], 1024);
+        assert_eq!(build["total"], 4096);
+        assert_eq!(build["percent"], 25.0);
+        assert_eq!(build["stalled"], false);
+
+        drop(guard);
+        let req = test::TestRequest::get().uri("/rom/status").to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert!(resp["builds"].as_array().unwrap().iter().all(|b| b["no_pre_mine"] != "status-test-0123..."));
+    }
+
+    #[actix_web::test]
+    async fn corrupt_rom_is_reported_and_regenerated() {
+        let _globals = GLOBALS.lock().await;
+        let app = test::init_service(App::new().route("/rom/verify", web::get().to(rom_verify_handler))).await;
+        let key = "corrupt-test-0123456789abcdef";
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let good = Rom::new(key.as_bytes(), gen_type, 64 * 1024).unwrap();
+
+        // Same data under a wrong digest looks exactly like a bit flip in the data
+        let mut digest = good.digest;
+        digest.0[0] ^= 1;
+        let corrupt = Arc::new(Rom::from_parts(digest, good.data().to_vec().into()));
+        ROMS.insert(key.to_string(), gen_type, Arc::clone(&corrupt), false);
+
+        let req = test::TestRequest::get().uri("/rom/verify").to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let check = resp["roms"].as_array().unwrap().iter().find(|c| c["no_pre_mine"] == "corrupt-test-012...").unwrap();
+        assert_eq!(check["ok"], false);
+        assert_eq!(resp["healthy"], false);
+
+        // Either still regenerating or already swapped in; both end with a verified ROM
+        if let Some(build) = PENDING_ROMS.get(key) {
+            build.wait();
+        }
+        let rom = ROMS.get(Some(key)).unwrap();
+        assert!(!Arc::ptr_eq(&rom, &corrupt));
+        assert!(rom.verify());
+        assert!(rom.digest == good.digest);
+        assert!(!ROM_INTEGRITY.corrupt().contains(&key.to_string()));
+        assert!(ROM_INTEGRITY.mismatches() >= 1);
+    }
+
+    #[actix_web::test]
+    async fn health_reports_numa_topology() {
+        let _globals = GLOBALS.lock().await;
+        let app = test::init_service(App::new().route("/health", web::get().to(health_handler))).await;
+        let key = "numa-health-0123456789abcdef";
+        let rom = Arc::new(Rom::new(key.as_bytes(), RomGenerationType::FullRandom, 64 * 1024).unwrap());
+        ROMS.insert(key.to_string(), RomGenerationType::FullRandom, rom, false);
+
+        // The status depends on the ROMs earlier tests left loaded; the body is the same
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let nodes = resp["numa"]["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), NUMA_TOPOLOGY.nodes.len());
+        assert!(nodes.iter().all(|n| !n["cpus"].as_array().unwrap().is_empty()));
+        assert_eq!(resp["numa"]["replication"], false);
+        let loaded = resp["roms"].as_array().unwrap().iter().find(|r| r["no_pre_mine"] == key).unwrap();
+        assert_eq!(loaded["replicas"], 1);
+    }
+
+    fn init_body(no_pre_mine: &str, generation: &str, pre_size: u32) -> serde_json::Value {
+        serde_json::json!({
+            "no_pre_mine": no_pre_mine,
+            "ashConfig": {
+                "nbLoops": 8,
+                "nbInstrs": 256,
+                "pre_size": pre_size,
+                "rom_size": 256 * 1024,
+                "mixing_numbers": 4,
+                "generation": generation,
+            },
+        })
+    }
+
+    #[actix_web::test]
+    async fn init_and_hash_with_each_generation_type() {
+        let _globals = GLOBALS.lock().await;
+        let app = test::init_service(
+            App::new()
+                .route("/init", web::post().to(init_handler))
+                .route("/hash", web::post().to(hash_handler)),
+        )
+        .await;
+        let preimage = "0000000000000001addr_test1qq**D07C100fffffff";
+
+        let cases = [
+            ("gen-test-twostep-0123456789", "TwoStep", RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 }),
+            ("gen-test-fullrandom-01234567", "FullRandom", RomGenerationType::FullRandom),
+        ];
+        let mut hashes = Vec::new();
+        for (key, generation, gen_type) in cases {
+            let req = test::TestRequest::post().uri("/init").set_json(init_body(key, generation, 16 * 1024)).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success(), "{} init failed", generation);
+            assert!(ROMS.contains(key, gen_type, 256 * 1024));
+
+            let req = test::TestRequest::post()
+                .uri("/hash")
+                .set_json(serde_json::json!({ "preimage": preimage, "no_pre_mine": key }))
+                .to_request();
+            let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let expected = sh_hash(preimage.as_bytes(), &Rom::new(key.as_bytes(), gen_type, 256 * 1024).unwrap(), 8, 256).unwrap();
+            assert_eq!(resp["hash"], hex::encode(expected));
+            hashes.push(expected);
+        }
+        assert_ne!(hashes[0], hashes[1]);
+
+        // Unknown generation types and parameters that would panic mid-generation are rejected
+        let mut bad_size = init_body("gen-test-invalid-0123456789", "TwoStep", 16 * 1024);
+        bad_size["ashConfig"]["rom_size"] = 1000.into();
+        let mut no_mixing = init_body("gen-test-invalid-0123456789", "TwoStep", 16 * 1024);
+        no_mixing["ashConfig"]["mixing_numbers"] = 0.into();
+        // Past ROM_MAX_SIZE_MB (default 2 GiB)
+        let mut huge_size = init_body("gen-test-invalid-0123456789", "TwoStep", 16 * 1024);
+        huge_size["ashConfig"]["rom_size"] = (u32::MAX - 63).into();
+        // Multi-byte characters straddling the 16-byte log prefix
+        let mut wide_key = bad_size.clone();
+        wide_key["no_pre_mine"] = "ééééééééééééééé€€".into();
+        for body in [
+            init_body("gen-test-invalid-0123456789", "ThreeStep", 16 * 1024),
+            init_body("gen-test-invalid-0123456789", "TwoStep", 3000),
+            bad_size,
+            no_mixing,
+            huge_size,
+            wide_key,
+        ] {
+            let req = test::TestRequest::post().uri("/init").set_json(body).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[actix_web::test]
+    async fn evicted_shared_rom_segment_is_unlinked() {
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let size = 256 * 1024;
+        let registry = RomRegistry::new(size);
+        let keys = [format!("shm-evict-a-{}", std::process::id()), format!("shm-evict-b-{}", std::process::id())];
+        let names = keys.clone().map(|key| rom_shm::segment_name(key.as_bytes(), gen_type, size));
+        for (key, name) in keys.iter().zip(&names) {
+            rom_shm::remove(name);
+            let (rom, attached) = rom_shm::open_or_publish(key.as_bytes(), gen_type, size, rom_shm::DEFAULT_WAIT, None).unwrap();
+            assert!(!attached);
+            // Only room for one: the second evicts the first, which is no longer the default
+            log_evictions(registry.insert(key.clone(), gen_type, Arc::new(rom), true));
+        }
+
+        let gone = rom_shm::attach(&names[0], keys[0].as_bytes(), gen_type, size).err().unwrap();
+        assert_eq!(gone.kind(), std::io::ErrorKind::NotFound);
+        assert!(rom_shm::attach(&names[1], keys[1].as_bytes(), gen_type, size).is_ok());
+        rom_shm::remove(&names[1]);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{ADMISSION, ROMS, METRICS};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::HttpResponse;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Counters, gauges and histograms for GET /metrics, kept in atomics and rendered in the
+// Prometheus text format (version 0.0.4). Metric names start with `hashengine_`, except
+// the standard `process_*` memory gauges.
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Request latency buckets in seconds: single hashes take about a millisecond, large
+// batches and searches seconds or more
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+const BATCH_SIZE_BUCKETS: &[f64] = &[1.0, 10.0, 100.0, 1_000.0, 10_000.0, 100_000.0, 1_000_000.0];
+
+// ROM builds range from milliseconds (cache, shared memory) to minutes (1 GiB generation)
+const ROM_INIT_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc_by(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Histogram {
+    bounds: &'static [f64],
+    // One per bound, not cumulative; rendering adds them up
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    // f64 bits
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        if let Some(i) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self.sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f64::from_bits(bits) + value).to_bits()));
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, bound, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, count);
+        let braces = |labels: &str| if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), f64::from_bits(self.sum.load(Ordering::Relaxed)));
+        let _ = writeln!(out, "{}_count{} {}", name, braces(labels), count);
+    }
+}
+
+pub struct Metrics {
+    /// Hashes computed by every endpoint, job, WebSocket session and pool share check
+    pub hashes: Counter,
+    batches: Counter,
+    batch_size: Histogram,
+    rom_inits_generated: Counter,
+    rom_inits_reused: Counter,
+    rom_init_duration: Histogram,
+    /// Work submitted to the hashing pool and not finished yet
+    rayon_pending: AtomicI64,
+    // (endpoint pattern, method)
+    requests: Mutex<BTreeMap<(String, String), Histogram>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            hashes: Counter::default(),
+            batches: Counter::default(),
+            batch_size: Histogram::new(BATCH_SIZE_BUCKETS),
+            rom_inits_generated: Counter::default(),
+            rom_inits_reused: Counter::default(),
+            rom_init_duration: Histogram::new(ROM_INIT_BUCKETS),
+            rayon_pending: AtomicI64::new(0),
+            requests: Mutex::default(),
+        }
+    }
+}
+
+/// Marks work as queued on or running in the hashing pool until dropped
+pub struct RayonTask<'a>(&'a AtomicI64);
+
+impl Drop for RayonTask<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// A batch of `size` preimages, hashed
+    pub fn batch(&self, size: usize) {
+        self.batches.inc_by(1);
+        self.batch_size.observe(size as f64);
+        self.hashes.inc_by(size as u64);
+    }
+
+    /// A ROM loaded for /init, /rom/prepare or a regeneration; `reused` when it came from
+    /// the cache or shared memory instead of being generated
+    pub fn rom_init(&self, duration: Duration, reused: bool) {
+        if reused { &self.rom_inits_reused } else { &self.rom_inits_generated }.inc_by(1);
+        self.rom_init_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Count work handed to the hashing pool while the returned guard lives
+    pub fn rayon_task(&self) -> RayonTask<'_> {
+        self.rayon_pending.fetch_add(1, Ordering::Relaxed);
+        RayonTask(&self.rayon_pending)
+    }
+
+    fn observe_request(&self, endpoint: &str, method: &str, duration: Duration) {
+        let mut requests = self.requests.lock().unwrap();
+        let key = (endpoint.to_string(), method.to_string());
+        requests.entry(key).or_insert_with(|| Histogram::new(LATENCY_BUCKETS)).observe(duration.as_secs_f64());
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::with_capacity(8 * 1024);
+        header(&mut out, "hashengine_hashes_total", "counter", "Hashes computed by every endpoint, job, WebSocket session and pool share check");
+        let _ = writeln!(out, "hashengine_hashes_total {}", self.hashes.get());
+
+        header(&mut out, "hashengine_batches_total", "counter", "Batch hashing requests served (/hash-batch, /hash-batch-bin, /hash-batch-shared)");
+        let _ = writeln!(out, "hashengine_batches_total {}", self.batches.get());
+        header(&mut out, "hashengine_batch_size", "histogram", "Preimages per batch request");
+        self.batch_size.render(&mut out, "hashengine_batch_size", "");
+
+        header(&mut out, "hashengine_http_request_duration_seconds", "histogram", "HTTP request latency by endpoint");
+        for ((endpoint, method), histogram) in self.requests.lock().unwrap().iter() {
+            let labels = format!("endpoint=\"{}\",method=\"{}\"", escape(endpoint), escape(method));
+            histogram.render(&mut out, "hashengine_http_request_duration_seconds", &labels);
+        }
+
+        header(&mut out, "hashengine_rom_inits_total", "counter", "ROMs loaded, by whether they were generated or reused from the cache or shared memory");
+        let _ = writeln!(out, "hashengine_rom_inits_total{{source=\"generated\"}} {}", self.rom_inits_generated.get());
+        let _ = writeln!(out, "hashengine_rom_inits_total{{source=\"reused\"}} {}", self.rom_inits_reused.get());
+        header(&mut out, "hashengine_rom_init_duration_seconds", "histogram", "Time to load or generate a ROM");
+        self.rom_init_duration.render(&mut out, "hashengine_rom_init_duration_seconds", "");
+
+        header(&mut out, "hashengine_rom_info", "gauge", "Loaded ROMs with their no_pre_mine and digest prefixes (always 1)");
+        for info in ROMS.list() {
+            let Some(rom) = ROMS.get(Some(&info.no_pre_mine)) else { continue };
+            let _ = writeln!(
+                out,
+                "hashengine_rom_info{{no_pre_mine=\"{}\",digest=\"{}\",default=\"{}\"}} 1",
+                escape(&info.no_pre_mine.chars().take(16).collect::<String>()),
+                hex::encode(&rom.digest.0[..8]),
+                info.is_default
+            );
+        }
+        header(&mut out, "hashengine_rom_bytes", "gauge", "Memory held by loaded ROMs, NUMA replicas included");
+        let _ = writeln!(out, "hashengine_rom_bytes {}", ROMS.total_bytes());
+
+        header(&mut out, "hashengine_rayon_threads", "gauge", "Threads in the hashing pool");
+        let _ = writeln!(out, "hashengine_rayon_threads {}", rayon::current_num_threads());
+        header(&mut out, "hashengine_rayon_pending_tasks", "gauge", "Requests and job workers queued on or running in the hashing pool");
+        let _ = writeln!(out, "hashengine_rayon_pending_tasks {}", self.rayon_pending.load(Ordering::Relaxed).max(0));
+
+        let admission = ADMISSION.status();
+        header(&mut out, "hashengine_admission_running", "gauge", "Batch and search requests holding a hashing slot");
+        let _ = writeln!(out, "hashengine_admission_running {}", admission.running);
+        header(&mut out, "hashengine_admission_queued", "gauge", "Batch and search requests waiting for a hashing slot");
+        let _ = writeln!(out, "hashengine_admission_queued {}", admission.queued);
+        header(&mut out, "hashengine_admission_rejected_total", "counter", "Requests turned away with 429 because the admission queue was full");
+        let _ = writeln!(out, "hashengine_admission_rejected_total {}", admission.rejected);
+
+        if let Some((resident, virtual_bytes)) = process_memory() {
+            header(&mut out, "process_resident_memory_bytes", "gauge", "Resident memory size in bytes");
+            let _ = writeln!(out, "process_resident_memory_bytes {}", resident);
+            header(&mut out, "process_virtual_memory_bytes", "gauge", "Virtual memory size in bytes");
+            let _ = writeln!(out, "process_virtual_memory_bytes {}", virtual_bytes);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value (backslash, double quote and newline)
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Resident and virtual memory of this process, from /proc/self/statm
+#[cfg(target_os = "linux")]
+fn process_memory() -> Option<(u64, u64)> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let mut fields = statm.split_whitespace().map(|f| f.parse::<u64>().ok());
+    let (virtual_pages, resident_pages) = (fields.next()??, fields.next()??);
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    Some((resident_pages * page_size, virtual_pages * page_size))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_memory() -> Option<(u64, u64)> {
+    None
+}
+
+/// Middleware recording the latency of every request, labelled by its route pattern
+/// (so `/jobs/{id}` is one series however many jobs there are)
+pub async fn track_requests(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let start = Instant::now();
+    let endpoint = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let response = next.call(req).await;
+    METRICS.observe_request(&endpoint, &method, start.elapsed());
+    response
+}
+
+/// GET /metrics - Prometheus text format
+pub async fn metrics_handler() -> HttpResponse {
+    HttpResponse::Ok().content_type(CONTENT_TYPE).body(METRICS.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{middleware, test, web, App};
+
+    #[actix_web::test]
+    async fn metrics_are_rendered_in_prometheus_text_format() {
+        let _globals = crate::tests::GLOBALS.lock().await;
+        let histogram = Histogram::new(&[1.0, 10.0]);
+        for value in [0.5, 5.0, 7.0, 50.0] {
+            histogram.observe(value);
+        }
+        let mut out = String::new();
+        histogram.render(&mut out, "h", "a=\"b\"");
+        assert_eq!(
+            out,
+            "h_bucket{a=\"b\",le=\"1\"} 1\nh_bucket{a=\"b\",le=\"10\"} 3\nh_bucket{a=\"b\",le=\"+Inf\"} 4\nh_sum{a=\"b\"} 62.5\nh_count{a=\"b\"} 4\n"
+        );
+        assert_eq!(escape("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(track_requests))
+                .route("/jobs/{id}", web::get().to(HttpResponse::NotFound))
+                .route("/metrics", web::get().to(metrics_handler)),
+        )
+        .await;
+        for id in ["a", "b"] {
+            test::call_service(&app, test::TestRequest::get().uri(&format!("/jobs/{}", id)).to_request()).await;
+        }
+        METRICS.batch(250);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(resp.headers().get("content-type").unwrap(), CONTENT_TYPE);
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        let count = body
+            .lines()
+            .find_map(|l| l.strip_prefix("hashengine_http_request_duration_seconds_count{endpoint=\"/jobs/{id}\",method=\"GET\"} "))
+            .unwrap();
+        assert!(count.parse::<u64>().unwrap() >= 2);
+        for name in ["hashengine_hashes_total", "hashengine_batch_size_bucket{le=\"1000\"}", "hashengine_rom_inits_total{source=\"generated\"}", "hashengine_rayon_pending_tasks", "hashengine_admission_queued"] {
+            assert!(body.lines().any(|l| l.starts_with(name)), "{} missing from\n{}", name, body);
+        }
+        #[cfg(target_os = "linux")]
+        assert!(body.contains("\nprocess_resident_memory_bytes "));
+    }
+}
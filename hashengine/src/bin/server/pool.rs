@@ -0,0 +1,692 @@
+use crate::hashengine::{build_preimage, hash_structure_good, Difficulty, Hasher};
+use crate::rom::RomGenerationType;
+use crate::stratum::{self, JobNotify, Notification, Request, Response, RomParams, SubmitResult, SubscribeResult};
+use crate::{auth, key_prefix, lookup_rom, ErrorResponse, METRICS, POOL, ROMS};
+
+use actix_web::{web, HttpResponse};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Pool mode: miners on other machines connect over TCP (see src/stratum.rs for the
+// protocol), each gets its own extranonce (nonce prefix) and an address of the current
+// job, and submits shares at an easier difficulty so their work can be accounted for.
+
+// Longest request line; a submit is well under 200 bytes
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+// Solutions kept for /pool/stats, oldest dropped first
+const MAX_SOLUTIONS: usize = 10_000;
+
+// Default share difficulty, in zero bits below the network difficulty (16x easier)
+const DEFAULT_SHARE_BITS_BELOW: usize = 4;
+
+/// POST /pool/job - Challenge fields and the addresses to mine for
+#[derive(Debug, Deserialize)]
+pub struct PoolJobRequest {
+    challenge_id: String,
+    difficulty: String,
+    no_pre_mine: String,
+    latest_submission: String,
+    no_pre_mine_hour: String,
+    /// Spread over the miners round-robin
+    addresses: Vec<String>,
+    /// Leading zero bits of a share (default: 4 below the difficulty's)
+    #[serde(default)]
+    share_zero_bits: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct PoolJobResponse {
+    job_id: String,
+    share_zero_bits: usize,
+    /// Miners the job was sent to
+    notified: usize,
+}
+
+/// GET /pool/stats
+#[derive(Debug, Serialize)]
+pub struct PoolStats {
+    job_id: Option<String>,
+    challenge_id: Option<String>,
+    share_zero_bits: Option<usize>,
+    /// Connected miners (TCP sessions)
+    miners: usize,
+    workers: Vec<WorkerInfo>,
+    solutions: Vec<PoolSolution>,
+}
+
+#[derive(Debug, Serialize)]
+struct WorkerInfo {
+    worker: String,
+    accepted_shares: u64,
+    rejected_shares: u64,
+    solutions: u64,
+    last_share_secs_ago: Option<f64>,
+}
+
+/// A nonce meeting the network difficulty, to be submitted for its address
+#[derive(Clone, Debug, Serialize)]
+struct PoolSolution {
+    worker: String,
+    job_id: String,
+    address: String,
+    challenge_id: String,
+    nonce: String,
+    hash: String,
+}
+
+/// The job every miner works on, with a different address and extranonce each
+struct PoolJob {
+    id: String,
+    request: PoolJobRequest,
+    difficulty: Difficulty,
+    share_zero_bits: usize,
+    rom: RomParams,
+}
+
+#[derive(Default)]
+struct WorkerStats {
+    accepted_shares: u64,
+    rejected_shares: u64,
+    solutions: u64,
+    last_share: Option<Instant>,
+}
+
+/// A connected miner
+struct Session {
+    extranonce: u32,
+    subscribed: bool,
+    /// Worker names authorized on this connection
+    workers: HashSet<String>,
+    /// Address of the current job, once notified
+    address: Option<String>,
+    outgoing: UnboundedSender<String>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    job: Option<Arc<PoolJob>>,
+    next_job: u64,
+    /// Round-robin position in the current job's addresses
+    next_address: usize,
+    sessions: HashMap<u64, Session>,
+    workers: BTreeMap<String, WorkerStats>,
+    /// (nonce, is_solution) submitted for the current job
+    submitted: HashSet<(u64, bool)>,
+    solutions: Vec<PoolSolution>,
+}
+
+pub struct Pool {
+    password: Option<String>,
+    next_session: AtomicU64,
+    state: Mutex<PoolState>,
+}
+
+/// Why a submit was refused; counted as a rejected share unless the request was malformed
+struct Rejection {
+    code: i32,
+    message: String,
+}
+
+fn reject(code: i32, message: impl Into<String>) -> Rejection {
+    Rejection { code, message: message.into() }
+}
+
+impl Pool {
+    /// `password`, when set, must be given by every mining.authorize
+    pub fn new(password: Option<String>) -> Self {
+        // Start extranonces at a random point so miners reconnecting to a restarted pool
+        // do not redo the nonces they already covered
+        let first = getrandom::u32().unwrap_or(0) as u64;
+        Self { password, next_session: AtomicU64::new(first), state: Mutex::default() }
+    }
+
+    /// Register a connection; lines sent to `outgoing` are written to it
+    fn connect(&self, outgoing: UnboundedSender<String>) -> u64 {
+        let id = self.next_session.fetch_add(1, Ordering::Relaxed);
+        let session = Session {
+            extranonce: id as u32,
+            subscribed: false,
+            workers: HashSet::new(),
+            address: None,
+            outgoing,
+        };
+        self.state.lock().unwrap().sessions.insert(id, session);
+        id
+    }
+
+    fn disconnect(&self, session: u64) {
+        self.state.lock().unwrap().sessions.remove(&session);
+    }
+
+    /// Answer one request line of `session`: the response, possibly followed by a
+    /// mining.notify. May hash, so call it off the async executor.
+    fn handle_line(&self, session: u64, line: &str) -> Vec<String> {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return vec![stratum::to_line(&Response::error(None, stratum::ERR_PARSE, format!("parse error: {}", e)))],
+        };
+        let id = request.id;
+        let mut lines = Vec::with_capacity(2);
+        let response = match request.method.as_str() {
+            stratum::SUBSCRIBE => self.subscribe(session, id),
+            stratum::AUTHORIZE => {
+                let (response, notify) = self.authorize(session, id, &request.params);
+                lines.push(stratum::to_line(&response));
+                lines.extend(notify);
+                return lines;
+            }
+            stratum::SUBMIT => self.submit(session, id, &request.params, false),
+            stratum::SUBMIT_SOLUTION => self.submit(session, id, &request.params, true),
+            other => Response::error(id, stratum::ERR_METHOD_NOT_FOUND, format!("unknown method {:?}", other)),
+        };
+        lines.push(stratum::to_line(&response));
+        lines
+    }
+
+    fn subscribe(&self, session: u64, id: Option<u64>) -> Response {
+        let mut state = self.state.lock().unwrap();
+        let Some(session_state) = state.sessions.get_mut(&session) else {
+            return Response::error(id, stratum::ERR_OTHER, "session closed");
+        };
+        session_state.subscribed = true;
+        Response::ok(id, SubscribeResult {
+            session_id: format!("{:x}", session),
+            extranonce: format!("{:08x}", session_state.extranonce),
+        })
+    }
+
+    fn authorize(&self, session: u64, id: Option<u64>, params: &serde_json::Value) -> (Response, Option<String>) {
+        let worker = match params.get(0).and_then(|w| w.as_str()) {
+            Some(w) if !w.is_empty() && w.len() <= 128 => w,
+            _ => return (Response::error(id, stratum::ERR_INVALID_PARAMS, "expected [worker, password]"), None),
+        };
+        if let Some(expected) = &self.password {
+            let password = params.get(1).and_then(|p| p.as_str()).unwrap_or_default();
+            if !auth::constant_time_eq(password.as_bytes(), expected.as_bytes()) {
+                return (Response::error(id, stratum::ERR_UNAUTHORIZED, "wrong password"), None);
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let Some(session_state) = state.sessions.get_mut(&session) else {
+            return (Response::error(id, stratum::ERR_OTHER, "session closed"), None);
+        };
+        if !session_state.subscribed {
+            return (Response::error(id, stratum::ERR_NOT_SUBSCRIBED, "call mining.subscribe first"), None);
+        }
+        session_state.workers.insert(worker.to_string());
+        state.workers.entry(worker.to_string()).or_default();
+        info!("Pool worker {:?} authorized (extranonce {:08x})", worker, session_state.extranonce);
+
+        // A miner joining mid-job gets it straight away
+        let notify = match (&state.job, &session_state.address) {
+            (Some(job), None) => Some(notify_line(job, session_state, &mut state.next_address)),
+            _ => None,
+        };
+        (Response::ok(id, true), notify)
+    }
+
+    fn submit(&self, session: u64, id: Option<u64>, params: &serde_json::Value, solution: bool) -> Response {
+        let strings: Option<Vec<&str>> = params.as_array().map(|p| p.iter().filter_map(|v| v.as_str()).collect());
+        let (worker, job_id, nonce_hex) = match strings.as_deref() {
+            Some(&[worker, job_id, nonce]) => (worker, job_id, nonce),
+            _ => return Response::error(id, stratum::ERR_INVALID_PARAMS, "expected [worker, job_id, nonce]"),
+        };
+
+        match self.check_submit(session, worker, job_id, nonce_hex, solution) {
+            Ok(result) => Response::ok(id, result),
+            Err(rejection) => {
+                debug!("Pool {} from {:?} rejected: {}", if solution { "solution" } else { "share" }, worker, rejection.message);
+                Response::error(id, rejection.code, rejection.message)
+            }
+        }
+    }
+
+    fn check_submit(&self, session: u64, worker: &str, job_id: &str, nonce_hex: &str, solution: bool) -> Result<SubmitResult, Rejection> {
+        let (job, nonce, address) = {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+            let session_state = state.sessions.get(&session).ok_or_else(|| reject(stratum::ERR_OTHER, "session closed"))?;
+            if !session_state.subscribed {
+                return Err(reject(stratum::ERR_NOT_SUBSCRIBED, "call mining.subscribe first"));
+            }
+            if !session_state.workers.contains(worker) {
+                return Err(reject(stratum::ERR_UNAUTHORIZED, format!("worker {:?} is not authorized", worker)));
+            }
+            let stats = state.workers.entry(worker.to_string()).or_default();
+
+            let current = match (&state.job, &session_state.address) {
+                (Some(job), Some(address)) if job.id == job_id => Some((Arc::clone(job), address.clone())),
+                _ => None,
+            };
+            let Some((job, address)) = current else {
+                stats.rejected_shares += 1;
+                return Err(reject(stratum::ERR_STALE_JOB, format!("job {:?} is not the current job", job_id)));
+            };
+            let nonce = match u64::from_str_radix(nonce_hex, 16) {
+                Ok(n) if nonce_hex.len() == 16 => n,
+                _ => {
+                    stats.rejected_shares += 1;
+                    return Err(reject(stratum::ERR_OTHER, "nonce must be exactly 16 hex characters"));
+                }
+            };
+            if (nonce >> stratum::MINER_NONCE_BITS) as u32 != session_state.extranonce {
+                stats.rejected_shares += 1;
+                return Err(reject(stratum::ERR_OTHER, format!("nonce does not start with extranonce {:08x}", session_state.extranonce)));
+            }
+            if !state.submitted.insert((nonce, solution)) {
+                stats.rejected_shares += 1;
+                return Err(reject(stratum::ERR_DUPLICATE, "duplicate share"));
+            }
+            (job, nonce, address)
+        };
+
+        let Some(rom) = ROMS.get_replicas(Some(&job.request.no_pre_mine)) else {
+            warn!("Pool ROM for no_pre_mine {} is no longer loaded", key_prefix(&job.request.no_pre_mine));
+            return Err(reject(stratum::ERR_OTHER, "ROM not loaded on the pool"));
+        };
+        let request = &job.request;
+        let preimage = build_preimage(
+            nonce,
+            &address,
+            &request.challenge_id,
+            &request.difficulty,
+            &request.no_pre_mine,
+            &request.latest_submission,
+            &request.no_pre_mine_hour,
+        );
+        let hash = Hasher::default().hash(preimage.as_bytes(), rom.local());
+        METRICS.hashes.inc_by(1);
+        let is_share = hash_structure_good(&hash, job.share_zero_bits);
+        let is_solution = job.difficulty.accepts(&hash);
+
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let stats = state.workers.entry(worker.to_string()).or_default();
+        if (solution && !is_solution) || (!solution && !is_share) {
+            stats.rejected_shares += 1;
+            let needed = if solution { "the network difficulty" } else { "the share difficulty" };
+            return Err(reject(stratum::ERR_LOW_DIFFICULTY, format!("hash does not meet {}", needed)));
+        }
+        // An accepted solution is worth at least a share
+        stats.accepted_shares += 1;
+        stats.last_share = Some(Instant::now());
+        // A share meeting the network difficulty is a solution too, whichever way it came in
+        let nonce_hex = format!("{:016x}", nonce);
+        if is_solution && !state.solutions.iter().any(|s| s.job_id == job.id && s.nonce == nonce_hex) {
+            stats.solutions += 1;
+            info!("Pool solution from {:?} for {}: nonce {}", worker, address, nonce_hex);
+            if state.solutions.len() == MAX_SOLUTIONS {
+                state.solutions.remove(0);
+            }
+            state.solutions.push(PoolSolution {
+                worker: worker.to_string(),
+                job_id: job.id.clone(),
+                address,
+                challenge_id: request.challenge_id.clone(),
+                nonce: nonce_hex,
+                hash: hex::encode(hash),
+            });
+        }
+        Ok(SubmitResult { accepted: true, solution: is_solution })
+    }
+
+    /// Make `job` the current job and send it to every authorized miner. Shares for
+    /// earlier jobs are rejected as stale from now on. Returns the job id and how many
+    /// miners were notified.
+    fn set_job(&self, request: PoolJobRequest, difficulty: Difficulty, share_zero_bits: usize, rom: RomParams) -> (String, usize) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.next_job += 1;
+        let job = Arc::new(PoolJob { id: format!("{:x}", state.next_job), request, difficulty, share_zero_bits, rom });
+        state.job = Some(Arc::clone(&job));
+        state.next_address = 0;
+        state.submitted.clear();
+
+        let mut notified = 0;
+        for session in state.sessions.values_mut() {
+            session.address = None;
+            if session.subscribed && !session.workers.is_empty() {
+                let line = notify_line(&job, session, &mut state.next_address);
+                notified += usize::from(session.outgoing.send(line).is_ok());
+            }
+        }
+        (job.id.clone(), notified)
+    }
+
+    fn stats(&self) -> PoolStats {
+        let state = self.state.lock().unwrap();
+        PoolStats {
+            job_id: state.job.as_ref().map(|job| job.id.clone()),
+            challenge_id: state.job.as_ref().map(|job| job.request.challenge_id.clone()),
+            share_zero_bits: state.job.as_ref().map(|job| job.share_zero_bits),
+            miners: state.sessions.len(),
+            workers: state
+                .workers
+                .iter()
+                .map(|(worker, stats)| WorkerInfo {
+                    worker: worker.clone(),
+                    accepted_shares: stats.accepted_shares,
+                    rejected_shares: stats.rejected_shares,
+                    solutions: stats.solutions,
+                    last_share_secs_ago: stats.last_share.map(|t| t.elapsed().as_secs_f64()),
+                })
+                .collect(),
+            solutions: state.solutions.clone(),
+        }
+    }
+}
+
+/// Assign `session` the next address of `job` and build its mining.notify line
+fn notify_line(job: &PoolJob, session: &mut Session, next_address: &mut usize) -> String {
+    let address = job.request.addresses[*next_address % job.request.addresses.len()].clone();
+    *next_address += 1;
+    session.address = Some(address.clone());
+    let request = &job.request;
+    stratum::to_line(&Notification::new(stratum::NOTIFY, JobNotify {
+        job_id: job.id.clone(),
+        address,
+        challenge_id: request.challenge_id.clone(),
+        difficulty: request.difficulty.clone(),
+        share_zero_bits: job.share_zero_bits,
+        no_pre_mine: request.no_pre_mine.clone(),
+        latest_submission: request.latest_submission.clone(),
+        no_pre_mine_hour: request.no_pre_mine_hour.clone(),
+        rom: job.rom.clone(),
+        extranonce: format!("{:08x}", session.extranonce),
+        clean_jobs: true,
+    }))
+}
+
+fn rom_params(gen_type: RomGenerationType, rom_size: usize) -> RomParams {
+    let (generation, pre_size, mixing_numbers) = match gen_type {
+        RomGenerationType::TwoStep { pre_size, mixing_numbers } => ("TwoStep", pre_size, mixing_numbers),
+        RomGenerationType::FullRandom => ("FullRandom", 0, 0),
+    };
+    RomParams { generation: generation.to_string(), pre_size, mixing_numbers, rom_size }
+}
+
+/// Accept miner connections until the listener fails
+pub async fn serve(pool: &'static Pool, listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                actix_web::rt::spawn(handle_connection(pool, stream, peer));
+            }
+            Err(e) => {
+                warn!("Pool accept failed: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn handle_connection(pool: &'static Pool, stream: TcpStream, peer: SocketAddr) {
+    let _ = stream.set_nodelay(true);
+    let (read, mut write) = stream.into_split();
+    let (outgoing, mut lines_out) = unbounded_channel::<String>();
+    let session = pool.connect(outgoing.clone());
+    info!("Pool miner connected from {}", peer);
+
+    // Responses and notifications share one writer, so lines are never interleaved
+    let writer = actix_web::rt::spawn(async move {
+        while let Some(line) = lines_out.recv().await {
+            write.write_all(line.as_bytes()).await?;
+            write.write_all(b"\n").await?;
+        }
+        Ok::<_, std::io::Error>(())
+    });
+
+    let mut reader = BufReader::new(read);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match (&mut reader).take(MAX_LINE_BYTES as u64 + 1).read_line(&mut line).await {
+            Ok(0) => break,
+            Ok(_) if line.len() > MAX_LINE_BYTES => {
+                warn!("Pool miner {} sent a line over {} bytes, disconnecting", peer, MAX_LINE_BYTES);
+                break;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                debug!("Pool miner {} read failed: {}", peer, e);
+                break;
+            }
+        }
+        let request = line.trim().to_string();
+        if request.is_empty() {
+            continue;
+        }
+        // Submits hash, so keep them off the executor
+        let Ok(replies) = actix_web::rt::task::spawn_blocking(move || pool.handle_line(session, &request)).await else {
+            break;
+        };
+        if replies.into_iter().any(|reply| outgoing.send(reply).is_err()) {
+            break;
+        }
+    }
+
+    pool.disconnect(session);
+    drop(outgoing);
+    let _ = writer.await;
+    info!("Pool miner {} disconnected", peer);
+}
+
+/// POST /pool/job - Start a pool job and send it to the connected miners
+pub async fn pool_job_handler(req: web::Json<PoolJobRequest>) -> HttpResponse {
+    let req = req.into_inner();
+    if req.addresses.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "addresses must not be empty".to_string() });
+    }
+    let difficulty = match Difficulty::parse(&req.difficulty) {
+        Ok(d) => d,
+        Err(e) => return HttpResponse::BadRequest().json(ErrorResponse { error: e.to_string() }),
+    };
+    let share_zero_bits = req
+        .share_zero_bits
+        .unwrap_or_else(|| difficulty.zero_bits().saturating_sub(DEFAULT_SHARE_BITS_BELOW));
+    if share_zero_bits > difficulty.zero_bits() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            error: format!("share_zero_bits {} is harder than the difficulty ({} zero bits)", share_zero_bits, difficulty.zero_bits()),
+        });
+    }
+
+    // Miners build the ROM themselves, so the pool only needs to know its parameters
+    if let Err(resp) = lookup_rom(Some(&req.no_pre_mine)) {
+        return resp;
+    }
+    let Some(info) = ROMS.list().into_iter().find(|info| info.no_pre_mine == req.no_pre_mine) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "ROM not loaded for this no_pre_mine. Call /init first.".to_string() });
+    };
+
+    let challenge_id = req.challenge_id.clone();
+    let (job_id, notified) = POOL.set_job(req, difficulty, share_zero_bits, rom_params(info.gen_type, info.size));
+    info!("Pool job {} for challenge {}: sent to {} miner(s), shares at {} zero bits", job_id, challenge_id, notified, share_zero_bits);
+    HttpResponse::Ok().json(PoolJobResponse { job_id, share_zero_bits, notified })
+}
+
+/// GET /pool/stats - Connected miners, per-worker share counts and solutions found
+pub async fn pool_stats_handler() -> HttpResponse {
+    HttpResponse::Ok().json(POOL.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashengine::Preimage;
+    use crate::rom::Rom;
+    use crate::stratum::PoolMessage;
+
+    const NO_PRE_MINE: &str = "5f1c2d3e4a5b6c7d8e9f0a1b2c3d4e5f";
+
+    fn load_rom() -> RomParams {
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let rom = Rom::new(NO_PRE_MINE.as_bytes(), gen_type, 256 * 1024).unwrap();
+        ROMS.insert(NO_PRE_MINE.to_string(), gen_type, Arc::new(rom), false);
+        rom_params(gen_type, 256 * 1024)
+    }
+
+    fn job_request(share_zero_bits: usize) -> PoolJobRequest {
+        PoolJobRequest {
+            challenge_id: "**D07C19".to_string(),
+            difficulty: "0FFFFFFF".to_string(),
+            no_pre_mine: NO_PRE_MINE.to_string(),
+            latest_submission: "2025-11-01T00:00:00.000Z".to_string(),
+            no_pre_mine_hour: "123456".to_string(),
+            addresses: vec!["addr_pool_a".to_string(), "addr_pool_b".to_string()],
+            share_zero_bits: Some(share_zero_bits),
+        }
+    }
+
+    fn request(id: u64, method: &str, params: serde_json::Value) -> String {
+        stratum::to_line(&Request::new(id, method, params))
+    }
+
+    fn response(line: &str) -> Response {
+        match serde_json::from_str(line).unwrap() {
+            PoolMessage::Response(response) => response,
+            PoolMessage::Notification(n) => panic!("expected a response, got {:?}", n),
+        }
+    }
+
+    fn notify(line: &str) -> JobNotify {
+        match serde_json::from_str(line).unwrap() {
+            PoolMessage::Notification(n) if n.method == stratum::NOTIFY => serde_json::from_value(n.params).unwrap(),
+            other => panic!("expected mining.notify, got {:?}", other),
+        }
+    }
+
+    /// Nonces in the miner's range: one share that is not a solution, one solution
+    /// and one hash too weak for a share
+    fn find_nonces(job: &JobNotify) -> (u64, u64, u64) {
+        let rom = ROMS.get(Some(NO_PRE_MINE)).unwrap();
+        let difficulty = Difficulty::parse(&job.difficulty).unwrap();
+        let base = (u32::from_str_radix(&job.extranonce, 16).unwrap() as u64) << stratum::MINER_NONCE_BITS;
+        let mut preimage =
+            Preimage::new(&job.address, &job.challenge_id, &job.difficulty, &job.no_pre_mine, &job.latest_submission, &job.no_pre_mine_hour);
+        let (mut share, mut solution, mut weak) = (None, None, None);
+        let mut hasher = Hasher::default();
+        for nonce in base.. {
+            preimage.set_nonce(nonce);
+            let hash = hasher.hash(preimage.as_bytes(), &rom);
+            if difficulty.accepts(&hash) {
+                solution.get_or_insert(nonce);
+            } else if hash_structure_good(&hash, job.share_zero_bits) {
+                share.get_or_insert(nonce);
+            } else {
+                weak.get_or_insert(nonce);
+            }
+            if let (Some(share), Some(solution), Some(weak)) = (share, solution, weak) {
+                return (share, solution, weak);
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn shares_are_checked_and_counted_per_worker() {
+        let _globals = crate::tests::GLOBALS.blocking_lock();
+        let rom = load_rom();
+        let pool = Pool::new(Some("secret".to_string()));
+        let (tx, mut rx) = unbounded_channel();
+        let session = pool.connect(tx);
+
+        // Authorize needs a subscription and the password
+        let reply = pool.handle_line(session, &request(1, stratum::AUTHORIZE, serde_json::json!(["rig1", "secret"])));
+        assert_eq!(response(&reply[0]).error.unwrap().code, stratum::ERR_NOT_SUBSCRIBED);
+        let reply = pool.handle_line(session, &request(2, stratum::SUBSCRIBE, serde_json::json!([])));
+        let subscribed: SubscribeResult = serde_json::from_value(response(&reply[0]).result).unwrap();
+        let reply = pool.handle_line(session, &request(3, stratum::AUTHORIZE, serde_json::json!(["rig1", "wrong"])));
+        assert_eq!(response(&reply[0]).error.unwrap().code, stratum::ERR_UNAUTHORIZED);
+        let reply = pool.handle_line(session, &request(4, stratum::AUTHORIZE, serde_json::json!(["rig1", "secret"])));
+        assert_eq!(reply.len(), 1, "no job yet, so no notify");
+        assert_eq!(response(&reply[0]).result, serde_json::json!(true));
+
+        let (job_id, notified) = pool.set_job(job_request(1), Difficulty::parse("0FFFFFFF").unwrap(), 1, rom.clone());
+        assert_eq!(notified, 1);
+        let job = notify(&rx.try_recv().unwrap());
+        assert_eq!((job.job_id.as_str(), job.address.as_str()), (job_id.as_str(), "addr_pool_a"));
+        assert_eq!((&job.extranonce, &job.rom), (&subscribed.extranonce, &rom));
+        let (share, solution, weak) = find_nonces(&job);
+
+        let submit = |id, method, worker: &str, job: &str, nonce: u64| {
+            let line = request(id, method, serde_json::json!([worker, job, format!("{:016x}", nonce)]));
+            response(&pool.handle_line(session, &line)[0])
+        };
+        let ok = |r: Response| serde_json::from_value::<SubmitResult>(r.result).unwrap();
+        let code = |r: Response| r.error.unwrap().code;
+
+        assert_eq!(ok(submit(10, stratum::SUBMIT, "rig1", &job_id, share)), SubmitResult { accepted: true, solution: false });
+        assert_eq!(code(submit(11, stratum::SUBMIT, "rig1", &job_id, share)), stratum::ERR_DUPLICATE);
+        assert_eq!(code(submit(12, stratum::SUBMIT, "rig1", &job_id, weak)), stratum::ERR_LOW_DIFFICULTY);
+        assert_eq!(code(submit(13, stratum::SUBMIT_SOLUTION, "rig1", &job_id, share)), stratum::ERR_LOW_DIFFICULTY);
+        assert_eq!(code(submit(14, stratum::SUBMIT, "rig1", "stale", share)), stratum::ERR_STALE_JOB);
+        assert_eq!(code(submit(15, stratum::SUBMIT, "rig1", &job_id, share ^ (1 << 40))), stratum::ERR_OTHER);
+        assert_eq!(code(submit(16, stratum::SUBMIT, "rig2", &job_id, share)), stratum::ERR_UNAUTHORIZED);
+        assert_eq!(ok(submit(17, stratum::SUBMIT_SOLUTION, "rig1", &job_id, solution)), SubmitResult { accepted: true, solution: true });
+
+        let stats = pool.stats();
+        assert_eq!(stats.workers.len(), 1);
+        let rig1 = &stats.workers[0];
+        assert_eq!((rig1.worker.as_str(), rig1.accepted_shares, rig1.rejected_shares, rig1.solutions), ("rig1", 2, 5, 1));
+        assert_eq!(stats.solutions.len(), 1);
+        assert_eq!(stats.solutions[0].nonce, format!("{:016x}", solution));
+
+        // A new job makes the old one stale
+        let (next_job, _) = pool.set_job(job_request(1), Difficulty::parse("0FFFFFFF").unwrap(), 1, rom);
+        assert_eq!(notify(&rx.try_recv().unwrap()).job_id, next_job);
+        assert_eq!(code(submit(18, stratum::SUBMIT, "rig1", &job_id, share)), stratum::ERR_STALE_JOB);
+    }
+
+    #[actix_web::test]
+    async fn miners_talk_line_delimited_json_over_tcp() {
+        let _globals = crate::tests::GLOBALS.lock().await;
+        let rom = load_rom();
+        let pool: &'static Pool = Box::leak(Box::new(Pool::new(None)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        actix_web::rt::spawn(serve(pool, listener));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let hello = [
+            request(1, stratum::SUBSCRIBE, serde_json::json!(["test-miner/1.0"])),
+            request(2, stratum::AUTHORIZE, serde_json::json!(["rig1"])),
+            "not json".to_string(),
+        ];
+        write.write_all((hello.join("\n") + "\n").as_bytes()).await.unwrap();
+
+        let subscribed = response(&lines.next_line().await.unwrap().unwrap());
+        assert_eq!(subscribed.id, Some(1));
+        assert_eq!(response(&lines.next_line().await.unwrap().unwrap()).result, serde_json::json!(true));
+        assert_eq!(response(&lines.next_line().await.unwrap().unwrap()).error.unwrap().code, stratum::ERR_PARSE);
+
+        let (job_id, notified) = pool.set_job(job_request(0), Difficulty::parse("0FFFFFFF").unwrap(), 0, rom);
+        assert_eq!(notified, 1);
+        let job = notify(&lines.next_line().await.unwrap().unwrap());
+        assert_eq!(job.job_id, job_id);
+
+        // Every hash meets a 0-bit share difficulty
+        let nonce = format!("{}00000000", job.extranonce);
+        write.write_all(format!("{}\n", request(3, stratum::SUBMIT, serde_json::json!(["rig1", job_id, nonce]))).as_bytes()).await.unwrap();
+        let submitted = response(&lines.next_line().await.unwrap().unwrap());
+        assert_eq!(submitted.id, Some(3));
+        assert!(serde_json::from_value::<SubmitResult>(submitted.result).unwrap().accepted);
+        assert_eq!(pool.stats().miners, 1);
+
+        drop(write);
+        assert!(lines.next_line().await.unwrap().is_none());
+    }
+}
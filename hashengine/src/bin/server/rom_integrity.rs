@@ -0,0 +1,57 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Outcome of the ROM digest checks (`Rom::verify`), for /health and /rom/verify.
+///
+/// A ROM that fails its check is listed as corrupt until its regenerated copy is
+/// swapped in; the server reports itself unhealthy meanwhile, since every hash
+/// computed against it is wrong.
+#[derive(Default)]
+pub struct RomIntegrity {
+    state: Mutex<IntegrityState>,
+}
+
+#[derive(Default)]
+struct IntegrityState {
+    corrupt: BTreeSet<String>,
+    last_check: Option<Instant>,
+    mismatches: u64,
+}
+
+impl RomIntegrity {
+    /// Record that a verification pass over the loaded ROMs finished
+    pub fn record_check(&self) {
+        self.state.lock().unwrap().last_check = Some(Instant::now());
+    }
+
+    /// Mark a ROM corrupt. Returns false if it was already marked.
+    pub fn mark_corrupt(&self, no_pre_mine: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.mismatches += 1;
+        state.corrupt.insert(no_pre_mine.to_string())
+    }
+
+    pub fn mark_repaired(&self, no_pre_mine: &str) {
+        self.state.lock().unwrap().corrupt.remove(no_pre_mine);
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.state.lock().unwrap().corrupt.is_empty()
+    }
+
+    /// Keys of the ROMs currently marked corrupt, sorted
+    pub fn corrupt(&self) -> Vec<String> {
+        self.state.lock().unwrap().corrupt.iter().cloned().collect()
+    }
+
+    /// Seconds since the last verification pass, None before the first one
+    pub fn secs_since_check(&self) -> Option<f64> {
+        self.state.lock().unwrap().last_check.map(|t| t.elapsed().as_secs_f64())
+    }
+
+    /// Digest mismatches detected since startup
+    pub fn mismatches(&self) -> u64 {
+        self.state.lock().unwrap().mismatches
+    }
+}
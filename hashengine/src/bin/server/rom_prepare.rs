@@ -0,0 +1,161 @@
+use crate::rom::RomProgress;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// ROM builds running in the background, keyed by no_pre_mine, so /init and
+/// /rom/activate can wait for an in-flight build instead of starting a second one.
+#[derive(Default)]
+pub struct PendingRoms {
+    builds: Mutex<HashMap<String, Arc<PendingBuild>>>,
+}
+
+pub struct PendingBuild {
+    done: Mutex<bool>,
+    cond: Condvar,
+    started_at: Instant,
+    progress: Mutex<(Option<RomProgress>, Instant)>,
+}
+
+impl PendingBuild {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            done: Mutex::new(false),
+            cond: Condvar::new(),
+            started_at: now,
+            progress: Mutex::new((None, now)),
+        }
+    }
+
+    /// Record generation progress; called from the generating threads
+    pub fn report(&self, progress: RomProgress) {
+        *self.progress.lock().unwrap() = (Some(progress), Instant::now());
+    }
+
+    /// Latest reported progress (None until generation starts, e.g. while reading the
+    /// ROM cache) and the time since it was reported
+    pub fn progress(&self) -> (Option<RomProgress>, Duration) {
+        let (progress, updated_at) = *self.progress.lock().unwrap();
+        (progress, updated_at.elapsed())
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Block until the build has finished (successfully or not)
+    pub fn wait(&self) {
+        let mut done = self.done.lock().unwrap();
+        while !*done {
+            done = self.cond.wait(done).unwrap();
+        }
+    }
+}
+
+/// Marks a build finished when dropped, so waiters are released even if the build panics
+pub struct BuildGuard<'a> {
+    pending: &'a PendingRoms,
+    key: String,
+    build: Arc<PendingBuild>,
+}
+
+impl BuildGuard<'_> {
+    pub fn build(&self) -> &PendingBuild {
+        &self.build
+    }
+}
+
+impl Drop for BuildGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.builds.lock().unwrap().remove(&self.key);
+        *self.build.done.lock().unwrap() = true;
+        self.build.cond.notify_all();
+    }
+}
+
+impl PendingRoms {
+    /// Register a build for `key`. Returns None if one is already in progress.
+    pub fn start(&self, key: &str) -> Option<BuildGuard<'_>> {
+        let mut builds = self.builds.lock().unwrap();
+        if builds.contains_key(key) {
+            return None;
+        }
+        let build = Arc::new(PendingBuild::new());
+        builds.insert(key.to_string(), Arc::clone(&build));
+        Some(BuildGuard {
+            pending: self,
+            key: key.to_string(),
+            build,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<PendingBuild>> {
+        self.builds.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.builds.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    /// In-flight builds sorted by key
+    pub fn list(&self) -> Vec<(String, Arc<PendingBuild>)> {
+        let mut builds: Vec<(String, Arc<PendingBuild>)> = self
+            .builds
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, build)| (key.clone(), Arc::clone(build)))
+            .collect();
+        builds.sort_by(|a, b| a.0.cmp(&b.0));
+        builds
+    }
+}
+
+/// Rayon pool for background ROM builds. Its threads run at a lower scheduling priority
+/// so a build competes as little as possible with hashing on the global pool.
+pub fn low_priority_pool(num_threads: usize) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(|i| format!("rom-prepare-{}", i))
+        .start_handler(|_| lower_current_thread_priority())
+        .build()
+        .expect("failed to build ROM prepare thread pool")
+}
+
+#[cfg(target_os = "linux")]
+fn lower_current_thread_priority() {
+    // On Linux the nice value is per thread, addressed by its tid
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        if libc::setpriority(libc::PRIO_PROCESS, tid, 10) != 0 {
+            log::warn!("Failed to lower ROM prepare thread priority: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lower_current_thread_priority() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waiters_are_released_when_build_finishes() {
+        let pending = PendingRoms::default();
+        let guard = pending.start("key").unwrap();
+        assert!(pending.start("key").is_none());
+
+        let build = pending.get("key").unwrap();
+        let waiter = std::thread::spawn(move || build.wait());
+        drop(guard);
+        waiter.join().unwrap();
+
+        assert!(pending.get("key").is_none());
+        assert!(pending.start("key").is_some());
+    }
+}
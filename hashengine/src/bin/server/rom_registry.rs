@@ -0,0 +1,308 @@
+use crate::numa::RomReplicas;
+use crate::rom::{Rom, RomGenerationType};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Loaded ROMs keyed by no_pre_mine, so overlapping challenges (or dev-fee mining on a
+/// different no_pre_mine) do not keep regenerating each other's ROM.
+///
+/// The default ROM (set by /init or /rom/activate) is the target for requests that do not
+/// name one. When the total ROM size exceeds the memory budget, least recently used ROMs
+/// are evicted; the default ROM and the ROM being inserted are always kept. Every NUMA
+/// replica of a ROM counts against the budget.
+pub struct RomRegistry {
+    budget_bytes: usize,
+    state: RwLock<RegistryState>,
+    clock: AtomicU64,
+}
+
+struct RegistryState {
+    entries: HashMap<String, RomEntry>,
+    default_key: Option<String>,
+}
+
+struct RomEntry {
+    rom: RomReplicas,
+    gen_type: RomGenerationType,
+    last_used: AtomicU64,
+}
+
+/// A ROM dropped from the registry: evicted over budget, or replaced by a ROM with other
+/// parameters under the same no_pre_mine
+pub struct RemovedRom {
+    pub no_pre_mine: String,
+    pub gen_type: RomGenerationType,
+    pub rom: RomReplicas,
+    /// Evicted over the memory budget rather than replaced
+    pub evicted: bool,
+}
+
+/// Summary of a loaded ROM, for /health
+pub struct RomInfo {
+    pub no_pre_mine: String,
+    pub size: usize,
+    pub gen_type: RomGenerationType,
+    pub backing: &'static str,
+    pub copies: usize,
+    pub is_default: bool,
+}
+
+impl RomRegistry {
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            budget_bytes,
+            state: RwLock::new(RegistryState {
+                entries: HashMap::new(),
+                default_key: None,
+            }),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    pub fn budget_bytes(&self) -> usize {
+        self.budget_bytes
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Look up a ROM by no_pre_mine, or the default ROM when `no_pre_mine` is None
+    pub fn get(&self, no_pre_mine: Option<&str>) -> Option<Arc<Rom>> {
+        self.get_replicas(no_pre_mine).map(|replicas| Arc::clone(replicas.primary()))
+    }
+
+    /// Same as `get`, with the NUMA replicas for hashing threads
+    pub fn get_replicas(&self, no_pre_mine: Option<&str>) -> Option<RomReplicas> {
+        let state = self.state.read().unwrap();
+        let key = no_pre_mine.or(state.default_key.as_deref())?;
+        let entry = state.entries.get(key)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(entry.rom.clone())
+    }
+
+    /// Whether a ROM with exactly these parameters is already loaded
+    pub fn contains(&self, no_pre_mine: &str, gen_type: RomGenerationType, size: usize) -> bool {
+        let state = self.state.read().unwrap();
+        state
+            .entries
+            .get(no_pre_mine)
+            .is_some_and(|e| e.gen_type == gen_type && e.rom.size() == size)
+    }
+
+    /// Insert (or replace) a ROM, optionally making it the default. Returns the ROMs
+    /// removed to make room, and the replaced one if its parameters differ.
+    pub fn insert(&self, no_pre_mine: String, gen_type: RomGenerationType, rom: impl Into<RomReplicas>, make_default: bool) -> Vec<RemovedRom> {
+        let rom = rom.into();
+        let size = rom.size();
+        let mut state = self.state.write().unwrap();
+        let replaced = state.entries.insert(
+            no_pre_mine.clone(),
+            RomEntry {
+                rom,
+                gen_type,
+                last_used: AtomicU64::new(self.tick()),
+            },
+        );
+        if make_default || state.default_key.is_none() {
+            state.default_key = Some(no_pre_mine.clone());
+        }
+        let mut removed = Vec::new();
+        if let Some(old) = replaced.filter(|old| old.gen_type != gen_type || old.rom.size() != size) {
+            removed.push(RemovedRom { no_pre_mine: no_pre_mine.clone(), gen_type: old.gen_type, rom: old.rom, evicted: false });
+        }
+        removed.extend(self.evict_over_budget(&mut state, &no_pre_mine));
+        removed
+    }
+
+    /// Make an already loaded ROM the default. Returns the evicted ROMs, or None if the
+    /// ROM is not loaded.
+    pub fn set_default(&self, no_pre_mine: &str) -> Option<Vec<RemovedRom>> {
+        let mut state = self.state.write().unwrap();
+        let entry = state.entries.get(no_pre_mine)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        state.default_key = Some(no_pre_mine.to_string());
+        Some(self.evict_over_budget(&mut state, no_pre_mine))
+    }
+
+    fn evict_over_budget(&self, state: &mut RegistryState, keep: &str) -> Vec<RemovedRom> {
+        let mut evicted = Vec::new();
+        while total_bytes(state) > self.budget_bytes {
+            let lru = state
+                .entries
+                .iter()
+                .filter(|(k, _)| k.as_str() != keep && Some(k.as_str()) != state.default_key.as_deref())
+                .min_by_key(|(_, e)| e.last_used.load(Ordering::Relaxed))
+                .map(|(k, _)| k.clone());
+            match lru {
+                Some(key) => {
+                    let entry = state.entries.remove(&key).unwrap();
+                    evicted.push(RemovedRom { no_pre_mine: key, gen_type: entry.gen_type, rom: entry.rom, evicted: true });
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Loaded ROMs with their generation parameters, for background checks
+    pub fn snapshot(&self) -> Vec<(String, RomGenerationType, RomReplicas)> {
+        let state = self.state.read().unwrap();
+        let mut roms: Vec<(String, RomGenerationType, RomReplicas)> = state
+            .entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.gen_type, entry.rom.clone()))
+            .collect();
+        roms.sort_by(|a, b| a.0.cmp(&b.0));
+        roms
+    }
+
+    /// Swap in `rom` for `no_pre_mine` only if the entry's primary is still `current`, so a
+    /// ROM re-initialized or evicted in the meantime is left alone. The default and LRU
+    /// position are kept.
+    pub fn replace(&self, no_pre_mine: &str, current: &Arc<Rom>, rom: impl Into<RomReplicas>) -> bool {
+        let mut state = self.state.write().unwrap();
+        match state.entries.get_mut(no_pre_mine) {
+            Some(entry) if Arc::ptr_eq(entry.rom.primary(), current) => {
+                entry.rom = rom.into();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn default_key(&self) -> Option<String> {
+        self.state.read().unwrap().default_key.clone()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        total_bytes(&self.state.read().unwrap())
+    }
+
+    pub fn list(&self) -> Vec<RomInfo> {
+        let state = self.state.read().unwrap();
+        let mut infos: Vec<RomInfo> = state
+            .entries
+            .iter()
+            .map(|(key, entry)| RomInfo {
+                no_pre_mine: key.clone(),
+                size: entry.rom.size(),
+                gen_type: entry.gen_type,
+                backing: entry.rom.primary().backing().as_str(),
+                copies: entry.rom.copies(),
+                is_default: state.default_key.as_deref() == Some(key.as_str()),
+            })
+            .collect();
+        infos.sort_by(|a, b| a.no_pre_mine.cmp(&b.no_pre_mine));
+        infos
+    }
+}
+
+fn total_bytes(state: &RegistryState) -> usize {
+    state.entries.values().map(|e| e.rom.size() * e.rom.copies()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::numa::{NumaNode, NumaTopology};
+    use crate::rom_storage::RomBacking;
+
+    const SIZE: usize = 64 * 1024;
+    const GEN: RomGenerationType = RomGenerationType::FullRandom;
+
+    fn rom(key: &str) -> Arc<Rom> {
+        Arc::new(Rom::new(key.as_bytes(), GEN, SIZE).unwrap())
+    }
+
+    fn keys(removed: Vec<RemovedRom>) -> Vec<String> {
+        removed.into_iter().map(|r| r.no_pre_mine).collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used_over_budget() {
+        let registry = RomRegistry::new(2 * SIZE);
+        assert!(registry.insert("a".into(), GEN, rom("a"), true).is_empty());
+        assert!(registry.insert("b".into(), GEN, rom("b"), true).is_empty());
+
+        // Touch "a" so "b" becomes the least recently used
+        assert!(registry.get(Some("a")).is_some());
+        assert_eq!(keys(registry.insert("c".into(), GEN, rom("c"), true)), vec!["b".to_string()]);
+
+        assert!(registry.get(Some("b")).is_none());
+        assert!(registry.contains("a", GEN, SIZE));
+        assert_eq!(registry.default_key().as_deref(), Some("c"));
+        assert!(Arc::ptr_eq(&registry.get(None).unwrap(), &registry.get(Some("c")).unwrap()));
+
+        // A single ROM larger than the budget is still kept
+        let registry = RomRegistry::new(SIZE / 2);
+        registry.insert("a".into(), GEN, rom("a"), true);
+        assert!(registry.get(None).is_some());
+    }
+
+    #[test]
+    fn replace_only_swaps_the_expected_rom() {
+        let registry = RomRegistry::new(4 * SIZE);
+        let original = rom("a");
+        registry.insert("a".into(), GEN, Arc::clone(&original), true);
+
+        let fresh = rom("a");
+        assert!(registry.replace("a", &original, Arc::clone(&fresh)));
+        assert!(Arc::ptr_eq(&registry.get(None).unwrap(), &fresh));
+
+        // Stale expectation or missing key: nothing changes
+        assert!(!registry.replace("a", &original, rom("a")));
+        assert!(!registry.replace("b", &original, rom("b")));
+        assert!(Arc::ptr_eq(&registry.get(Some("a")).unwrap(), &fresh));
+        assert_eq!(registry.snapshot().len(), 1);
+    }
+
+    #[test]
+    fn numa_replicas_count_against_the_budget() {
+        let registry = RomRegistry::new(3 * SIZE);
+        let cpus = vec![0];
+        let topology = NumaTopology {
+            nodes: vec![NumaNode { id: 0, cpus: cpus.clone() }, NumaNode { id: 1, cpus }],
+        };
+        let replicas = RomReplicas::replicate(rom("a"), &topology, RomBacking::Heap);
+        registry.insert("a".into(), GEN, replicas.clone(), true);
+        assert_eq!(registry.total_bytes(), 2 * SIZE);
+        assert_eq!(registry.list()[0].copies, 2);
+        assert!(Arc::ptr_eq(&registry.get(None).unwrap(), replicas.primary()));
+        assert_eq!(registry.get_replicas(Some("a")).unwrap().copies(), 2);
+
+        // Two more single-copy ROMs go over budget: "b" (least recently used) is evicted
+        registry.insert("b".into(), GEN, rom("b"), false);
+        assert_eq!(keys(registry.insert("c".into(), GEN, rom("c"), false)), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn prepared_rom_does_not_displace_default_until_activated() {
+        let registry = RomRegistry::new(SIZE);
+        registry.insert("current".into(), GEN, rom("current"), true);
+
+        // Over budget, but neither the default nor the prepared ROM may be evicted
+        assert!(registry.insert("next".into(), GEN, rom("next"), false).is_empty());
+        assert_eq!(registry.default_key().as_deref(), Some("current"));
+        assert!(registry.get(Some("next")).is_some());
+
+        assert_eq!(registry.set_default("next").map(keys), Some(vec!["current".to_string()]));
+        assert_eq!(registry.default_key().as_deref(), Some("next"));
+        assert!(registry.set_default("missing").is_none());
+    }
+
+    #[test]
+    fn replacing_with_other_parameters_returns_the_old_rom() {
+        let registry = RomRegistry::new(4 * SIZE);
+        registry.insert("a".into(), GEN, rom("a"), true);
+        assert!(registry.insert("a".into(), GEN, rom("a"), true).is_empty());
+
+        let two_step = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let removed = registry.insert("a".into(), two_step, Arc::new(Rom::new(b"a", two_step, SIZE).unwrap()), true);
+        assert_eq!(removed.len(), 1);
+        assert!(!removed[0].evicted && removed[0].gen_type == GEN);
+        assert!(registry.contains("a", two_step, SIZE));
+    }
+}
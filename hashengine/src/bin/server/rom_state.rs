@@ -0,0 +1,193 @@
+use crate::error::HashEngineError;
+use crate::numa::RomReplicas;
+use crate::rom::RomGenerationType;
+use crate::rom_cache::RomCache;
+use crate::{key_prefix, load_or_generate_rom, log_evictions, replicate_for_numa, METRICS, PENDING_ROMS, ROMS, ROM_BACKING, ROM_CACHE};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+// Loaded ROMs saved at shutdown and restored at startup, with ROM_STATE_DIR. The ROM data
+// is written in the `rom_cache` format, so its digest is checked when it is read back,
+// next to a roms.json manifest with each ROM's parameters and which one was the default.
+
+const MANIFEST: &str = "roms.json";
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SavedRom {
+    no_pre_mine: String,
+    generation: String,
+    pre_size: usize,
+    mixing_numbers: usize,
+    rom_size: usize,
+    default: bool,
+}
+
+impl SavedRom {
+    fn new(no_pre_mine: String, gen_type: RomGenerationType, rom_size: usize, default: bool) -> Self {
+        let (generation, pre_size, mixing_numbers) = match gen_type {
+            RomGenerationType::FullRandom => ("FullRandom", 0, 0),
+            RomGenerationType::TwoStep { pre_size, mixing_numbers } => ("TwoStep", pre_size, mixing_numbers),
+        };
+        Self { no_pre_mine, generation: generation.to_string(), pre_size, mixing_numbers, rom_size, default }
+    }
+
+    fn gen_type(&self) -> Result<RomGenerationType, HashEngineError> {
+        RomGenerationType::from_config(&self.generation, self.pre_size, self.mixing_numbers)
+    }
+}
+
+/// Save every loaded ROM to `dir`. Returns the number saved.
+pub fn save(dir: &Path) -> io::Result<usize> {
+    // Sharing ROM_CACHE_DIR is fine, but then its own limits decide what to keep
+    let shared_with_cache = ROM_CACHE.as_ref().is_some_and(|cache| same_dir(cache.dir(), dir));
+    save_roms(dir, ROMS.snapshot(), ROMS.default_key().as_deref(), !shared_with_cache)
+}
+
+/// Write `roms` and the manifest. ROM files already in `dir` are kept as they are; with
+/// `prune`, files of ROMs no longer loaded are removed.
+fn save_roms(dir: &Path, roms: Vec<(String, RomGenerationType, RomReplicas)>, default_key: Option<&str>, prune: bool) -> io::Result<usize> {
+    let store = RomCache::new(dir, roms.len(), None)?;
+    let mut saved = Vec::with_capacity(roms.len());
+    for (key, gen_type, replicas) in roms {
+        let rom = replicas.primary();
+        let path = store.entry_path(key.as_bytes(), gen_type, rom.data().len());
+        if path.exists() {
+            // Mark it recently used so pruning keeps it
+            File::options().write(true).open(&path)?.set_modified(SystemTime::now())?;
+        } else {
+            store.store(key.as_bytes(), gen_type, rom)?;
+        }
+        let default = default_key == Some(key.as_str());
+        saved.push(SavedRom::new(key, gen_type, rom.data().len(), default));
+    }
+
+    let manifest = dir.join(MANIFEST);
+    let tmp_path = manifest.with_extension(format!("json.tmp{}", std::process::id()));
+    fs::write(&tmp_path, serde_json::to_vec_pretty(&saved)?)?;
+    fs::rename(&tmp_path, &manifest)?;
+    if prune {
+        store.prune()?;
+    }
+    Ok(saved.len())
+}
+
+fn read_manifest(dir: &Path) -> io::Result<Option<Vec<SavedRom>>> {
+    match fs::read(dir.join(MANIFEST)) {
+        Ok(manifest) => serde_json::from_slice(&manifest).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Reload the ROMs saved in `dir` on a background thread; they show up in /rom/status
+/// meanwhile. A ROM whose file is missing or corrupt is rebuilt the usual way.
+pub fn restore(dir: PathBuf) -> io::Result<()> {
+    let Some(mut saved) = read_manifest(&dir)? else {
+        return Ok(());
+    };
+    // The default goes last so it ends up the default
+    saved.sort_by_key(|rom| rom.default);
+    info!("Restoring {} ROM(s) from {}", saved.len(), dir.display());
+
+    let store = RomCache::new(&dir, saved.len(), None)?;
+    std::thread::Builder::new().name("rom-restore".to_string()).spawn(move || {
+        for rom in saved {
+            restore_rom(&store, rom);
+        }
+    })?;
+    Ok(())
+}
+
+fn restore_rom(store: &RomCache, saved: SavedRom) {
+    let short_key = key_prefix(&saved.no_pre_mine);
+    let gen_type = match saved.gen_type() {
+        Ok(gen_type) => gen_type,
+        Err(e) => return warn!("Not restoring ROM {}: {}", short_key, e),
+    };
+    // Loaded or being built by a request that came in first
+    if ROMS.contains(&saved.no_pre_mine, gen_type, saved.rom_size) {
+        return;
+    }
+    let Some(guard) = PENDING_ROMS.start(&saved.no_pre_mine) else {
+        return;
+    };
+
+    let start = Instant::now();
+    let key = saved.no_pre_mine.as_bytes();
+    let rom = match store.load(key, gen_type, saved.rom_size, *ROM_BACKING) {
+        Ok(Some(rom)) => {
+            METRICS.rom_init(start.elapsed(), true);
+            Ok(Arc::new(rom))
+        }
+        result => {
+            if let Err(e) = result {
+                warn!("Cannot read saved ROM {}, rebuilding: {}", short_key, e);
+            }
+            load_or_generate_rom(key, gen_type, saved.rom_size, &|p| guard.build().report(p)).map(|(rom, _)| rom)
+        }
+    };
+    match rom {
+        Ok(rom) => {
+            log_evictions(ROMS.insert(saved.no_pre_mine.clone(), gen_type, replicate_for_numa(rom), saved.default));
+            info!(
+                "✓ Restored ROM {} in {:.1}s{}",
+                short_key,
+                start.elapsed().as_secs_f64(),
+                if saved.default { " (default)" } else { "" }
+            );
+        }
+        Err(e) => warn!("Failed to restore ROM {}: {}", short_key, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Rom;
+    use crate::rom_storage::RomBacking;
+
+    #[test]
+    fn saved_roms_round_trip_through_the_manifest() {
+        let dir = std::env::temp_dir().join(format!("hashengine-rom-state-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let size = 256 * 1024;
+        let roms: Vec<(String, RomGenerationType, RomReplicas)> = ["state-a", "state-b"]
+            .into_iter()
+            .map(|key| (key.to_string(), gen_type, Arc::new(Rom::new(key.as_bytes(), gen_type, size).unwrap()).into()))
+            .collect();
+
+        assert_eq!(save_roms(&dir, roms.clone(), Some("state-b"), true).unwrap(), 2);
+        let manifest = read_manifest(&dir).unwrap().unwrap();
+        assert_eq!(manifest[1], SavedRom::new("state-b".to_string(), gen_type, size, true));
+        assert!(!manifest[0].default);
+        assert_eq!(manifest[0].gen_type().unwrap(), gen_type);
+
+        let store = RomCache::new(&dir, 2, None).unwrap();
+        let loaded = store.load(b"state-a", gen_type, size, RomBacking::Heap).unwrap().unwrap();
+        assert_eq!(loaded.digest.0, roms[0].2.primary().digest.0);
+
+        // Saving again with one ROM left removes the other's file
+        assert_eq!(save_roms(&dir, roms[1..].to_vec(), None, true).unwrap(), 1);
+        assert!(!store.entry_path(b"state-a", gen_type, size).exists());
+        assert!(store.entry_path(b"state-b", gen_type, size).exists());
+        assert_eq!(read_manifest(&dir).unwrap().unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).ok();
+        assert!(read_manifest(&dir).unwrap().is_none());
+    }
+}
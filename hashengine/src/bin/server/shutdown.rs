@@ -0,0 +1,216 @@
+use crate::{rom_state, ws_session, ErrorResponse, DRAIN, JOBS, ROM_STATE_DIR, SHUTDOWN_TIMEOUT};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServerHandle, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::HttpResponse;
+use log::{error, info, warn};
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Graceful shutdown. SIGTERM or SIGINT puts the server into draining: requests that would
+// start new work get 503, while status endpoints keep answering and /health reports
+// `draining`. In-flight batches, searches, jobs and WebSocket mining run to completion for
+// up to SHUTDOWN_TIMEOUT_SECS, then the loaded ROMs are saved (with ROM_STATE_DIR) and the
+// server stops. A second signal skips the wait.
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Draining flag and the work requests still being served
+#[derive(Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+/// Counts a request as in flight until dropped
+pub struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drain {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Start draining; false if already draining
+    fn start(&self) -> bool {
+        !self.draining.swap(true, Ordering::SeqCst)
+    }
+
+    /// Admit a work request, or None once draining. Counted before the flag is checked, so
+    /// a request racing the signal is either refused or waited for.
+    pub fn enter(&self) -> Option<InFlight<'_>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlight(&self.in_flight);
+        (!self.is_draining()).then_some(guard)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/// Requests still allowed while draining: reads, job status and cancellation. Anything that
+/// hashes, starts a job or session, or loads a ROM is refused, including /rom/verify,
+/// which regenerates the ROMs that fail it.
+pub fn allowed_while_draining(method: &Method, pattern: &str) -> bool {
+    match (method, pattern) {
+        (&Method::GET, "/ws" | "/rom/verify") => false,
+        (&Method::GET | &Method::HEAD | &Method::DELETE, _) => true,
+        _ => false,
+    }
+}
+
+/// Middleware refusing new work while draining and counting the work in flight
+pub async fn refuse_while_draining(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let pattern = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+    if allowed_while_draining(req.method(), &pattern) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+    let Some(_in_flight) = DRAIN.enter() else {
+        let response = HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, SHUTDOWN_TIMEOUT.as_secs().max(1).to_string()))
+            .json(ErrorResponse { error: "Server is shutting down".to_string() });
+        return Ok(req.into_response(response).map_into_right_body());
+    };
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/// Work the shutdown waits for
+struct Pending {
+    requests: usize,
+    jobs: usize,
+    sessions: usize,
+}
+
+impl Pending {
+    fn current() -> Self {
+        Self { requests: DRAIN.in_flight(), jobs: JOBS.running(), sessions: ws_session::mining_sessions() }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.requests == 0 && self.jobs == 0 && self.sessions == 0
+    }
+}
+
+impl fmt::Display for Pending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} request(s), {} job(s), {} WebSocket job(s)", self.requests, self.jobs, self.sessions)
+    }
+}
+
+#[cfg(unix)]
+struct Signals {
+    term: tokio::signal::unix::Signal,
+    int: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self { term: signal(SignalKind::terminate())?, int: signal(SignalKind::interrupt())? })
+    }
+
+    async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.term.recv() => "SIGTERM",
+            _ = self.int.recv() => "SIGINT",
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) -> &'static str {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
+
+/// Wait for SIGTERM/SIGINT, drain, save the ROM state and stop `server`
+pub async fn on_signal(server: ServerHandle) {
+    let mut signals = match Signals::new() {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Cannot listen for shutdown signals, stop the server with SIGKILL: {}", e);
+            return;
+        }
+    };
+    let signal = signals.recv().await;
+    DRAIN.start();
+    info!("{} received: draining for up to {}s (send it again to stop now)", signal, SHUTDOWN_TIMEOUT.as_secs());
+
+    let deadline = Instant::now() + *SHUTDOWN_TIMEOUT;
+    loop {
+        let pending = Pending::current();
+        if pending.is_idle() {
+            info!("Drained, stopping");
+            break;
+        }
+        if Instant::now() >= deadline {
+            warn!("Shutdown deadline reached, abandoning {}", pending);
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(DRAIN_POLL_INTERVAL) => {}
+            signal = signals.recv() => {
+                warn!("{} received again, abandoning {}", signal, pending);
+                break;
+            }
+        }
+    }
+
+    if let Some(dir) = ROM_STATE_DIR.clone() {
+        let start = Instant::now();
+        match actix_web::rt::task::spawn_blocking(move || rom_state::save(&dir).map(|n| (n, dir))).await {
+            Ok(Ok((n, dir))) => info!("Saved {} ROM(s) to {} in {:.1}s", n, dir.display(), start.elapsed().as_secs_f64()),
+            Ok(Err(e)) => error!("Failed to save ROM state: {}", e),
+            Err(e) => error!("ROM state task failed: {}", e),
+        }
+    }
+    server.stop(true).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draining_refuses_new_work_and_counts_in_flight() {
+        assert!(allowed_while_draining(&Method::GET, "/health"));
+        assert!(allowed_while_draining(&Method::DELETE, "/jobs/{id}"));
+        assert!(!allowed_while_draining(&Method::POST, "/hash-batch"));
+        assert!(!allowed_while_draining(&Method::POST, "/init"));
+        assert!(!allowed_while_draining(&Method::GET, "/ws"));
+        assert!(!allowed_while_draining(&Method::GET, "/rom/verify"));
+        assert!(allowed_while_draining(&Method::GET, "/rom/status"));
+
+        let drain = Drain::default();
+        let running = drain.enter().unwrap();
+        assert_eq!(drain.in_flight(), 1);
+
+        assert!(drain.start());
+        assert!(!drain.start());
+        assert!(drain.is_draining());
+        assert!(drain.enter().is_none());
+        assert_eq!(drain.in_flight(), 1);
+        drop(running);
+        assert_eq!(drain.in_flight(), 0);
+    }
+}
@@ -0,0 +1,321 @@
+use log::{info, warn};
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig, SignatureScheme};
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// Optional HTTPS for the HTTP listener. TLS_CERT and TLS_KEY (PEM files) turn it on, and
+// TLS_CLIENT_CA additionally requires clients to present a certificate issued by one of
+// its CAs. The files are re-read periodically and swapped in without a restart; a renewal
+// that fails to load is logged and the previous certificate stays in use.
+
+/// PEM files the TLS configuration is loaded from
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    /// Certificate chain, leaf first
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// CAs that client certificates must chain to; None to accept any client
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsFiles {
+    /// Paths from TLS_CERT, TLS_KEY and TLS_CLIENT_CA; None when TLS is not configured
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let var = |name| std::env::var(name).ok().filter(|v: &String| !v.trim().is_empty()).map(PathBuf::from);
+        match (var("TLS_CERT"), var("TLS_KEY"), var("TLS_CLIENT_CA")) {
+            (Some(cert), Some(key), client_ca) => Ok(Some(Self { cert, key, client_ca })),
+            (None, None, None) => Ok(None),
+            (None, None, Some(_)) => Err("TLS_CLIENT_CA needs TLS_CERT and TLS_KEY".to_string()),
+            _ => Err("TLS_CERT and TLS_KEY must be set together".to_string()),
+        }
+    }
+}
+
+/// Contents of the files, compared to tell when they change
+#[derive(PartialEq)]
+struct Pem {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+    client_ca: Option<Vec<u8>>,
+}
+
+struct Loaded {
+    key: Arc<CertifiedKey>,
+    verifier: Option<Arc<dyn ClientCertVerifier>>,
+    pem: Pem,
+}
+
+/// Server certificate and client verifier, replaced in place when the files change
+pub struct Tls {
+    files: TlsFiles,
+    provider: Arc<CryptoProvider>,
+    loaded: RwLock<Loaded>,
+}
+
+impl Tls {
+    pub fn load(files: TlsFiles) -> Result<Self, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let loaded = parse(&files, &provider, read_files(&files)?)?;
+        Ok(Self { files, provider, loaded: RwLock::new(loaded) })
+    }
+
+    pub fn files(&self) -> &TlsFiles {
+        &self.files
+    }
+
+    /// Configuration for `HttpServer::bind_rustls_0_23`; connections made with it always
+    /// see the latest certificate
+    pub fn server_config(self: &Arc<Self>) -> Result<ServerConfig, String> {
+        let builder = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?;
+        let builder = match self.files.client_ca {
+            Some(_) => builder.with_client_cert_verifier(self.clone()),
+            None => builder.with_no_client_auth(),
+        };
+        Ok(builder.with_cert_resolver(self.clone()))
+    }
+
+    /// Re-read the files and swap in their contents if they changed. Ok(true) when a new
+    /// configuration was loaded; on Err the current one is kept.
+    pub fn reload_if_changed(&self) -> Result<bool, String> {
+        let pem = read_files(&self.files)?;
+        if self.loaded.read().unwrap().pem == pem {
+            return Ok(false);
+        }
+        let loaded = parse(&self.files, &self.provider, pem)?;
+        *self.loaded.write().unwrap() = loaded;
+        Ok(true)
+    }
+
+    /// Check the files for changes every `interval` on a background thread
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> std::io::Result<()> {
+        let tls = self.clone();
+        std::thread::Builder::new().name("tls-reload".to_string()).spawn(move || loop {
+            std::thread::sleep(interval);
+            match tls.reload_if_changed() {
+                Ok(true) => info!("TLS: reloaded {}", tls.files.cert.display()),
+                Ok(false) => {}
+                Err(e) => warn!("TLS: reload failed, keeping the current certificate: {}", e),
+            }
+        })?;
+        Ok(())
+    }
+
+    fn client_verifier(&self) -> Arc<dyn ClientCertVerifier> {
+        let loaded = self.loaded.read().unwrap();
+        loaded.verifier.clone().expect("client verifier is only installed with TLS_CLIENT_CA")
+    }
+}
+
+fn read_files(files: &TlsFiles) -> Result<Pem, String> {
+    let read = |path: &Path| std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e));
+    Ok(Pem { cert: read(&files.cert)?, key: read(&files.key)?, client_ca: files.client_ca.as_deref().map(read).transpose()? })
+}
+
+fn parse(files: &TlsFiles, provider: &Arc<CryptoProvider>, pem: Pem) -> Result<Loaded, String> {
+    let chain = certificates(&pem.cert, &files.cert)?;
+    let key = PrivateKeyDer::from_pem_slice(&pem.key).map_err(|e| format!("no private key in {}: {}", files.key.display(), e))?;
+    let key = CertifiedKey::from_der(chain, key, provider)
+        .map_err(|e| format!("{} does not fit {}: {}", files.key.display(), files.cert.display(), e))?;
+
+    let verifier = match (&files.client_ca, &pem.client_ca) {
+        (Some(path), Some(ca_pem)) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(ca_pem, path)? {
+                roots.add(cert).map_err(|e| format!("invalid CA in {}: {}", path.display(), e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(|e| format!("invalid client CA {}: {}", path.display(), e))?;
+            Some(verifier)
+        }
+        _ => None,
+    };
+    Ok(Loaded { key: Arc::new(key), verifier, pem })
+}
+
+fn certificates(pem: &[u8], path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid certificate in {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+impl fmt::Debug for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tls").field("files", &self.files).finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for Tls {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.loaded.read().unwrap().key.clone())
+    }
+}
+
+impl ClientCertVerifier for Tls {
+    // No CA hints, as they would have to outlive a reload; clients then send whichever
+    // certificate they have
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], now: UnixTime) -> Result<ClientCertVerified, rustls::Error> {
+        self.client_verifier().verify_client_cert(end_entity, intermediates, now)
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpServer};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, StreamOwned};
+    use std::io::{Read, Write};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hashengine-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write a fresh self-signed certificate for localhost, returning its DER
+    fn write_self_signed(files: &TlsFiles) -> CertificateDer<'static> {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(&files.cert, generated.cert.pem()).unwrap();
+        std::fs::write(&files.key, generated.key_pair.serialize_pem()).unwrap();
+        generated.cert.der().clone()
+    }
+
+    fn start_server(tls: &Arc<Tls>) -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = HttpServer::new(|| App::new().route("/health", web::get().to(|| async { "ok" })))
+            .workers(1)
+            .listen_rustls_0_23(listener, tls.server_config().unwrap())
+            .unwrap()
+            .run();
+        actix_web::rt::spawn(server);
+        port
+    }
+
+    /// GET /health over TLS, returning the response and the certificate the server sent
+    fn fetch(port: u16, trusted: CertificateDer<'static>, identity: Option<(CertificateDer<'static>, PrivateKeyDer<'static>)>) -> std::io::Result<(String, CertificateDer<'static>)> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add(trusted).unwrap();
+        let builder = ClientConfig::builder_with_provider(provider).with_safe_default_protocol_versions().unwrap().with_root_certificates(roots);
+        let config = match identity {
+            Some((cert, key)) => builder.with_client_auth_cert(vec![cert], key).unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        let connection = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+        let mut stream = StreamOwned::new(connection, std::net::TcpStream::connect(("127.0.0.1", port))?);
+        stream.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+        let mut response = Vec::new();
+        match stream.read_to_end(&mut response) {
+            Ok(_) => {}
+            // Servers may close without a close_notify once the response is sent
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !response.is_empty() => {}
+            Err(e) => return Err(e),
+        }
+        let served = stream.conn.peer_certificates().unwrap()[0].clone();
+        Ok((String::from_utf8_lossy(&response).into_owned(), served))
+    }
+
+    async fn fetch_async(
+        port: u16,
+        trusted: &CertificateDer<'static>,
+        identity: Option<(CertificateDer<'static>, PrivateKeyDer<'static>)>,
+    ) -> std::io::Result<(String, CertificateDer<'static>)> {
+        let trusted = trusted.clone();
+        web::block(move || fetch(port, trusted, identity)).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn serves_https_and_reloads_certificates() {
+        let dir = test_dir("reload");
+        let files = TlsFiles { cert: dir.join("cert.pem"), key: dir.join("key.pem"), client_ca: None };
+        let first = write_self_signed(&files);
+        let tls = Arc::new(Tls::load(files.clone()).unwrap());
+        let port = start_server(&tls);
+
+        let (response, served) = fetch_async(port, &first, None).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert_eq!(served, first);
+        assert_eq!(tls.reload_if_changed(), Ok(false));
+
+        // A renewed certificate is served to new connections without restarting
+        let second = write_self_signed(&files);
+        assert_eq!(tls.reload_if_changed(), Ok(true));
+        let (response, served) = fetch_async(port, &second, None).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert_eq!(served, second);
+        assert!(fetch_async(port, &first, None).await.is_err());
+
+        // A broken renewal keeps the current certificate
+        std::fs::write(&files.key, "not a key").unwrap();
+        assert!(tls.reload_if_changed().is_err());
+        assert_eq!(fetch_async(port, &second, None).await.unwrap().1, second);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn client_certificates_are_required_with_a_client_ca() {
+        let dir = test_dir("mtls");
+        let files = TlsFiles { cert: dir.join("cert.pem"), key: dir.join("key.pem"), client_ca: Some(dir.join("ca.pem")) };
+        let server_cert = write_self_signed(&files);
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        std::fs::write(files.client_ca.as_ref().unwrap(), ca.pem()).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["rig1".to_string()]).unwrap().signed_by(&client_key, &ca, &ca_key).unwrap();
+        let identity = || (client_cert.der().clone(), PrivateKeyDer::try_from(client_key.serialize_der()).unwrap());
+
+        let tls = Arc::new(Tls::load(files).unwrap());
+        let port = start_server(&tls);
+
+        let (response, _) = fetch_async(port, &server_cert, Some(identity())).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        // Without a certificate, or with one from another CA, nothing is served
+        let refused = |result: std::io::Result<(String, _)>| !matches!(result, Ok((response, _)) if response.starts_with("HTTP/1.1 200"));
+        assert!(refused(fetch_async(port, &server_cert, None).await));
+        let stranger = rcgen::generate_simple_self_signed(vec!["rig2".to_string()]).unwrap();
+        let stranger = (stranger.cert.der().clone(), PrivateKeyDer::try_from(stranger.key_pair.serialize_der()).unwrap());
+        assert!(refused(fetch_async(port, &server_cert, Some(stranger)).await));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,472 @@
+use crate::hashengine::{Difficulty, Hasher, Preimage};
+use crate::{key_prefix, parse_nonce_range, SplitHashes, ADMISSION, DRAIN, METRICS, ROMS};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::AggregatedMessage;
+use log::{debug, info};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Nonces hashed between checks for cancel and challenge updates (a fraction of a second
+// on a typical rig)
+const CHUNK_NONCES: u64 = 4096;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Largest client message; a job lists its addresses, so allow a few thousand of them
+const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+// Mining threads still running, across all sessions, so a shutdown can wait for them
+static MINING_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn mining_sessions() -> usize {
+    MINING_THREADS.load(Ordering::SeqCst)
+}
+
+/// Client -> server messages, JSON text frames tagged by `type`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Start mining, replacing (cancelling) the session's current job
+    Job(JobRequest),
+    /// Stop the current job
+    Cancel,
+    /// Swap the challenge of the current job; mining carries on from the current nonces
+    UpdateChallenge(Challenge),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Challenge {
+    challenge_id: String,
+    difficulty: String,
+    no_pre_mine: String,
+    latest_submission: String,
+    no_pre_mine_hour: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobRequest {
+    /// Echoed in every message about this job; assigned by the server when absent
+    #[serde(default)]
+    job_id: Option<String>,
+    #[serde(flatten)]
+    challenge: Challenge,
+    addresses: Vec<AddressRange>,
+}
+
+/// Nonces to try for one address, mined in the order given
+#[derive(Debug, Deserialize)]
+struct AddressRange {
+    address: String,
+    /// 16 hex chars, as in /search
+    start_nonce: String,
+    nonce_count: u64,
+}
+
+/// Server -> client messages
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Accepted { job_id: String },
+    ChallengeUpdated { job_id: String, challenge_id: String },
+    Progress { job_id: String, hashes: u64, hash_rate: f64, elapsed_secs: f64 },
+    /// An address found a nonce meeting the difficulty; mining moves to the next address
+    Solution { job_id: String, address: String, challenge_id: String, nonce: String, hash: String },
+    Done { job_id: String, reason: DoneReason, hashes: u64, solutions: usize },
+    Error { error: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DoneReason {
+    /// Every address found a solution or exhausted its range
+    Completed,
+    Cancelled,
+    /// The ROM for the job's no_pre_mine is not loaded (call /init first)
+    RomNotLoaded,
+}
+
+/// Validated nonce range `start..end` for an address
+struct AddressWork {
+    address: String,
+    start: u64,
+    end: u64,
+}
+
+/// Challenge fields with the difficulty already parsed
+struct ActiveChallenge {
+    fields: Challenge,
+    difficulty: Difficulty,
+}
+
+impl ActiveChallenge {
+    fn parse(fields: Challenge) -> Result<Self, String> {
+        let difficulty = Difficulty::parse(&fields.difficulty).map_err(|e| e.to_string())?;
+        Ok(Self { fields, difficulty })
+    }
+}
+
+/// A running job, shared between the session and its mining thread
+struct Job {
+    id: String,
+    challenge: Mutex<Arc<ActiveChallenge>>,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    hashes: AtomicU64,
+    started: Instant,
+}
+
+impl Job {
+    fn challenge(&self) -> Arc<ActiveChallenge> {
+        Arc::clone(&self.challenge.lock().unwrap())
+    }
+}
+
+/// GET /ws - Upgrade to a WebSocket mining session
+pub async fn ws_handler(req: HttpRequest, body: web::Payload) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let stream = stream.max_frame_size(MAX_MESSAGE_BYTES).aggregate_continuations().max_continuation_size(MAX_MESSAGE_BYTES);
+    actix_web::rt::spawn(run_session(session, stream));
+    Ok(response)
+}
+
+async fn run_session(mut session: actix_ws::Session, mut stream: actix_ws::AggregatedMessageStream) {
+    let (events_tx, mut events) = unbounded_channel();
+    let mut progress = tokio::time::interval(PROGRESS_INTERVAL);
+    let mut current: Option<Arc<Job>> = None;
+    let mut next_job = 1u64;
+    debug!("WebSocket mining session opened");
+
+    loop {
+        let reply = tokio::select! {
+            msg = stream.recv() => match msg {
+                Some(Ok(AggregatedMessage::Text(text))) => {
+                    handle_message(&text, &mut current, &mut next_job, &events_tx).await
+                }
+                Some(Ok(AggregatedMessage::Binary(_))) => {
+                    Some(ServerMessage::Error { error: "binary messages are not supported, send JSON text".to_string() })
+                }
+                Some(Ok(AggregatedMessage::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        break;
+                    }
+                    None
+                }
+                Some(Ok(AggregatedMessage::Pong(_))) => None,
+                Some(Ok(AggregatedMessage::Close(_))) | Some(Err(_)) | None => break,
+            },
+            Some(event) = events.recv() => Some(event),
+            _ = progress.tick() => current.as_ref().filter(|job| !job.finished.load(Ordering::Relaxed)).map(|job| {
+                let hashes = job.hashes.load(Ordering::Relaxed);
+                let elapsed_secs = job.started.elapsed().as_secs_f64();
+                ServerMessage::Progress { job_id: job.id.clone(), hashes, hash_rate: hashes as f64 / elapsed_secs, elapsed_secs }
+            }),
+        };
+
+        if let Some(reply) = reply {
+            let text = serde_json::to_string(&reply).expect("server messages serialize");
+            if session.text(text).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    // The client is gone: stop mining for it
+    if let Some(job) = current {
+        job.cancelled.store(true, Ordering::Relaxed);
+    }
+    let _ = session.close(None).await;
+    debug!("WebSocket mining session closed");
+}
+
+/// Apply a client message and return the immediate reply, if any. A job waits for an
+/// admission slot like a batch, and is refused when the admission queue is full.
+async fn handle_message(
+    text: &str,
+    current: &mut Option<Arc<Job>>,
+    next_job: &mut u64,
+    events: &UnboundedSender<ServerMessage>,
+) -> Option<ServerMessage> {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => return Some(ServerMessage::Error { error: format!("Invalid message: {}", e) }),
+    };
+
+    match message {
+        ClientMessage::Job(_) if DRAIN.is_draining() => Some(ServerMessage::Error { error: "Server is shutting down".to_string() }),
+        ClientMessage::Job(req) => {
+            let job_id = req.job_id.clone().unwrap_or_else(|| format!("job-{}", next_job));
+            *next_job += 1;
+            let (challenge, ranges) = match validate_job(req) {
+                Ok(parsed) => parsed,
+                Err(error) => return Some(ServerMessage::Error { error }),
+            };
+            if let Some(previous) = current.take() {
+                previous.cancelled.store(true, Ordering::Relaxed);
+            }
+            let Ok(admitted) = ADMISSION.admit().await else {
+                return Some(ServerMessage::Error { error: "Server is busy, retry the job later".to_string() });
+            };
+
+            info!(
+                "WebSocket job {} started: {} address(es) on ROM {}",
+                job_id,
+                ranges.len(),
+                key_prefix(&challenge.fields.no_pre_mine)
+            );
+            let job = Arc::new(Job {
+                id: job_id.clone(),
+                challenge: Mutex::new(Arc::new(challenge)),
+                cancelled: AtomicBool::new(false),
+                finished: AtomicBool::new(false),
+                hashes: AtomicU64::new(0),
+                started: Instant::now(),
+            });
+            let worker_job = Arc::clone(&job);
+            let worker_events = events.clone();
+            MINING_THREADS.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || {
+                mine(&worker_job, &ranges, &worker_events);
+                drop(admitted);
+                MINING_THREADS.fetch_sub(1, Ordering::SeqCst);
+            });
+            *current = Some(job);
+            Some(ServerMessage::Accepted { job_id })
+        }
+        ClientMessage::Cancel => match current.take() {
+            // The mining thread reports `done` with reason `cancelled`
+            Some(job) => {
+                job.cancelled.store(true, Ordering::Relaxed);
+                None
+            }
+            None => Some(ServerMessage::Error { error: "No job is running".to_string() }),
+        },
+        ClientMessage::UpdateChallenge(fields) => {
+            let Some(job) = current.as_ref().filter(|job| !job.finished.load(Ordering::Relaxed)) else {
+                return Some(ServerMessage::Error { error: "No job is running".to_string() });
+            };
+            match ActiveChallenge::parse(fields) {
+                Ok(challenge) => {
+                    let challenge_id = challenge.fields.challenge_id.clone();
+                    *job.challenge.lock().unwrap() = Arc::new(challenge);
+                    Some(ServerMessage::ChallengeUpdated { job_id: job.id.clone(), challenge_id })
+                }
+                Err(error) => Some(ServerMessage::Error { error }),
+            }
+        }
+    }
+}
+
+fn validate_job(req: JobRequest) -> Result<(ActiveChallenge, Vec<AddressWork>), String> {
+    let challenge = ActiveChallenge::parse(req.challenge)?;
+    if req.addresses.is_empty() {
+        return Err("addresses must not be empty".to_string());
+    }
+    let ranges = req
+        .addresses
+        .into_iter()
+        .map(|a| parse_nonce_range(&a.start_nonce, a.nonce_count).map(|(start, end)| AddressWork { address: a.address, start, end }))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((challenge, ranges))
+}
+
+/// Mine `ranges` for `job` on the rayon pool, sending solutions and the final `done` to
+/// `events`. The challenge and the cancel flag are re-read, and the hashes counted, after
+/// every chunk.
+fn mine(job: &Job, ranges: &[AddressWork], events: &UnboundedSender<ServerMessage>) {
+    let mut solutions = 0;
+    let reason = 'ranges: {
+        for AddressWork { address, start, end } in ranges {
+            let mut next = *start;
+            while next < *end {
+                if job.cancelled.load(Ordering::Relaxed) {
+                    break 'ranges DoneReason::Cancelled;
+                }
+                let challenge = job.challenge();
+                let fields = &challenge.fields;
+                let Some(rom) = ROMS.get_replicas(Some(&fields.no_pre_mine)) else {
+                    break 'ranges DoneReason::RomNotLoaded;
+                };
+
+                let chunk_end = (*end).min(next.saturating_add(CHUNK_NONCES));
+                let _task = METRICS.rayon_task();
+                let chunk_hashes = AtomicU64::new(0);
+                let found = (next..chunk_end)
+                    .into_par_iter()
+                    .map_init(
+                        || {
+                            let preimage = Preimage::new(
+                                address,
+                                &fields.challenge_id,
+                                &fields.difficulty,
+                                &fields.no_pre_mine,
+                                &fields.latest_submission,
+                                &fields.no_pre_mine_hour,
+                            );
+                            (Hasher::default(), preimage, rom.local(), SplitHashes::new(&chunk_hashes))
+                        },
+                        |(hasher, preimage, rom, hashes), nonce| {
+                            preimage.set_nonce(nonce);
+                            hashes.count += 1;
+                            (nonce, hasher.hash(preimage.as_bytes(), rom))
+                        },
+                    )
+                    .find_any(|(_, hash)| challenge.difficulty.accepts(hash));
+                let chunk_hashes = chunk_hashes.into_inner();
+                job.hashes.fetch_add(chunk_hashes, Ordering::Relaxed);
+                METRICS.hashes.inc_by(chunk_hashes);
+
+                if let Some((nonce, hash)) = found {
+                    solutions += 1;
+                    let _ = events.send(ServerMessage::Solution {
+                        job_id: job.id.clone(),
+                        address: address.clone(),
+                        challenge_id: fields.challenge_id.clone(),
+                        nonce: format!("{:016x}", nonce),
+                        hash: hex::encode(hash),
+                    });
+                    break;
+                }
+                next = chunk_end;
+            }
+        }
+        DoneReason::Completed
+    };
+
+    job.finished.store(true, Ordering::Relaxed);
+    let hashes = job.hashes.load(Ordering::Relaxed);
+    info!("WebSocket job {} done ({:?}): {} hashes, {} solution(s)", job.id, reason, hashes, solutions);
+    let _ = events.send(ServerMessage::Done { job_id: job.id.clone(), reason, hashes, solutions });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashengine;
+
+    fn job_message(no_pre_mine: &str, difficulty: &str, nonce_count: u64) -> String {
+        serde_json::json!({
+            "type": "job",
+            "job_id": "j1",
+            "challenge_id": "**D07C10",
+            "difficulty": difficulty,
+            "no_pre_mine": no_pre_mine,
+            "latest_submission": "2025-11-01T00:00:00.000Z",
+            "no_pre_mine_hour": "123456",
+            "addresses": [
+                {"address": "addr_test1qq", "start_nonce": "0000000000000000", "nonce_count": nonce_count},
+                {"address": "addr_test1zz", "start_nonce": "0000000000001000", "nonce_count": nonce_count},
+            ],
+        })
+        .to_string()
+    }
+
+    #[actix_web::test]
+    async fn ws_route_upgrades_the_connection() {
+        use actix_web::{test, App};
+        let app = test::init_service(App::new().route("/ws", web::get().to(ws_handler))).await;
+        let req = test::TestRequest::get()
+            .uri("/ws")
+            .insert_header(("upgrade", "websocket"))
+            .insert_header(("connection", "upgrade"))
+            .insert_header(("sec-websocket-version", "13"))
+            .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::SWITCHING_PROTOCOLS);
+
+        // A plain GET is not a WebSocket handshake
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/ws").to_request()).await;
+        assert!(resp.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn job_streams_solutions_then_done() {
+        let _globals = crate::tests::GLOBALS.lock().await;
+        let key = "ws-session-0123456789abcdef";
+        let gen_type = crate::RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let rom = crate::Rom::new(key.as_bytes(), gen_type, 256 * 1024).unwrap();
+        ROMS.insert(key.to_string(), gen_type, Arc::new(rom), false);
+
+        let (events_tx, mut events) = unbounded_channel();
+        let mut current = None;
+        let mut next_job = 1;
+
+        // 4 leading zero bits: both addresses find a solution well within their range
+        let reply = handle_message(&job_message(key, "0fffffff", 4096), &mut current, &mut next_job, &events_tx).await;
+        assert!(matches!(reply, Some(ServerMessage::Accepted { ref job_id }) if job_id == "j1"));
+
+        let mut solutions = Vec::new();
+        loop {
+            match events.recv().await.unwrap() {
+                ServerMessage::Solution { address, nonce, hash, .. } => solutions.push((address, nonce, hash)),
+                ServerMessage::Done { reason, hashes, solutions: count, .. } => {
+                    assert_eq!(reason, DoneReason::Completed);
+                    assert_eq!(count, 2);
+                    assert!(hashes >= 2);
+                    break;
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        let rom = ROMS.get(Some(key)).unwrap();
+        for (address, nonce, hash) in &solutions {
+            let nonce = u64::from_str_radix(nonce, 16).unwrap();
+            let preimage = hashengine::build_preimage(
+                nonce,
+                address,
+                "**D07C10",
+                "0fffffff",
+                key,
+                "2025-11-01T00:00:00.000Z",
+                "123456",
+            );
+            let expected = hashengine::hash(preimage.as_bytes(), &rom, 8, 256).unwrap();
+            assert_eq!(*hash, hex::encode(expected));
+        }
+
+        // An impossible difficulty runs until cancelled; the update is applied mid-job
+        let reply = handle_message(&job_message(key, "00000000", u64::MAX / 4), &mut current, &mut next_job, &events_tx).await;
+        assert!(matches!(reply, Some(ServerMessage::Accepted { .. })));
+        let update = serde_json::json!({
+            "type": "update_challenge",
+            "challenge_id": "**D07C11",
+            "difficulty": "00000000",
+            "no_pre_mine": key,
+            "latest_submission": "2025-11-01T01:00:00.000Z",
+            "no_pre_mine_hour": "123457",
+        })
+        .to_string();
+        let reply = handle_message(&update, &mut current, &mut next_job, &events_tx).await;
+        assert!(matches!(reply, Some(ServerMessage::ChallengeUpdated { ref challenge_id, .. }) if challenge_id == "**D07C11"));
+        assert!(handle_message(r#"{"type":"cancel"}"#, &mut current, &mut next_job, &events_tx).await.is_none());
+        match events.recv().await.unwrap() {
+            ServerMessage::Done { reason, .. } => assert_eq!(reason, DoneReason::Cancelled),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Invalid messages get an error reply and start nothing
+        for bad in [
+            r#"{"type":"cancel"}"#.to_string(),
+            r#"{"type":"mine"}"#.to_string(),
+            job_message(key, "zz", 10),
+            job_message(key, "0fffffff", 0),
+        ] {
+            let reply = handle_message(&bad, &mut current, &mut next_job, &events_tx).await;
+            assert!(matches!(reply, Some(ServerMessage::Error { .. })), "{}", bad);
+        }
+        assert!(current.is_none());
+
+        // A job for a ROM that is not loaded ends straight away
+        handle_message(&job_message("ws-not-loaded", "0fffffff", 10), &mut current, &mut next_job, &events_tx).await;
+        match events.recv().await.unwrap() {
+            ServerMessage::Done { reason, .. } => assert_eq!(reason, DoneReason::RomNotLoaded),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::fmt;
+
+/// Invalid input to the ROM, hashing and difficulty APIs.
+///
+/// Everything a caller can get wrong is reported through this type instead of a panic,
+/// since the release profile aborts on panic and would take the whole server down.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HashEngineError {
+    /// ROM generation parameters that cannot produce a ROM, with the reason
+    RomConfig(String),
+    /// VM parameters below the minimums (nb_loops >= 2, nb_instrs >= 256)
+    HashParams { nb_loops: u32, nb_instrs: u32 },
+    /// Difficulty that is not exactly 8 hex characters
+    Difficulty(String),
+    /// Malformed binary batch frame (see `batch_codec`), with the reason
+    BatchFrame(String),
+}
+
+impl fmt::Display for HashEngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RomConfig(reason) => write!(f, "Invalid ROM config: {}", reason),
+            Self::HashParams { nb_loops, nb_instrs } => write!(
+                f,
+                "Invalid hash parameters: nb_loops {} (minimum 2), nb_instrs {} (minimum 256)",
+                nb_loops, nb_instrs
+            ),
+            Self::Difficulty(difficulty) => {
+                write!(f, "Invalid difficulty: {:?} - must be exactly 8 hex characters", difficulty)
+            }
+            Self::BatchFrame(reason) => write!(f, "Invalid batch frame: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for HashEngineError {}
@@ -1,4 +1,5 @@
-use crate::rom::{RomGenerationType, Rom, RomDigest};
+use crate::error::HashEngineError;
+use crate::rom::{Rom, RomDigest};
 
 
 use cryptoxide::{
@@ -7,11 +8,10 @@ use cryptoxide::{
 };
 
 // ** Consolidated Imports required for scavenge function **
-use std::sync::mpsc::{Sender, channel};
-use std::{sync::Arc, thread, time::SystemTime};
+use std::sync::mpsc::{Sender, RecvTimeoutError, channel};
+use std::{sync::Arc, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 use std::sync::atomic::{AtomicBool, Ordering};
 // use indicatif::{ProgressBar, ProgressStyle};
-use hex;
 // ************************************
 
 
@@ -27,8 +27,13 @@ type Register = u64;
 
 const REGISTER_SIZE: usize = std::mem::size_of::<Register>();
 
+const MIXING_OUT_SIZE: usize = NB_REGS * REGISTER_SIZE * 32;
+
 struct VM {
     program: Program,
+    // Scratch buffers, fully overwritten before each use so they can be reused across hashes
+    mixing_out: Vec<u8>,
+    init_input: Vec<u8>,
     regs: [Register; NB_REGS],
     ip: u32,
     prog_digest: blake2b::Context<512>,
@@ -113,41 +118,56 @@ impl VM {
     /// Create a new VM which is specific to the ROM by using the RomDigest,
     /// but mainly dependent on the salt which is an arbitrary byte content
     pub fn new(rom_digest: &RomDigest, nb_instrs: u32, salt: &[u8]) -> Self {
+        let mut vm = Self::with_buffers(nb_instrs);
+        vm.reset(rom_digest, salt);
+        vm
+    }
+
+    /// Allocate the program and scratch buffers; the VM must be `reset` before use
+    fn with_buffers(nb_instrs: u32) -> Self {
+        Self {
+            program: Program::new(nb_instrs),
+            mixing_out: vec![0; MIXING_OUT_SIZE],
+            init_input: Vec::with_capacity(64 + 512),
+            regs: [0; NB_REGS],
+            prog_digest: Blake2b::<512>::new(),
+            mem_digest: Blake2b::<512>::new(),
+            prog_seed: [0; 64],
+            ip: 0,
+            loop_counter: 0,
+            memory_counter: 0,
+        }
+    }
+
+    /// Re-initialize every piece of VM state from the ROM digest and salt,
+    /// keeping the already allocated buffers
+    fn reset(&mut self, rom_digest: &RomDigest, salt: &[u8]) {
         const DIGEST_INIT_SIZE: usize = 64;
         const REGS_CONTENT_SIZE: usize = REGISTER_SIZE * NB_REGS;
 
         let mut init_buffer = [0; REGS_CONTENT_SIZE + 3 * DIGEST_INIT_SIZE];
 
-        let mut init_buffer_input = rom_digest.0.to_vec();
-        init_buffer_input.extend_from_slice(salt);
-        argon2::hprime(&mut init_buffer, &init_buffer_input);
+        self.init_input.clear();
+        self.init_input.extend_from_slice(&rom_digest.0);
+        self.init_input.extend_from_slice(salt);
+        argon2::hprime(&mut init_buffer, &self.init_input);
 
         let (init_buffer_regs, init_buffer_digests) = init_buffer.split_at(REGS_CONTENT_SIZE);
 
-        let mut regs = [0; NB_REGS];
-        for (reg, reg_bytes) in regs.iter_mut().zip(init_buffer_regs.chunks(REGISTER_SIZE)) {
+        for (reg, reg_bytes) in self.regs.iter_mut().zip(init_buffer_regs.chunks(REGISTER_SIZE)) {
             *reg = u64::from_le_bytes(*<&[u8; 8]>::try_from(reg_bytes).unwrap());
         }
 
         let mut digests = init_buffer_digests.chunks(DIGEST_INIT_SIZE);
-        let prog_digest = Blake2b::<512>::new().update(&digests.next().unwrap());
-        let mem_digest = Blake2b::<512>::new().update(&digests.next().unwrap());
-        let prog_seed = *<&[u8; 64]>::try_from(digests.next().unwrap()).unwrap();
+        self.prog_digest = Blake2b::<512>::new().update(digests.next().unwrap());
+        self.mem_digest = Blake2b::<512>::new().update(digests.next().unwrap());
+        self.prog_seed = *<&[u8; 64]>::try_from(digests.next().unwrap()).unwrap();
 
         assert_eq!(digests.next(), None);
 
-        let program = Program::new(nb_instrs);
-
-        Self {
-            program,
-            regs,
-            prog_digest,
-            mem_digest,
-            prog_seed,
-            ip: 0,
-            loop_counter: 0,
-            memory_counter: 0,
-        }
+        self.ip = 0;
+        self.loop_counter = 0;
+        self.memory_counter = 0;
     }
 
     pub fn step(&mut self, rom: &Rom) {
@@ -178,10 +198,9 @@ impl VM {
             .update(&mem_value)
             .update(&self.loop_counter.to_le_bytes())
             .finalize();
-        let mut mixing_out = vec![0; NB_REGS * REGISTER_SIZE * 32];
-        argon2::hprime(&mut mixing_out, &mixing_value);
+        argon2::hprime(&mut self.mixing_out, &mixing_value);
 
-        for mem_chunks in mixing_out.chunks(NB_REGS * REGISTER_SIZE) {
+        for mem_chunks in self.mixing_out.chunks(NB_REGS * REGISTER_SIZE) {
             for (reg, reg_chunk) in self.regs.iter_mut().zip(mem_chunks.chunks(8)) {
                 *reg ^= u64::from_le_bytes(*<&[u8; 8]>::try_from(reg_chunk).unwrap())
             }
@@ -199,9 +218,11 @@ impl VM {
         self.post_instructions()
     }
 
-    pub fn finalize(self) -> [u8; 64] {
-        let prog_digest = self.prog_digest.finalize();
-        let mem_digest = self.mem_digest.finalize();
+    /// Compute the final hash. Takes `&self` (cloning the digest contexts) so that the
+    /// VM buffers survive for the next `reset`
+    pub fn finalize(&self) -> [u8; 64] {
+        let prog_digest = self.prog_digest.clone().finalize();
+        let mem_digest = self.mem_digest.clone().finalize();
         let mut context = Blake2b::<512>::new()
             .update(&prog_digest)
             .update(&mem_digest)
@@ -285,6 +306,8 @@ fn decode_instruction(instruction: &[u8; INSTR_SIZE]) -> Instruction {
     }
 }
 
+// Div and Mod keep the reference implementation's explicit zero checks (Mod divides too)
+#[allow(clippy::manual_checked_ops)]
 fn execute_one_instruction(vm: &mut VM, rom: &Rom) {
     let prog_chunk = *vm.program.at(vm.ip);
 
@@ -400,14 +423,60 @@ fn execute_one_instruction(vm: &mut VM, rom: &Rom) {
     vm.prog_digest.update_mut(&prog_chunk);
 }
 
-pub fn hash(salt: &[u8], rom: &Rom, nb_loops: u32, nb_instrs: u32) -> [u8; 64] {
-    assert!(nb_loops >= 2);
-    assert!(nb_instrs >= 256);
+pub fn hash(salt: &[u8], rom: &Rom, nb_loops: u32, nb_instrs: u32) -> std::result::Result<[u8; 64], HashEngineError> {
+    check_hash_params(nb_loops, nb_instrs)?;
     let mut vm = VM::new(&rom.digest, nb_instrs, salt);
     for _ in 0..nb_loops {
         vm.execute(rom, nb_instrs);
     }
-    vm.finalize()
+    Ok(vm.finalize())
+}
+
+fn check_hash_params(nb_loops: u32, nb_instrs: u32) -> std::result::Result<(), HashEngineError> {
+    if nb_loops < 2 || nb_instrs < 256 {
+        return Err(HashEngineError::HashParams { nb_loops, nb_instrs });
+    }
+    Ok(())
+}
+
+/// Reusable hashing state for the hot path.
+///
+/// `hash` allocates the program and mixing buffers on every call. A `Hasher` keeps them
+/// for its lifetime (typically one per worker thread) and produces bit-identical output.
+pub struct Hasher {
+    vm: VM,
+    nb_loops: u32,
+    nb_instrs: u32,
+}
+
+impl Hasher {
+    pub fn new(nb_loops: u32, nb_instrs: u32) -> std::result::Result<Self, HashEngineError> {
+        check_hash_params(nb_loops, nb_instrs)?;
+        Ok(Self {
+            vm: VM::with_buffers(nb_instrs),
+            nb_loops,
+            nb_instrs,
+        })
+    }
+
+    pub fn hash(&mut self, salt: &[u8], rom: &Rom) -> [u8; 64] {
+        self.vm.reset(&rom.digest, salt);
+        for _ in 0..self.nb_loops {
+            self.vm.execute(rom, self.nb_instrs);
+        }
+        self.vm.finalize()
+    }
+}
+
+impl Default for Hasher {
+    /// AshMaze parameters (nb_loops=8, nb_instrs=256)
+    fn default() -> Self {
+        Self {
+            vm: VM::with_buffers(256),
+            nb_loops: 8,
+            nb_instrs: 256,
+        }
+    }
 }
 
 pub fn hash_structure_good(hash: &[u8], zero_bits: usize) -> bool {
@@ -436,18 +505,21 @@ pub fn hash_structure_good(hash: &[u8], zero_bits: usize) -> bool {
 // SCAVENGE LOGIC
 // --------------------------------------------------------------------------
 
-pub struct Thread {}
+/// Handle on a spawned `spin` worker
+pub struct Thread {
+    handle: thread::JoinHandle<()>,
+}
 
 // Structure to hold dynamic challenge parameters from the API
 #[derive(Clone)]
 pub struct ChallengeParams {
     pub rom_key: String, // no_pre_mine hex string (used for ROM init)
-    pub difficulty_mask: String, // difficulty hex string (used for submission check)
+    pub difficulty_mask: String, // difficulty hex string as received (used verbatim in the preimage)
     pub address: String, // Registered Cardano address
     pub challenge_id: String,
     pub latest_submission: String,
     pub no_pre_mine_hour: String,
-    pub required_zero_bits: usize, // Derived from difficulty_mask
+    pub difficulty: Difficulty, // Parsed from difficulty_mask
     pub rom: Arc<Rom>,
 }
 
@@ -479,54 +551,118 @@ pub fn build_preimage(
     preimage
 }
 
-// Utility function to convert difficulty mask (e.g., "000FFFFF") to number of required zero bits
-fn difficulty_to_zero_bits(difficulty_hex: &str) -> usize {
-    let difficulty_bytes = hex::decode(difficulty_hex).unwrap();
-    let mut zero_bits = 0;
-    for &byte in difficulty_bytes.iter() {
-        if byte == 0x00 {
-            zero_bits += 8;
-        } else {
-            zero_bits += byte.leading_zeros() as usize;
-            break;
+/// Preimage buffer with the nonce hex at the front, rewritten in place for each nonce.
+/// Produces the same bytes as `build_preimage` without allocating per nonce.
+pub struct Preimage {
+    bytes: Vec<u8>,
+}
+
+impl Preimage {
+    const NONCE_LEN: usize = 16;
+
+    pub fn new(
+        address: &str,
+        challenge_id: &str,
+        difficulty: &str,
+        no_pre_mine: &str,
+        latest_submission: &str,
+        no_pre_mine_hour: &str,
+    ) -> Self {
+        let bytes = build_preimage(0, address, challenge_id, difficulty, no_pre_mine, latest_submission, no_pre_mine_hour)
+            .into_bytes();
+        Self { bytes }
+    }
+
+    /// Overwrite the nonce with its 16 lowercase hex chars (same as `{:016x}`)
+    pub fn set_nonce(&mut self, nonce: u64) {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        for (i, out) in self.bytes[..Self::NONCE_LEN].iter_mut().enumerate() {
+            *out = HEX[((nonce >> (60 - 4 * i)) & 0xf) as usize];
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Challenge difficulty (e.g. "000FFFFF").
+///
+/// The submission server validates solutions with BOTH rules, so `accepts` applies both:
+/// 1. Heist Engine: the hash starts with as many zero bits as the difficulty does
+/// 2. ShadowHarvester: `(hash | mask) == mask` on the first 4 hash bytes (big endian)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Difficulty {
+    mask: u32,
+}
+
+impl Difficulty {
+    pub fn parse(difficulty_hex: &str) -> std::result::Result<Self, HashEngineError> {
+        let invalid = || HashEngineError::Difficulty(difficulty_hex.to_string());
+        if difficulty_hex.len() != 8 || !difficulty_hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
         }
+        let mask = u32::from_str_radix(difficulty_hex, 16).map_err(|_| invalid())?;
+        Ok(Self { mask })
+    }
+
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Number of leading zero bits required by the Heist Engine rule
+    pub fn zero_bits(&self) -> usize {
+        self.mask.leading_zeros() as usize
+    }
+
+    pub fn accepts(&self, hash: &[u8; 64]) -> bool {
+        let prefix = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]);
+        hash_structure_good(hash, self.zero_bits()) && (prefix | self.mask) == self.mask
+    }
+}
+
+impl std::str::FromStr for Difficulty {
+    type Err = HashEngineError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::parse(s)
     }
-    zero_bits
 }
 
 // The worker thread function
 fn spin(params: ChallengeParams, sender: Sender<Result>, stop_signal: Arc<AtomicBool>, start_nonce: u64, step_size: u64) {
     let mut nonce_value = start_nonce;
     const CHUNKS_SIZE: usize = 0xff;
-    const NB_LOOPS: u32 = 8;
-    const NB_INSTRS: u32 = 256;
 
-    let my_address = &params.address;
+    let mut preimage = Preimage::new(
+        &params.address,
+        &params.challenge_id,
+        &params.difficulty_mask,
+        &params.rom_key,
+        &params.latest_submission,
+        &params.no_pre_mine_hour,
+    );
+    let mut hasher = Hasher::default();
+    // Counted separately from the nonce: with a strided nonce the low bits of
+    // nonce_value are not guaranteed to ever wrap to zero
+    let mut hashes_since_report = 0;
 
     while !stop_signal.load(Ordering::Relaxed) {
-        let preimage_string = build_preimage(
-            nonce_value,
-            my_address,
-            &params.challenge_id,
-            &params.difficulty_mask,
-            &params.rom_key,
-            &params.latest_submission,
-            &params.no_pre_mine_hour,
-        );
-        let preimage_bytes = preimage_string.as_bytes();
-        let h = hash(preimage_bytes, &params.rom, NB_LOOPS, NB_INSTRS);
+        preimage.set_nonce(nonce_value);
+        let h = hasher.hash(preimage.as_bytes(), &params.rom);
+        hashes_since_report += 1;
 
-        if hash_structure_good(&h, params.required_zero_bits) {
-            if sender.send(Result::Found(nonce_value)).is_ok() {
-                // Sent the found nonce
-            }
+        if params.difficulty.accepts(&h) {
+            let _ = sender.send(Result::Progress(hashes_since_report));
+            let _ = sender.send(Result::Found(nonce_value));
             return;
         }
 
-        if nonce_value & (CHUNKS_SIZE as u64) == 0 {
-            if sender.send(Result::Progress(CHUNKS_SIZE)).is_err() {
-                 return;
+        if hashes_since_report == CHUNKS_SIZE {
+            if sender.send(Result::Progress(hashes_since_report)).is_err() {
+                return;
             }
+            hashes_since_report = 0;
         }
 
         // Increment nonce by the thread step size
@@ -534,4 +670,197 @@ fn spin(params: ChallengeParams, sender: Sender<Result>, stop_signal: Arc<Atomic
     }
 }
 
+/// Snapshot of a running scavenge, reported roughly once per `PROGRESS_INTERVAL`
+#[derive(Clone, Copy, Debug)]
+pub struct ScavengeProgress {
+    pub hashes: u64,
+    pub elapsed: Duration,
+    pub hash_rate: f64, // hashes per second since the scavenge started
+}
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
 // The main orchestration function
+
+/// Mine `params` on `nb_threads` worker threads until a nonce satisfying the
+/// difficulty is found. Returns `None` only if every worker exited without a result.
+pub fn scavenge(params: ChallengeParams, nb_threads: usize) -> Option<u64> {
+    scavenge_with_progress(params, nb_threads, |_| {})
+}
+
+/// Random first nonce of a scavenge. Without an OS random source it falls back to the clock
+/// and process id, which still keeps restarts from redoing the same nonces.
+fn random_base_nonce() -> u64 {
+    let mut base = [0u8; 8];
+    match getrandom::fill(&mut base) {
+        Ok(()) => u64::from_le_bytes(base),
+        Err(e) => {
+            log::warn!("OS random source unavailable, seeding the nonce from the clock: {}", e);
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+            nanos ^ (u64::from(std::process::id()) << 32)
+        }
+    }
+}
+
+/// Same as `scavenge`, calling `on_progress` with the aggregated hash rate while mining.
+///
+/// Each worker starts at `base + thread_index` and strides by `nb_threads`, so the
+/// threads cover disjoint nonces. The base is random so restarts do not redo work.
+pub fn scavenge_with_progress<F>(params: ChallengeParams, nb_threads: usize, mut on_progress: F) -> Option<u64>
+where
+    F: FnMut(ScavengeProgress),
+{
+    let nb_threads = nb_threads.max(1);
+    let stop_signal = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = channel();
+
+    let base_nonce = random_base_nonce();
+
+    let threads: Vec<Thread> = (0..nb_threads as u64)
+        .map(|i| {
+            let params = params.clone();
+            let sender = sender.clone();
+            let stop_signal = Arc::clone(&stop_signal);
+            let start_nonce = base_nonce.wrapping_add(i);
+            let step_size = nb_threads as u64;
+            Thread {
+                handle: thread::spawn(move || spin(params, sender, stop_signal, start_nonce, step_size)),
+            }
+        })
+        .collect();
+    // Only the workers hold senders now, so recv fails once they have all exited
+    drop(sender);
+
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut hashes: u64 = 0;
+    let mut found = None;
+
+    loop {
+        match receiver.recv_timeout(PROGRESS_INTERVAL) {
+            Ok(Result::Progress(n)) => hashes += n as u64,
+            Ok(Result::Found(nonce)) => {
+                found = Some(nonce);
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            let elapsed = start.elapsed();
+            on_progress(ScavengeProgress {
+                hashes,
+                elapsed,
+                hash_rate: hashes as f64 / elapsed.as_secs_f64(),
+            });
+        }
+    }
+
+    stop_signal.store(true, Ordering::Relaxed);
+    for t in threads {
+        let _ = t.handle.join();
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::RomGenerationType;
+
+    #[test]
+    fn scavenge_finds_nonce_meeting_difficulty() {
+        let rom_key = "e8a195800b0fd6a2ba9ee4c8f9a5b8b2".to_string();
+        let rom = Rom::new(
+            rom_key.as_bytes(),
+            RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 },
+            256 * 1024,
+        )
+        .unwrap();
+        let params = ChallengeParams {
+            rom_key,
+            difficulty_mask: "0fffffff".to_string(),
+            address: "addr_test1qq".to_string(),
+            challenge_id: "**D07C10".to_string(),
+            latest_submission: "2025-11-01T00:00:00.000Z".to_string(),
+            no_pre_mine_hour: "123456".to_string(),
+            difficulty: Difficulty::parse("0fffffff").unwrap(),
+            rom: Arc::new(rom),
+        };
+
+        let nonce = scavenge(params.clone(), 4).expect("workers exited without a result");
+
+        let preimage = build_preimage(
+            nonce,
+            &params.address,
+            &params.challenge_id,
+            &params.difficulty_mask,
+            &params.rom_key,
+            &params.latest_submission,
+            &params.no_pre_mine_hour,
+        );
+        assert!(params.difficulty.accepts(&hash(preimage.as_bytes(), &params.rom, 8, 256).unwrap()));
+    }
+
+    #[test]
+    fn hasher_reuse_is_bit_identical_to_hash() {
+        let rom = Rom::new(
+            b"e8a195800b0fd6a2ba9ee4c8f9a5b8b2",
+            RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 },
+            256 * 1024,
+        )
+        .unwrap();
+        // Known answers computed with the original allocating implementation
+        let expected = [
+            "92ead1ba26142748acd1e4067f6d43829758f11c543f74117801015877548ff10556b2bedfa4aef09e6937b94ddf46241964f80d82a43df953afa1da43cadf45",
+            "245cf3aa3b699728eaebab3edd3435b042d0dd6bb2f284d59f983c6632484c9e84759b56cea4d90eb85582d0a1dd36a1b82c99c5ece45a88075b3c1498562691",
+        ];
+
+        let mut hasher = Hasher::default();
+        for _ in 0..2 {
+            assert_eq!(hex::encode(hasher.hash(b"hello", &rom)), expected[0]);
+            assert_eq!(hex::encode(hasher.hash(b"0000000000000000addr_test1qq**D07C10", &rom)), expected[1]);
+        }
+        assert_eq!(hex::encode(hash(b"hello", &rom, 8, 256).unwrap()), expected[0]);
+
+        // Parameters below the VM minimums are an error, not a panic
+        let err = HashEngineError::HashParams { nb_loops: 1, nb_instrs: 256 };
+        assert_eq!(hash(b"hello", &rom, 1, 256), Err(err.clone()));
+        assert_eq!(Hasher::new(1, 256).err(), Some(err));
+        assert!(Hasher::new(8, 255).is_err());
+
+        let mut preimage = Preimage::new("addr_test1qq", "**D07C10", "000FFFFF", "e8a1", "2025-11-01T00:00:00.000Z", "12");
+        for nonce in [0, 1, 0xdead_beef, u64::MAX, 0x0123_4567_89ab_cdef] {
+            preimage.set_nonce(nonce);
+            let expected = build_preimage(nonce, "addr_test1qq", "**D07C10", "000FFFFF", "e8a1", "2025-11-01T00:00:00.000Z", "12");
+            assert_eq!(preimage.as_bytes(), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn difficulty_applies_zero_bits_and_mask() {
+        let difficulty = Difficulty::parse("000FFFFF").unwrap();
+        assert_eq!(difficulty.zero_bits(), 12);
+        assert_eq!(difficulty.mask(), 0x000f_ffff);
+
+        let mut h = [0xffu8; 64];
+        h[..4].copy_from_slice(&[0x00, 0x0a, 0xbc, 0xde]);
+        assert!(difficulty.accepts(&h));
+
+        // Zero bits pass, but the low bit of the second byte falls in a hole of the mask
+        let holey = Difficulty::parse("000EFFFF").unwrap();
+        assert_eq!(holey.zero_bits(), 12);
+        assert!(hash_structure_good(&h, holey.zero_bits()));
+        h[1] = 0x01;
+        assert!(!holey.accepts(&h));
+
+        h[1] = 0x10;
+        assert!(!difficulty.accepts(&h));
+
+        assert_eq!(Difficulty::parse("000FFFF"), Err(HashEngineError::Difficulty("000FFFF".to_string())));
+        assert!(Difficulty::parse("000FFFFG").is_err());
+        assert!(Difficulty::parse("+00FFFFF").is_err());
+    }
+}
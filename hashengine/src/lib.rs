@@ -1,11 +1,23 @@
+// The crate name is kept as-is because build scripts copy libHashEngine_napi.* to index.node
+#![allow(non_snake_case)]
+
 // Import HashEngine modules
-mod hashengine;
-mod rom;
+pub mod batch_codec;
+pub mod error;
+pub mod hashengine;
+pub mod numa;
+pub mod rom;
+pub mod rom_cache;
+pub mod rom_checkpoint;
+pub mod rom_shm;
+pub mod rom_storage;
+pub mod stratum;
 
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
 use std::sync::Mutex;
 
+use error::HashEngineError;
 use hashengine::hash as sh_hash;
 use rom::{RomGenerationType, Rom};
 
@@ -19,6 +31,14 @@ static ROM_READY: Mutex<bool> = Mutex::new(false);
 /// CRITICAL: no_pre_mine_hex is the hex string AS-IS (e.g., "e8a195800b...")
 /// We pass it to ROM as UTF-8 bytes, NOT decoded hex bytes
 /// This matches HashEngine/src/lib.rs:384 which uses no_pre_mine_key.as_bytes()
+///
+/// `nb_loops` and `nb_instrs` are accepted for API compatibility but ignored: hashing
+/// always uses the AshMaze values of 8 loops and 256 instructions (see `hash_preimage`)
+///
+/// `generation` is "TwoStep" (default, the AshMaze spec) or "FullRandom"
+///
+/// With ROM_SHARED_MEMORY=1 the ROM is shared with other processes through a POSIX
+/// shared-memory segment (see `rom_shm`) instead of generated privately
 #[napi]
 pub fn init_rom(
   no_pre_mine_hex: String,
@@ -27,20 +47,32 @@ pub fn init_rom(
   pre_size: u32,
   rom_size: u32,
   mixing_numbers: u32,
+  generation: Option<String>,
 ) -> Result<()> {
   // CRITICAL: Convert hex STRING to bytes (not decode hex!)
   // This matches HashEngine reference: no_pre_mine_key.as_bytes()
   let no_pre_mine = no_pre_mine_hex.as_bytes();
+  let _ = (nb_loops, nb_instrs);
 
-  // Create ROM using TwoStep generation (matches AshMaze spec)
-  let rom = Rom::new(
-    no_pre_mine,
-    RomGenerationType::TwoStep {
-      pre_size: pre_size as usize,
-      mixing_numbers: mixing_numbers as usize,
-    },
-    rom_size as usize,
-  );
+  let gen_type = RomGenerationType::from_config(
+    generation.as_deref().unwrap_or("TwoStep"),
+    pre_size as usize,
+    mixing_numbers as usize,
+  )
+  .map_err(to_js_error)?;
+
+  let rom = if rom_shm::enabled_by_env() {
+    gen_type.validate(rom_size as usize).map_err(to_js_error)?;
+    match rom_shm::open_or_publish(no_pre_mine, gen_type, rom_size as usize, rom_shm::DEFAULT_WAIT, None) {
+      Ok((rom, _)) => rom,
+      Err(e) => {
+        log::warn!("Shared-memory ROM unavailable, generating a private copy: {}", e);
+        Rom::new(no_pre_mine, gen_type, rom_size as usize).map_err(to_js_error)?
+      }
+    }
+  } else {
+    Rom::new(no_pre_mine, gen_type, rom_size as usize).map_err(to_js_error)?
+  };
 
   // Store ROM in global state
   let mut rom_state = ROM_STATE.lock().unwrap();
@@ -71,7 +103,7 @@ pub fn hash_preimage(preimage: String) -> Result<String> {
   let salt = preimage.as_bytes();
 
   // Hash using HashEngine (nb_loops=8, nb_instrs=256 per AshMaze spec)
-  let hash_bytes = sh_hash(salt, rom, 8, 256);
+  let hash_bytes = sh_hash(salt, rom, 8, 256).map_err(to_js_error)?;
 
   // Convert to hex string
   Ok(hex::encode(hash_bytes))
@@ -83,3 +115,8 @@ pub fn rom_ready() -> bool {
   let ready = ROM_READY.lock().unwrap();
   *ready
 }
+
+/// Invalid arguments surface in JS as a thrown Error (code InvalidArg)
+fn to_js_error(e: HashEngineError) -> Error {
+  Error::new(Status::InvalidArg, e.to_string())
+}
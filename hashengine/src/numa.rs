@@ -0,0 +1,264 @@
+use crate::rom::Rom;
+use crate::rom_storage::RomBacking;
+
+use std::cell::Cell;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// NUMA topology from sysfs, thread pinning and per-node ROM replicas.
+//
+// On a multi-socket host a single ROM allocation lives on one node, and every `Rom::at`
+// from a thread on another node pays remote-memory latency. Replicas are plain copies,
+// each written by a thread pinned to its node, so the kernel's first-touch policy puts
+// their pages in that node's memory; no libnuma is needed.
+
+const SYSFS_NODES: &str = "/sys/devices/system/node";
+
+thread_local! {
+    static CURRENT_NODE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// A NUMA node and the CPUs it holds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumaNode {
+    /// Kernel node id (the N in /sys/devices/system/node/nodeN)
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// NUMA nodes that have CPUs, ordered by node id
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumaTopology {
+    pub nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// Read the topology from /sys/devices/system/node. Falls back to a single node
+    /// holding every CPU when sysfs is unavailable (or off Linux).
+    pub fn detect() -> Self {
+        match Self::from_sysfs(Path::new(SYSFS_NODES)) {
+            Ok(topology) if !topology.nodes.is_empty() => topology,
+            _ => Self::single_node(),
+        }
+    }
+
+    /// One node with all available CPUs
+    pub fn single_node() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self { nodes: vec![NumaNode { id: 0, cpus: (0..cpus).collect() }] }
+    }
+
+    /// Read `nodeN/cpulist` for every node under `dir`, skipping memory-only nodes
+    pub fn from_sysfs(dir: &Path) -> io::Result<Self> {
+        let mut nodes = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(id) = name.to_str().and_then(|n| n.strip_prefix("node")).and_then(|n| n.parse().ok()) else {
+                continue;
+            };
+            let cpulist = std::fs::read_to_string(entry.path().join("cpulist"))?;
+            let cpus = parse_cpulist(&cpulist)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad cpulist for node{}: {:?}", id, cpulist)))?;
+            if !cpus.is_empty() {
+                nodes.push(NumaNode { id, cpus });
+            }
+        }
+        nodes.sort_by_key(|n| n.id);
+        Ok(Self { nodes })
+    }
+
+    pub fn is_multi_node(&self) -> bool {
+        self.nodes.len() > 1
+    }
+
+    /// Node index (into `nodes`) for the `i`-th worker thread: threads are spread over
+    /// the CPUs in order, so each node gets as many threads as it has CPUs
+    pub fn node_for_thread(&self, i: usize) -> usize {
+        let total: usize = self.nodes.iter().map(|n| n.cpus.len()).sum();
+        let mut slot = i % total.max(1);
+        for (index, node) in self.nodes.iter().enumerate() {
+            if slot < node.cpus.len() {
+                return index;
+            }
+            slot -= node.cpus.len();
+        }
+        0
+    }
+
+    /// Restrict the calling thread to the CPUs of node `index` and remember the node for
+    /// `RomReplicas::local`
+    pub fn pin_current_thread(&self, index: usize) -> io::Result<()> {
+        set_affinity(&self.nodes[index].cpus)?;
+        CURRENT_NODE.with(|n| n.set(Some(index)));
+        Ok(())
+    }
+}
+
+/// Node index the calling thread was pinned to with `NumaTopology::pin_current_thread`
+pub fn current_node() -> Option<usize> {
+    CURRENT_NODE.with(|n| n.get())
+}
+
+/// Parse a sysfs CPU list such as "0-3,8-11" (empty for a node without CPUs)
+pub fn parse_cpulist(list: &str) -> Option<Vec<usize>> {
+    let list = list.trim();
+    let mut cpus = Vec::new();
+    if list.is_empty() {
+        return Some(cpus);
+    }
+    for range in list.split(',') {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let (first, last): (usize, usize) = (first.parse().ok()?, last.parse().ok()?);
+                if first > last {
+                    return None;
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus.iter().filter(|&&cpu| cpu < libc::CPU_SETSIZE as usize) {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "thread pinning is only supported on Linux"))
+}
+
+/// A ROM and its per-node copies. Hashing threads read the copy for their node through
+/// `local`; everything else (digest checks aside) uses `primary`.
+#[derive(Clone)]
+pub struct RomReplicas {
+    // One per node in topology order, or just the ROM when not replicated
+    roms: Vec<Arc<Rom>>,
+}
+
+impl From<Arc<Rom>> for RomReplicas {
+    fn from(rom: Arc<Rom>) -> Self {
+        Self { roms: vec![rom] }
+    }
+}
+
+impl RomReplicas {
+    /// Copy `rom` into the memory of every node of `topology`, one pinned thread per node.
+    /// On a single-node topology the ROM is used as is.
+    pub fn replicate(rom: Arc<Rom>, topology: &NumaTopology, backing: RomBacking) -> Self {
+        if !topology.is_multi_node() {
+            return rom.into();
+        }
+        let roms = std::thread::scope(|scope| {
+            let copies: Vec<_> = (0..topology.nodes.len())
+                .map(|index| {
+                    let rom = &rom;
+                    scope.spawn(move || {
+                        if let Err(e) = topology.pin_current_thread(index) {
+                            log::warn!("Could not pin to NUMA node {}, replica may be remote: {}", topology.nodes[index].id, e);
+                        }
+                        Arc::new(rom.replicate(backing))
+                    })
+                })
+                .collect();
+            copies.into_iter().map(|c| c.join().expect("ROM replica thread panicked")).collect()
+        });
+        Self { roms }
+    }
+
+    /// The ROM of record: the first copy
+    pub fn primary(&self) -> &Arc<Rom> {
+        &self.roms[0]
+    }
+
+    /// The copy for the calling thread's node, or the primary on an unpinned thread
+    pub fn local(&self) -> &Rom {
+        let index = current_node().unwrap_or(0);
+        self.roms.get(index).unwrap_or(&self.roms[0])
+    }
+
+    /// Number of copies held (1 when not replicated)
+    pub fn copies(&self) -> usize {
+        self.roms.len()
+    }
+
+    /// Size of one copy in bytes
+    pub fn size(&self) -> usize {
+        self.roms[0].size()
+    }
+
+    /// Check every copy against the digest (see `Rom::verify`)
+    pub fn verify(&self) -> bool {
+        self.roms.iter().all(|rom| rom.verify())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::RomGenerationType;
+
+    #[test]
+    fn topology_is_read_from_sysfs() {
+        assert_eq!(parse_cpulist("0-3,8,10-11\n"), Some(vec![0, 1, 2, 3, 8, 10, 11]));
+        assert_eq!(parse_cpulist("\n"), Some(vec![]));
+        assert_eq!(parse_cpulist("3-1"), None);
+
+        let dir = std::env::temp_dir().join(format!("hashengine-numa-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (node, cpulist) in [("node1", "2-3\n"), ("node0", "0-1\n"), ("node2", "\n")] {
+            std::fs::create_dir_all(dir.join(node)).unwrap();
+            std::fs::write(dir.join(node).join("cpulist"), cpulist).unwrap();
+        }
+        std::fs::write(dir.join("online"), "0-2\n").unwrap();
+
+        // node2 has memory only and is skipped
+        let topology = NumaTopology::from_sysfs(&dir).unwrap();
+        assert_eq!(
+            topology.nodes,
+            vec![NumaNode { id: 0, cpus: vec![0, 1] }, NumaNode { id: 1, cpus: vec![2, 3] }]
+        );
+        let nodes: Vec<usize> = (0..6).map(|i| topology.node_for_thread(i)).collect();
+        assert_eq!(nodes, vec![0, 0, 1, 1, 0, 0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pinned_threads_read_their_node_replica() {
+        // Two "nodes" sharing the first CPU, so pinning works on any machine
+        let cpus = vec![0];
+        let topology = NumaTopology {
+            nodes: vec![NumaNode { id: 0, cpus: cpus.clone() }, NumaNode { id: 1, cpus }],
+        };
+        let rom = Arc::new(Rom::new(b"numa", RomGenerationType::FullRandom, 64 * 1024).unwrap());
+        let replicas = RomReplicas::replicate(Arc::clone(&rom), &topology, RomBacking::Heap);
+        assert_eq!(replicas.copies(), 2);
+        assert!(replicas.verify());
+        assert!(!Arc::ptr_eq(replicas.primary(), &rom));
+        assert!(replicas.primary().data() == rom.data());
+
+        // Unpinned threads use the primary
+        assert!(std::ptr::eq(replicas.local(), &**replicas.primary()));
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                topology.pin_current_thread(1).unwrap();
+                assert!(std::ptr::eq(replicas.local(), &*replicas.roms[1]));
+            });
+        });
+
+        let single = RomReplicas::replicate(Arc::clone(&rom), &NumaTopology::single_node(), RomBacking::Heap);
+        assert!(single.copies() == 1 && Arc::ptr_eq(single.primary(), &rom));
+    }
+}
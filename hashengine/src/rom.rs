@@ -3,7 +3,13 @@ use cryptoxide::{
     kdf::argon2,
 };
 
+use crate::error::HashEngineError;
+use crate::rom_storage::{RomBacking, RomBackingKind, RomStorage};
+
+use rayon::prelude::*;
 use std::{fmt, convert::TryInto};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 // function to help debug bytestrings
 pub fn print_hex(name: &str, data: &[u8]) {
@@ -16,6 +22,20 @@ pub fn print_hex(name: &str, data: &[u8]) {
 
 pub const DATASET_ACCESS_SIZE: usize = 64;
 
+// Number of ROM chunks mixed per rayon task (256 KiB of output)
+const MIXING_BLOCK_CHUNKS: usize = 4096;
+
+// Largest rom_size or pre_size accepted, ROM_MAX_SIZE_MB (default 2048), so an oversized
+// request fails validation instead of allocating until the process aborts
+static MAX_ROM_SIZE: once_cell::sync::Lazy<usize> = once_cell::sync::Lazy::new(|| {
+    let mb = std::env::var("ROM_MAX_SIZE_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(2048);
+    mb.max(1).saturating_mul(1024 * 1024)
+});
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct RomDigest(pub [u8; 64]);
 impl fmt::Display for RomDigest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -30,11 +50,11 @@ impl fmt::Display for RomDigest {
 /// The **R**ead **O**only **M**emory used to generate the proram.
 pub struct Rom {
     pub digest: RomDigest,
-    data: Vec<u8>,
+    data: RomStorage,
 }
 
 /// The generation type of the **ROM**.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RomGenerationType {
     FullRandom,
     TwoStep {
@@ -43,10 +63,92 @@ pub enum RomGenerationType {
     },
 }
 
-// --- DEBUG STRUCT ---
+impl RomGenerationType {
+    /// Build a generation type from its name ("TwoStep" or "FullRandom", case and
+    /// underscores ignored) and the TwoStep parameters, which FullRandom ignores.
+    pub fn from_config(name: &str, pre_size: usize, mixing_numbers: usize) -> Result<Self, HashEngineError> {
+        match name.to_ascii_lowercase().replace('_', "").as_str() {
+            "twostep" => Ok(Self::TwoStep { pre_size, mixing_numbers }),
+            "fullrandom" => Ok(Self::FullRandom),
+            _ => Err(HashEngineError::RomConfig(format!("unknown generation type {:?} (expected TwoStep or FullRandom)", name))),
+        }
+    }
+
+    /// Check that a ROM of `size` bytes can be generated with these parameters,
+    /// instead of panicking halfway through generation or running out of memory.
+    /// Neither `size` nor `pre_size` may exceed ROM_MAX_SIZE_MB.
+    pub fn validate(&self, size: usize) -> Result<(), HashEngineError> {
+        if size == 0 || !size.is_multiple_of(DATASET_ACCESS_SIZE) {
+            return Err(HashEngineError::RomConfig(format!(
+                "rom_size must be a non-zero multiple of {}, got {}",
+                DATASET_ACCESS_SIZE, size
+            )));
+        }
+        if size > *MAX_ROM_SIZE {
+            return Err(HashEngineError::RomConfig(format!(
+                "rom_size {} exceeds the limit of {} (ROM_MAX_SIZE_MB)",
+                size, *MAX_ROM_SIZE
+            )));
+        }
+        if let Self::TwoStep { pre_size, mixing_numbers } = *self {
+            if !pre_size.is_power_of_two() || pre_size < DATASET_ACCESS_SIZE {
+                return Err(HashEngineError::RomConfig(format!(
+                    "pre_size must be a power of two of at least {}, got {}",
+                    DATASET_ACCESS_SIZE, pre_size
+                )));
+            }
+            if pre_size > *MAX_ROM_SIZE {
+                return Err(HashEngineError::RomConfig(format!(
+                    "pre_size {} exceeds the limit of {} (ROM_MAX_SIZE_MB)",
+                    pre_size, *MAX_ROM_SIZE
+                )));
+            }
+            if mixing_numbers == 0 {
+                return Err(HashEngineError::RomConfig("mixing_numbers must be at least 1".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Phase of ROM generation, in order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomPhase {
+    /// hprime of the TwoStep mixing buffer (pre_size bytes)
+    MixingBuffer,
+    /// offsets_diff and the per-chunk offsets
+    Offsets,
+    /// Filling the ROM chunks. For FullRandom this is a single hprime over the whole ROM
+    Mixing,
+    /// blake2b digest over the ROM data
+    Digest,
+}
+
+/// ROM generation progress: `done` out of `total` units of the current phase.
+/// Mixing counts 64-byte chunks; the other phases are a single unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RomProgress {
+    pub phase: RomPhase,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Progress callback, called from the generating threads
+pub type RomProgressFn<'a> = &'a (dyn Fn(RomProgress) + Sync);
 
-/// State required to generate the next chunk index and perform XOR mixing.
+// --- MIXING STATE ---
+
+/// State of a TwoStep ROM build: everything needed to generate the chunks from
+/// `current_chunk_index` on, plus the running digest of the chunks generated so far.
+///
+/// `step_debug` advances one chunk, `advance` many in parallel. The state serializes with
+/// `write_to`/`read_from`, which `rom_checkpoint` uses to resume an interrupted build. Only
+/// the digest of the chunks generated so far is serialized: `restore_digest` re-derives the
+/// running digest from those chunks and checks it, so a checkpoint only needs to be taken
+/// at a chunk boundary and cannot resume from corrupted chunks.
 pub struct RomMixingState {
+    /// blake2b-256 of the ROM size and key; identifies the ROM being built
+    pub seed: [u8; 32],
     pub mixing_buffer: Vec<u8>,
     pub offsets_bs: Vec<u8>,
     pub offsets_diff: Vec<u16>,
@@ -57,6 +159,180 @@ pub struct RomMixingState {
     pub steps_taken: usize,
     pub max_steps: usize,
     pub digest_ctx: blake2b::Context<512>,
+    /// Digest of the generated chunks saved with a state `read_from` a checkpoint
+    saved_digest: Option<[u8; 64]>,
+}
+
+// Serialized layout (integers little endian):
+//   magic "HEMIX\0\0\x03", seed (32), nb_source_chunks (u32), mixing_numbers, total_chunks,
+//   current_chunk_index, steps_taken, max_steps (u64 each), blake2b-512 of the generated
+//   chunks (64), then mixing_buffer, offsets_bs and offsets_diff (u16s), each prefixed by its u64 length
+const MIXING_STATE_MAGIC: &[u8; 8] = b"HEMIX\0\0\x03";
+
+impl RomMixingState {
+    pub fn is_complete(&self) -> bool {
+        self.current_chunk_index >= self.total_chunks
+    }
+
+    /// Generate up to `max_chunks` chunks from `current_chunk_index` into `rom_data` (the
+    /// whole ROM buffer), in parallel, and feed them to the digest in order.
+    /// Returns the number of chunks generated.
+    pub fn advance(&mut self, rom_data: &mut [u8], max_chunks: usize) -> usize {
+        self.advance_with_progress(rom_data, max_chunks, None)
+    }
+
+    /// Same as `advance`, reporting `Mixing` progress per rayon block like `Rom::with_progress`
+    pub(crate) fn advance_with_progress(&mut self, rom_data: &mut [u8], max_chunks: usize, progress: Option<RomProgressFn>) -> usize {
+        assert_eq!(rom_data.len(), self.total_chunks * DATASET_ACCESS_SIZE);
+        let start = self.current_chunk_index;
+        let end = (start + max_chunks).min(self.total_chunks);
+        let segment = &mut rom_data[start * DATASET_ACCESS_SIZE..end * DATASET_ACCESS_SIZE];
+        let chunks_done = AtomicUsize::new(start);
+
+        segment
+            .par_chunks_mut(DATASET_ACCESS_SIZE * MIXING_BLOCK_CHUNKS)
+            .enumerate()
+            .for_each(|(block, block_out)| {
+                let first_chunk = start + block * MIXING_BLOCK_CHUNKS;
+                for (j, chunk) in block_out.chunks_mut(DATASET_ACCESS_SIZE).enumerate() {
+                    mix_chunk(
+                        first_chunk + j,
+                        chunk,
+                        &self.mixing_buffer,
+                        &self.offsets_bs,
+                        &self.offsets_diff,
+                        self.nb_source_chunks,
+                        self.mixing_numbers,
+                    );
+                }
+                if let Some(progress) = progress {
+                    let block_chunks = block_out.len() / DATASET_ACCESS_SIZE;
+                    let done = chunks_done.fetch_add(block_chunks, Ordering::Relaxed) + block_chunks;
+                    progress(RomProgress { phase: RomPhase::Mixing, done, total: self.total_chunks });
+                }
+            });
+        self.digest_ctx.update_mut(segment);
+
+        self.current_chunk_index = end;
+        self.steps_taken += end - start;
+        end - start
+    }
+
+    /// Restart the running digest from `generated`, the first `current_chunk_index` chunks
+    /// of the ROM, after `read_from`. Chunks that do not match the digest saved with the
+    /// state are an `InvalidData` error.
+    pub fn restore_digest(&mut self, generated: &[u8]) -> io::Result<()> {
+        assert_eq!(generated.len(), self.current_chunk_index * DATASET_ACCESS_SIZE);
+        let digest_ctx = blake2b::Context::<512>::new().update(generated);
+        if let Some(saved) = self.saved_digest {
+            if digest_ctx.clone().finalize().as_slice() != saved.as_slice() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "generated chunks do not match the saved digest"));
+            }
+        }
+        self.digest_ctx = digest_ctx;
+        Ok(())
+    }
+
+    /// Turn a completed build into a ROM; `data` must hold every generated chunk
+    pub(crate) fn finish(self, data: RomStorage) -> Rom {
+        assert!(self.is_complete(), "ROM build finished at chunk {} of {}", self.current_chunk_index, self.total_chunks);
+        let digest = self.digest_ctx.finalize();
+        Rom::from_parts(RomDigest(digest.as_slice().try_into().unwrap()), data)
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MIXING_STATE_MAGIC)?;
+        out.write_all(&self.seed)?;
+        out.write_all(&self.nb_source_chunks.to_le_bytes())?;
+        for n in [self.mixing_numbers, self.total_chunks, self.current_chunk_index, self.steps_taken, self.max_steps] {
+            out.write_all(&(n as u64).to_le_bytes())?;
+        }
+        out.write_all(self.digest_ctx.clone().finalize().as_slice())?;
+
+        out.write_all(&(self.mixing_buffer.len() as u64).to_le_bytes())?;
+        out.write_all(&self.mixing_buffer)?;
+        out.write_all(&(self.offsets_bs.len() as u64).to_le_bytes())?;
+        out.write_all(&self.offsets_bs)?;
+        out.write_all(&(self.offsets_diff.len() as u64).to_le_bytes())?;
+        let diff_bytes: Vec<u8> = self.offsets_diff.iter().flat_map(|d| d.to_le_bytes()).collect();
+        out.write_all(&diff_bytes)
+    }
+
+    /// Read a state written by `write_to`. Malformed input is an `InvalidData` error.
+    /// The running digest covers no chunks until `restore_digest` is called.
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MIXING_STATE_MAGIC {
+            return Err(invalid("not a ROM mixing state"));
+        }
+        let mut seed = [0; 32];
+        input.read_exact(&mut seed)?;
+        let mut nb_source_chunks = [0; 4];
+        input.read_exact(&mut nb_source_chunks)?;
+        let nb_source_chunks = u32::from_le_bytes(nb_source_chunks);
+        let mixing_numbers = read_len(input)?;
+        let total_chunks = read_len(input)?;
+        let current_chunk_index = read_len(input)?;
+        let steps_taken = read_len(input)?;
+        let max_steps = read_len(input)?;
+        let mut saved_digest = [0; 64];
+        input.read_exact(&mut saved_digest)?;
+
+        if nb_source_chunks == 0 || current_chunk_index > total_chunks {
+            return Err(invalid("inconsistent ROM mixing state"));
+        }
+        let mixing_buffer = read_vec(input, nb_source_chunks as usize * DATASET_ACCESS_SIZE)?;
+        let offsets_bs = read_vec(input, total_chunks)?;
+        let diff_len = read_len(input)?;
+        if diff_len == 0 || diff_len > 1 << 20 {
+            return Err(invalid("bad offsets_diff length"));
+        }
+        let offsets_diff = read_bytes(input, diff_len * 2)?
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+
+        Ok(Self {
+            seed,
+            mixing_buffer,
+            offsets_bs,
+            offsets_diff,
+            nb_source_chunks,
+            mixing_numbers,
+            total_chunks,
+            current_chunk_index,
+            steps_taken,
+            max_steps,
+            digest_ctx: blake2b::Context::<512>::new(),
+            saved_digest: Some(saved_digest),
+        })
+    }
+}
+
+fn read_len<R: Read>(input: &mut R) -> io::Result<usize> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "length overflow"))
+}
+
+/// Read a length-prefixed byte vector whose length must be `expected`
+fn read_vec<R: Read>(input: &mut R, expected: usize) -> io::Result<Vec<u8>> {
+    if read_len(input)? != expected {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected field length"));
+    }
+    read_bytes(input, expected)
+}
+
+fn read_bytes<R: Read>(input: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    data.try_reserve_exact(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "field too large"))?;
+    data.resize(len, 0);
+    input.read_exact(&mut data)?;
+    Ok(data)
 }
 
 // --- CORE UTILITY FUNCTIONS ---
@@ -92,19 +368,80 @@ pub fn digest_to_u16s(digest: &[u8; 64]) -> impl Iterator<Item = u16> + '_ {
 // --- ROM IMPLEMENTATION ---
 
 impl Rom {
-    pub fn new(key: &[u8], gen_type: RomGenerationType, size: usize) -> Self {
-        let mut data = vec![0; size];
-        let size_bytes = (data.len() as u32).to_le_bytes();
+    /// Generate a ROM. Fails if `gen_type` cannot produce a ROM of `size` bytes
+    /// (see `RomGenerationType::validate`).
+    pub fn new(key: &[u8], gen_type: RomGenerationType, size: usize) -> Result<Self, HashEngineError> {
+        Self::with_backing(key, gen_type, size, RomBacking::Heap)
+    }
 
-        let seed = blake2b::Context::<256>::new()
-            .update(&size_bytes)
-            .update(key)
-            .finalize();
+    /// Same as `new`, allocating the ROM data with the requested backing
+    /// (see `backing()` for what was actually used)
+    pub fn with_backing(key: &[u8], gen_type: RomGenerationType, size: usize, backing: RomBacking) -> Result<Self, HashEngineError> {
+        Self::generate(key, gen_type, size, backing, None)
+    }
+
+    /// Same as `with_backing`, reporting generation progress to `progress`
+    pub fn with_progress(
+        key: &[u8],
+        gen_type: RomGenerationType,
+        size: usize,
+        backing: RomBacking,
+        progress: RomProgressFn,
+    ) -> Result<Self, HashEngineError> {
+        Self::generate(key, gen_type, size, backing, Some(progress))
+    }
 
-        let digest = random_gen(gen_type, seed.try_into().unwrap(), &mut data);
+    pub(crate) fn generate(
+        key: &[u8],
+        gen_type: RomGenerationType,
+        size: usize,
+        backing: RomBacking,
+        progress: Option<RomProgressFn>,
+    ) -> Result<Self, HashEngineError> {
+        gen_type.validate(size)?;
+        let mut data = RomStorage::allocate(size, backing);
+        let digest = generate_into(key, gen_type, &mut data, progress);
+        Ok(Self { digest, data })
+    }
+
+    /// Rebuild a ROM from previously generated data (see `rom_cache`).
+    /// The caller is responsible for checking that `digest` matches `data`.
+    pub(crate) fn from_parts(digest: RomDigest, data: RomStorage) -> Self {
         Self { digest, data }
     }
 
+    /// Copy of this ROM in a new allocation with the requested backing. The new pages are
+    /// placed on the NUMA node of the calling thread (first touch), see `numa::RomReplicas`.
+    pub fn replicate(&self, backing: RomBacking) -> Self {
+        let mut data = RomStorage::allocate(self.size(), backing);
+        data.copy_from_slice(&self.data);
+        Self { digest: self.digest, data }
+    }
+
+    /// Size of the ROM data in bytes
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn backing(&self) -> RomBackingKind {
+        self.data.kind()
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Recompute the digest over the ROM data and compare it with the stored one.
+    /// False means the data changed since generation (e.g. a bit flipped in RAM).
+    pub fn verify(&self) -> bool {
+        Self::compute_digest(&self.data) == self.digest
+    }
+
+    /// Recompute the blake2b digest of the ROM data (both generation types digest the full output)
+    pub(crate) fn compute_digest(data: &[u8]) -> RomDigest {
+        RomDigest(blake2b::Context::<512>::new().update(data).finalize().as_slice().try_into().unwrap())
+    }
+
     pub(crate) fn at(&self, i: u32) -> &[u8; DATASET_ACCESS_SIZE] {
         let start = i as usize % (self.data.len() / DATASET_ACCESS_SIZE);
         <&[u8; DATASET_ACCESS_SIZE]>::try_from(&self.data[start..start + DATASET_ACCESS_SIZE])
@@ -113,14 +450,50 @@ impl Rom {
 }
 
 
-fn random_gen(gen_type: RomGenerationType, seed: [u8; 32], output: &mut [u8]) -> RomDigest {
+/// Fill ROM chunk `i` from the mixing buffer (TwoStep generation)
+#[inline]
+fn mix_chunk(
+    i: usize,
+    chunk: &mut [u8],
+    mixing_buffer: &[u8],
+    offsets: &[u8],
+    offsets_diff: &[u16],
+    nb_source_chunks: u32,
+    mixing_numbers: usize,
+) {
+    let start_idx = offsets[i % offsets.len()] as u32 % nb_source_chunks;
+    let idx0 = (i as u32) % nb_source_chunks;
+    let offset = (idx0 as usize).wrapping_mul(DATASET_ACCESS_SIZE);
+    let input = &mixing_buffer[offset..offset + DATASET_ACCESS_SIZE];
+    chunk.copy_from_slice(input);
+
+    for d in 1..mixing_numbers {
+        let idx = start_idx.wrapping_add(offsets_diff[(d - 1) % offsets_diff.len()] as u32)
+            % nb_source_chunks;
+        let offset = (idx as usize).wrapping_mul(DATASET_ACCESS_SIZE);
+        let input = &mixing_buffer[offset..offset + DATASET_ACCESS_SIZE];
+        xorbuf(chunk, input);
+    }
+}
+
+fn random_gen(gen_type: RomGenerationType, seed: [u8; 32], output: &mut [u8], progress: Option<RomProgressFn>) -> RomDigest {
+    let report = |phase, done, total| {
+        if let Some(progress) = progress {
+            progress(RomProgress { phase, done, total });
+        }
+    };
+
     if let RomGenerationType::TwoStep { pre_size, mixing_numbers } = gen_type {
 
         assert!(pre_size.is_power_of_two());
         let mut mixing_buffer = vec![0; pre_size];
 
         // FIX: The seed used for hprime must be a slice reference, not an array.
+        report(RomPhase::MixingBuffer, 0, 1);
         argon2::hprime(&mut mixing_buffer, &seed);
+        report(RomPhase::MixingBuffer, 1, 1);
+
+        report(RomPhase::Offsets, 0, 1);
 
         const OFFSET_LOOPS: u32 = 4;
 
@@ -146,60 +519,103 @@ fn random_gen(gen_type: RomGenerationType, seed: [u8; 32], output: &mut [u8]) ->
         argon2::hprime(&mut offsets_bytes, &offset_bytes_input);
 
         let offsets = offsets_bytes;
+        report(RomPhase::Offsets, 1, 1);
 
-        let mut digest = blake2b::Context::<512>::new();
         let nb_source_chunks = (pre_size / DATASET_ACCESS_SIZE) as u32;
-
-        for (i, chunk) in output.chunks_mut(DATASET_ACCESS_SIZE).enumerate() {
-
-            let start_idx = offsets[i % offsets.len()] as u32 % nb_source_chunks;
-            let idx0 = (i as u32) % nb_source_chunks;
-            let offset = (idx0 as usize).wrapping_mul(DATASET_ACCESS_SIZE);
-            let input = &mixing_buffer[offset..offset + DATASET_ACCESS_SIZE];
-            chunk.copy_from_slice(input);
-
-            for d in 1..mixing_numbers {
-                let idx = start_idx.wrapping_add(offsets_diff[(d - 1) % offsets_diff.len()] as u32)
-                    % nb_source_chunks;
-                let offset = (idx as usize).wrapping_mul(DATASET_ACCESS_SIZE);
-                let input = &mixing_buffer[offset..offset + DATASET_ACCESS_SIZE];
-                xorbuf(chunk, input);
-            }
-
-            digest.update_mut(chunk);
-        }
-        RomDigest(digest.finalize().as_slice().try_into().unwrap())
+        let total_chunks = output.len() / DATASET_ACCESS_SIZE;
+        let chunks_done = AtomicUsize::new(0);
+        report(RomPhase::Mixing, 0, total_chunks);
+
+        // Each output chunk only depends on the read-only mixing_buffer and offsets,
+        // so chunks are generated in parallel. Only the digest below is order-dependent.
+        output
+            .par_chunks_mut(DATASET_ACCESS_SIZE * MIXING_BLOCK_CHUNKS)
+            .enumerate()
+            .for_each(|(block, block_out)| {
+                let first_chunk = block * MIXING_BLOCK_CHUNKS;
+                for (j, chunk) in block_out.chunks_mut(DATASET_ACCESS_SIZE).enumerate() {
+                    mix_chunk(
+                        first_chunk + j,
+                        chunk,
+                        &mixing_buffer,
+                        &offsets,
+                        &offsets_diff,
+                        nb_source_chunks,
+                        mixing_numbers,
+                    );
+                }
+                if progress.is_some() {
+                    let block_chunks = block_out.len() / DATASET_ACCESS_SIZE;
+                    let done = chunks_done.fetch_add(block_chunks, Ordering::Relaxed) + block_chunks;
+                    report(RomPhase::Mixing, done, total_chunks);
+                }
+            });
+
+        // Hashing the whole buffer at once is equivalent to updating chunk by chunk in order
+        report(RomPhase::Digest, 0, 1);
+        let digest = Rom::compute_digest(output);
+        report(RomPhase::Digest, 1, 1);
+        digest
 
     } else {
+        report(RomPhase::Mixing, 0, 1);
         argon2::hprime(output, &seed);
-        RomDigest(blake2b::Context::<512>::new().update(output).finalize().as_slice().try_into().unwrap())
+        report(RomPhase::Mixing, 1, 1);
+
+        report(RomPhase::Digest, 0, 1);
+        let digest = Rom::compute_digest(output);
+        report(RomPhase::Digest, 1, 1);
+        digest
     }
 }
 
 
-// --- DEBUG FUNCTIONS EXPOSED FOR TESTING ---
+/// Generate the ROM for `key` into `output` (whose length is the ROM size) and return its
+/// digest. The caller validates `gen_type` for that size first.
+pub(crate) fn generate_into(key: &[u8], gen_type: RomGenerationType, output: &mut [u8], progress: Option<RomProgressFn>) -> RomDigest {
+    let seed = rom_seed(key, output.len());
+    random_gen(gen_type, seed, output, progress)
+}
 
-/// Runs setup logic and returns the initial state before the chunk loop starts.
-pub fn new_debug(key: &[u8], gen_type: RomGenerationType, size: usize) -> RomMixingState {
-    // 1. Run V0 seed logic
-    let size_bytes = (size as u32).to_le_bytes();
-    let seed_raw = blake2b::Context::<256>::new()
-        .update(&size_bytes)
+/// Seed of the ROM generation, from the ROM size and key
+pub(crate) fn rom_seed(key: &[u8], size: usize) -> [u8; 32] {
+    blake2b::Context::<256>::new()
+        .update(&(size as u32).to_le_bytes())
         .update(key)
-        .finalize();
+        .finalize()
+}
+
+// --- STEPWISE GENERATION (TESTING AND RESUMABLE BUILDS) ---
 
-    // 2. Extract parameters and run HPrime
+/// Runs setup logic and returns the initial state before the chunk loop starts.
+pub fn new_debug(key: &[u8], gen_type: RomGenerationType, size: usize) -> RomMixingState {
     let (pre_size, mixing_numbers) = match gen_type {
         RomGenerationType::TwoStep { pre_size, mixing_numbers } => (pre_size, mixing_numbers),
         _ => panic!("new_debug only supports TwoStep"),
     };
+    new_mixing_state(key, pre_size, mixing_numbers, size, None)
+}
 
+/// Same as `new_debug` for the TwoStep parameters, reporting the `MixingBuffer` and
+/// `Offsets` phases like `Rom::with_progress`
+pub(crate) fn new_mixing_state(key: &[u8], pre_size: usize, mixing_numbers: usize, size: usize, progress: Option<RomProgressFn>) -> RomMixingState {
+    let report = |phase, done, total| {
+        if let Some(progress) = progress {
+            progress(RomProgress { phase, done, total });
+        }
+    };
+
+    // 1. Run V0 seed logic
+    let seed = rom_seed(key, size);
+
+    // 2. Run HPrime
     let mut mixing_buffer = vec![0; pre_size];
-    let seed: [u8; 32] = seed_raw.try_into().unwrap();
-    let data = vec![0; size];
+    report(RomPhase::MixingBuffer, 0, 1);
     argon2::hprime(&mut mixing_buffer, &seed);
+    report(RomPhase::MixingBuffer, 1, 1);
 
     // 3. Generate offsets_diff
+    report(RomPhase::Offsets, 0, 1);
     const OFFSET_LOOPS: u32 = 4;
     let mut offsets_diff = vec![];
     for i in 0u32..OFFSET_LOOPS {
@@ -212,13 +628,14 @@ pub fn new_debug(key: &[u8], gen_type: RomGenerationType, size: usize) -> RomMix
     }
 
     // 4. Generate offsets_bs
-    let nb_chunks_bytes = data.len() / DATASET_ACCESS_SIZE;
+    let nb_chunks_bytes = size / DATASET_ACCESS_SIZE;
     let mut offsets_bs = vec![0; nb_chunks_bytes];
     let offset_bytes_input = blake2b::Context::<512>::new()
         .update(&seed)
         .update(b"generation offset base")
         .finalize();
     argon2::hprime(&mut offsets_bs, &offset_bytes_input);
+    report(RomPhase::Offsets, 1, 1);
 
     let nb_source_chunks = (pre_size / DATASET_ACCESS_SIZE) as u32;
     let total_chunks = size / DATASET_ACCESS_SIZE;
@@ -226,6 +643,7 @@ pub fn new_debug(key: &[u8], gen_type: RomGenerationType, size: usize) -> RomMix
     let digest_ctx = blake2b::Context::<512>::new();
 
     RomMixingState {
+        seed,
         mixing_buffer,
         offsets_bs,
         offsets_diff,
@@ -236,6 +654,7 @@ pub fn new_debug(key: &[u8], gen_type: RomGenerationType, size: usize) -> RomMix
         steps_taken: 0,
         max_steps: total_chunks,
         digest_ctx,
+        saved_digest: None,
     }
 }
 
@@ -309,7 +728,7 @@ pub fn build_rom_from_state(mut state: RomMixingState, size: usize) -> Rom {
 
     Rom {
         digest: final_digest,
-        data: rom_data_vec,
+        data: rom_data_vec.into(),
     }
 }
 
@@ -331,9 +750,10 @@ mod tests {
                 mixing_numbers: 4,
             },
             SIZE,
-        );
+        )
+        .unwrap();
 
-        for byte in rom.data {
+        for &byte in rom.data.iter() {
             let index = byte as usize;
             distribution[index] += 1;
         }
@@ -355,4 +775,103 @@ mod tests {
                 .all(|&count| count > MIN && count < MAX)
         );
     }
+
+    #[test]
+    fn parallel_generation_matches_sequential() {
+        // Not a multiple of MIXING_BLOCK_CHUNKS, so the last rayon block is partial
+        const SIZE: usize = 3 * MIXING_BLOCK_CHUNKS * DATASET_ACCESS_SIZE + 5 * DATASET_ACCESS_SIZE;
+        let gen_type = RomGenerationType::TwoStep {
+            pre_size: 64 * 1024,
+            mixing_numbers: 4,
+        };
+
+        let parallel = Rom::new(b"password", gen_type, SIZE).unwrap();
+        // step_debug walks the chunks one at a time, feeding the digest in order
+        let sequential = build_rom_from_state(new_debug(b"password", gen_type, SIZE), SIZE);
+
+        assert_eq!(parallel.digest.0, sequential.digest.0);
+        assert!(parallel.data[..] == sequential.data[..]);
+    }
+
+    #[test]
+    fn progress_reports_every_phase_in_order() {
+        use std::sync::Mutex;
+
+        const SIZE: usize = 3 * MIXING_BLOCK_CHUNKS * DATASET_ACCESS_SIZE;
+        let gen_type = RomGenerationType::TwoStep {
+            pre_size: 64 * 1024,
+            mixing_numbers: 4,
+        };
+        let reports = Mutex::new(Vec::new());
+        let rom = Rom::with_progress(b"password", gen_type, SIZE, RomBacking::Heap, &|p| reports.lock().unwrap().push(p)).unwrap();
+        let reports = reports.into_inner().unwrap();
+
+        let phases: Vec<RomPhase> = reports.iter().map(|p| p.phase).collect();
+        assert!(phases.windows(2).all(|w| w[0] as u8 <= w[1] as u8));
+        assert_eq!(phases.first(), Some(&RomPhase::MixingBuffer));
+
+        let mixing: Vec<&RomProgress> = reports.iter().filter(|p| p.phase == RomPhase::Mixing).collect();
+        assert_eq!(mixing.len(), 4); // start + one per rayon block
+        assert!(mixing.iter().all(|p| p.total == SIZE / DATASET_ACCESS_SIZE));
+        assert_eq!(mixing.iter().map(|p| p.done).max(), Some(SIZE / DATASET_ACCESS_SIZE));
+
+        assert_eq!(reports.last(), Some(&RomProgress { phase: RomPhase::Digest, done: 1, total: 1 }));
+        assert_eq!(rom.digest.0, Rom::new(b"password", gen_type, SIZE).unwrap().digest.0);
+    }
+
+    #[test]
+    fn verify_detects_a_flipped_bit() {
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let mut rom = Rom::new(b"password", gen_type, 256 * 1024).unwrap();
+        assert!(rom.verify());
+
+        rom.data[123_456] ^= 0x10;
+        assert!(!rom.verify());
+    }
+
+    #[test]
+    fn generation_type_config_is_validated() {
+        let two_step = RomGenerationType::from_config("TwoStep", 16 * 1024, 4).unwrap();
+        assert_eq!(two_step, RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 });
+        assert_eq!(RomGenerationType::from_config("full_random", 0, 0), Ok(RomGenerationType::FullRandom));
+        assert!(RomGenerationType::from_config("ThreeStep", 16 * 1024, 4).is_err());
+
+        assert!(two_step.validate(256 * 1024).is_ok());
+        assert!(two_step.validate(0).is_err());
+        assert!(two_step.validate(1000).is_err());
+        assert!(RomGenerationType::TwoStep { pre_size: 3000, mixing_numbers: 4 }.validate(256 * 1024).is_err());
+        assert!(RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 0 }.validate(256 * 1024).is_err());
+        assert!(RomGenerationType::FullRandom.validate(64).is_ok());
+
+        // Sizes past ROM_MAX_SIZE_MB are refused before anything is allocated
+        let too_big = *MAX_ROM_SIZE + DATASET_ACCESS_SIZE;
+        assert!(matches!(two_step.validate(too_big), Err(HashEngineError::RomConfig(_))));
+        assert!(RomGenerationType::FullRandom.validate(too_big).is_err());
+        let huge_pre_size = RomGenerationType::TwoStep { pre_size: too_big.next_power_of_two(), mixing_numbers: 4 };
+        assert!(matches!(huge_pre_size.validate(256 * 1024), Err(HashEngineError::RomConfig(_))));
+        assert!(matches!(Rom::new(b"password", two_step, too_big), Err(HashEngineError::RomConfig(_))));
+
+        // Rom::new reports bad parameters instead of panicking in random_gen
+        assert!(matches!(Rom::new(b"password", two_step, 1000), Err(HashEngineError::RomConfig(_))));
+        assert!(Rom::new(b"password", RomGenerationType::TwoStep { pre_size: 3000, mixing_numbers: 4 }, 256 * 1024).is_err());
+    }
+
+    #[test]
+    fn huge_page_backing_matches_heap() {
+        let gen_type = RomGenerationType::TwoStep {
+            pre_size: 64 * 1024,
+            mixing_numbers: 4,
+        };
+        // Not a multiple of the huge page size
+        const SIZE: usize = 3 * 1024 * 1024 + 64;
+
+        let heap = Rom::new(b"password", gen_type, SIZE).unwrap();
+        let huge = Rom::with_backing(b"password", gen_type, SIZE, RomBacking::HugePages).unwrap();
+
+        assert_eq!(heap.backing(), RomBackingKind::Heap);
+        #[cfg(target_os = "linux")]
+        assert_ne!(huge.backing(), RomBackingKind::Heap);
+        assert_eq!(heap.digest.0, huge.digest.0);
+        assert!(heap.data[..] == huge.data[..]);
+    }
 }
@@ -0,0 +1,324 @@
+use crate::rom::{Rom, RomDigest, RomGenerationType};
+use crate::rom_storage::{RomBacking, RomStorage};
+
+use cryptoxide::hashing::blake2b;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// On-disk layout (all integers little endian):
+//   magic          8 bytes  "HEROM\0\0\x01"
+//   gen_type       1 byte   0 = FullRandom, 1 = TwoStep
+//   pre_size       8 bytes  (0 for FullRandom)
+//   mixing_numbers 8 bytes  (0 for FullRandom)
+//   size           8 bytes
+//   key_len        4 bytes
+//   key            key_len bytes
+//   digest         64 bytes (RomDigest)
+//   data           size bytes
+const MAGIC: &[u8; 8] = b"HEROM\0\0\x01";
+const FILE_EXTENSION: &str = "rom";
+const CHECKPOINT_EXTENSION: &str = "ckpt";
+/// Longest ROM key accepted in an entry, so a corrupt header cannot trigger a huge allocation
+const MAX_KEY_LEN: usize = 1024;
+/// A temporary file not written to for this long was left by a crashed or failed `store`
+const STALE_TMP_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Directory of generated ROMs, so a restarted server can skip regeneration.
+///
+/// Entries are keyed by a hash of the ROM key, generation type and size. The stored
+/// `RomDigest` is checked against the data on every load.
+pub struct RomCache {
+    dir: PathBuf,
+    max_entries: usize,
+    max_age: Option<Duration>,
+}
+
+impl RomCache {
+    pub fn new(dir: impl Into<PathBuf>, max_entries: usize, max_age: Option<Duration>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, max_entries, max_age })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the cache entry for these ROM parameters
+    pub fn entry_path(&self, key: &[u8], gen_type: RomGenerationType, size: usize) -> PathBuf {
+        self.dir.join(format!("{}.{}", entry_name(key, gen_type, size), FILE_EXTENSION))
+    }
+
+    /// Path of the build checkpoint (see `rom_checkpoint`) for these ROM parameters
+    pub fn checkpoint_path(&self, key: &[u8], gen_type: RomGenerationType, size: usize) -> PathBuf {
+        self.entry_path(key, gen_type, size).with_extension(CHECKPOINT_EXTENSION)
+    }
+
+    /// Load a cached ROM. Returns `Ok(None)` when there is no usable entry; an entry whose
+    /// header or digest does not match is removed so it gets regenerated.
+    pub fn load(&self, key: &[u8], gen_type: RomGenerationType, size: usize, backing: RomBacking) -> io::Result<Option<Rom>> {
+        let path = self.entry_path(key, gen_type, size);
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        match read_entry(file, key, gen_type, size, backing) {
+            Ok(rom) => {
+                // Refresh the mtime so pruning treats the entry as recently used
+                let _ = File::options().write(true).open(&path).and_then(|f| f.set_modified(SystemTime::now()));
+                Ok(Some(rom))
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData || e.kind() == io::ErrorKind::UnexpectedEof => {
+                fs::remove_file(&path)?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write a ROM to the cache. The file is written under a temporary name and renamed,
+    /// so a crash mid-write never leaves a truncated entry behind.
+    pub fn store(&self, key: &[u8], gen_type: RomGenerationType, rom: &Rom) -> io::Result<PathBuf> {
+        if key.len() > MAX_KEY_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "ROM key too long for the cache"));
+        }
+        let path = self.entry_path(key, gen_type, rom.data().len());
+        let tmp_path = path.with_extension(format!("{}.tmp{}", FILE_EXTENSION, std::process::id()));
+
+        let result = (|| {
+            let mut out = BufWriter::new(File::create(&tmp_path)?);
+            out.write_all(MAGIC)?;
+            out.write_all(&header_params(gen_type, rom.data().len()))?;
+            out.write_all(&(key.len() as u32).to_le_bytes())?;
+            out.write_all(key)?;
+            out.write_all(&rom.digest.0)?;
+            out.write_all(rom.data())?;
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            fs::rename(&tmp_path, &path)
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result.map(|_| path)
+    }
+
+    /// Remove entries older than `max_age`, then the least recently used entries beyond
+    /// `max_entries`. Build checkpoints left behind by abandoned builds are only removed
+    /// by age, and temporary files of interrupted writes once they are `STALE_TMP_AGE`
+    /// old. Returns the number of files removed.
+    pub fn prune(&self) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let modified = entry.metadata()?.modified()?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.contains(&format!(".{}.tmp", FILE_EXTENSION)) {
+                if now.duration_since(modified).unwrap_or_default() > STALE_TMP_AGE {
+                    fs::remove_file(&path)?;
+                    removed += 1;
+                }
+                continue;
+            }
+            if name.ends_with(&format!(".{}", CHECKPOINT_EXTENSION)) || name.ends_with(&format!(".{}.data", CHECKPOINT_EXTENSION)) {
+                let expired = self
+                    .max_age
+                    .is_some_and(|max_age| now.duration_since(modified).unwrap_or_default() > max_age);
+                if expired {
+                    fs::remove_file(&path)?;
+                    removed += 1;
+                }
+                continue;
+            }
+            if path.extension().and_then(|e| e.to_str()) != Some(FILE_EXTENSION) {
+                continue;
+            }
+            entries.push((modified, path));
+        }
+
+        // Most recently used first
+        entries.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+        for (i, (modified, path)) in entries.into_iter().enumerate() {
+            let expired = self
+                .max_age
+                .is_some_and(|max_age| now.duration_since(modified).unwrap_or_default() > max_age);
+            if expired || i >= self.max_entries {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Hex name identifying these ROM parameters, shared by cache entries and `rom_shm` segments
+pub(crate) fn entry_name(key: &[u8], gen_type: RomGenerationType, size: usize) -> String {
+    let mut ctx = blake2b::Context::<256>::new().update(&header_params(gen_type, size));
+    ctx.update_mut(key);
+    hex::encode(&ctx.finalize()[..16])
+}
+
+pub(crate) fn header_params(gen_type: RomGenerationType, size: usize) -> [u8; 25] {
+    let (tag, pre_size, mixing_numbers) = match gen_type {
+        RomGenerationType::FullRandom => (0u8, 0u64, 0u64),
+        RomGenerationType::TwoStep { pre_size, mixing_numbers } => (1, pre_size as u64, mixing_numbers as u64),
+    };
+    let mut out = [0; 25];
+    out[0] = tag;
+    out[1..9].copy_from_slice(&pre_size.to_le_bytes());
+    out[9..17].copy_from_slice(&mixing_numbers.to_le_bytes());
+    out[17..25].copy_from_slice(&(size as u64).to_le_bytes());
+    out
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_entry(file: File, key: &[u8], gen_type: RomGenerationType, size: usize, backing: RomBacking) -> io::Result<Rom> {
+    let mut input = BufReader::new(file);
+
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a ROM cache file"));
+    }
+
+    let mut params = [0; 25];
+    input.read_exact(&mut params)?;
+    if params != header_params(gen_type, size) {
+        return Err(invalid("ROM generation parameters differ"));
+    }
+
+    let mut key_len = [0; 4];
+    input.read_exact(&mut key_len)?;
+    let key_len = u32::from_le_bytes(key_len) as usize;
+    if key_len > MAX_KEY_LEN {
+        return Err(invalid("ROM key too long"));
+    }
+    let mut stored_key = vec![0; key_len];
+    input.read_exact(&mut stored_key)?;
+    if stored_key != key {
+        return Err(invalid("ROM key differs"));
+    }
+
+    let mut digest = [0; 64];
+    input.read_exact(&mut digest)?;
+
+    let mut data = RomStorage::allocate(size, backing);
+    input.read_exact(&mut data)?;
+    if input.read(&mut [0])? != 0 {
+        return Err(invalid("trailing data after ROM"));
+    }
+
+    let digest = RomDigest(digest);
+    if Rom::compute_digest(&data) != digest {
+        return Err(invalid("ROM digest mismatch"));
+    }
+    Ok(Rom::from_parts(digest, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hashengine-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn store_load_and_reject_corruption() {
+        let dir = temp_cache_dir("rom-cache");
+        let cache = RomCache::new(&dir, 2, None).unwrap();
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let size = 64 * 1024;
+        let rom = Rom::new(b"key-a", gen_type, size).unwrap();
+
+        assert!(cache.load(b"key-a", gen_type, size, RomBacking::Heap).unwrap().is_none());
+        let path = cache.store(b"key-a", gen_type, &rom).unwrap();
+
+        let loaded = cache.load(b"key-a", gen_type, size, RomBacking::Heap).unwrap().expect("cached ROM");
+        assert!(loaded.digest == rom.digest);
+        assert!(loaded.data() == rom.data());
+
+        // Other parameters map to other entries
+        assert!(cache.load(b"key-b", gen_type, size, RomBacking::Heap).unwrap().is_none());
+        assert!(cache.load(b"key-a", RomGenerationType::FullRandom, size, RomBacking::Heap).unwrap().is_none());
+
+        // A flipped bit in the data is caught by the digest check and the entry dropped
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        assert!(cache.load(b"key-a", gen_type, size, RomBacking::Heap).unwrap().is_none());
+        assert!(!path.exists());
+
+        // An absurd key length is rejected before anything is allocated for it
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&header_params(gen_type, size));
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, header).unwrap();
+        assert!(cache.load(b"key-a", gen_type, size, RomBacking::Heap).unwrap().is_none());
+        assert!(!path.exists());
+        assert_eq!(cache.store(&[0; MAX_KEY_LEN + 1], gen_type, &rom).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune_keeps_most_recent_entries() {
+        let dir = temp_cache_dir("rom-cache-prune");
+        let cache = RomCache::new(&dir, 2, None).unwrap();
+        let gen_type = RomGenerationType::FullRandom;
+        let size = 4 * 1024;
+
+        let keys: [&[u8]; 3] = [b"oldest", b"middle", b"newest"];
+        for (age, key) in keys.iter().enumerate() {
+            let path = cache.store(key, gen_type, &Rom::new(key, gen_type, size).unwrap()).unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(3600 * (3 - age as u64));
+            File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+        }
+
+        assert_eq!(cache.prune().unwrap(), 1);
+        assert!(!cache.entry_path(b"oldest", gen_type, size).exists());
+        assert!(cache.entry_path(b"newest", gen_type, size).exists());
+
+        // An abandoned build checkpoint only expires by age, like the middle entry
+        let checkpoint = cache.checkpoint_path(b"abandoned", gen_type, size);
+        let checkpoint_data = checkpoint.with_extension("ckpt.data");
+        for path in [&checkpoint, &checkpoint_data] {
+            fs::write(path, b"partial").unwrap();
+            File::options().write(true).open(path).unwrap().set_modified(SystemTime::now() - Duration::from_secs(7200)).unwrap();
+        }
+        assert_eq!(cache.prune().unwrap(), 0);
+
+        // Temporary files of interrupted writes go once nothing has written to them for a while
+        let stale_tmp = cache.entry_path(b"crashed", gen_type, size).with_extension("rom.tmp1");
+        let live_tmp = cache.entry_path(b"writing", gen_type, size).with_extension("rom.tmp2");
+        fs::write(&stale_tmp, b"partial").unwrap();
+        fs::write(&live_tmp, b"partial").unwrap();
+        File::options().write(true).open(&stale_tmp).unwrap().set_modified(SystemTime::now() - STALE_TMP_AGE * 2).unwrap();
+        assert_eq!(cache.prune().unwrap(), 1);
+        assert!(!stale_tmp.exists() && live_tmp.exists());
+        fs::remove_file(&live_tmp).unwrap();
+
+        let cache = RomCache::new(&dir, 10, Some(Duration::from_secs(90 * 60))).unwrap();
+        assert_eq!(cache.prune().unwrap(), 3);
+        assert!(!cache.entry_path(b"middle", gen_type, size).exists());
+        assert!(!checkpoint.exists() && !checkpoint_data.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
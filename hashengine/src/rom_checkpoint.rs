@@ -0,0 +1,236 @@
+use crate::rom::{self, Rom, RomGenerationType, RomMixingState, RomPhase, RomProgress, RomProgressFn, DATASET_ACCESS_SIZE};
+use crate::rom_storage::{RomBacking, RomStorage};
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+// A checkpoint is two files:
+//   <path>       the serialized RomMixingState, replaced atomically at every checkpoint
+//   <path>.data  the ROM chunks generated so far, appended before the state is replaced
+// so the data file always covers at least `current_chunk_index` chunks of the state. The
+// running digest is re-derived from those chunks on resume and checked against the digest
+// saved in the state, so corrupted data is rebuilt rather than resumed.
+
+/// Generate a ROM, saving a checkpoint to `path` every `interval_chunks` chunks and
+/// resuming from an existing checkpoint for the same ROM, so a build interrupted by a
+/// restart does not start over. The checkpoint is removed once the ROM is complete.
+///
+/// Only TwoStep builds are checkpointed; FullRandom is a single hprime pass and is
+/// generated directly. A checkpoint for other parameters, an unreadable one, or one whose
+/// data does not match its saved digest is discarded.
+pub fn generate_resumable(
+    key: &[u8],
+    gen_type: RomGenerationType,
+    size: usize,
+    backing: RomBacking,
+    path: &Path,
+    interval_chunks: usize,
+    progress: Option<RomProgressFn>,
+) -> io::Result<Rom> {
+    gen_type
+        .validate(size)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let RomGenerationType::TwoStep { pre_size, mixing_numbers } = gen_type else {
+        return Rom::generate(key, gen_type, size, backing, progress).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+    };
+    let report = |phase, done, total| {
+        if let Some(progress) = progress {
+            progress(RomProgress { phase, done, total });
+        }
+    };
+
+    let mut data = RomStorage::allocate(size, backing);
+    let resumed = load(path, key, pre_size, mixing_numbers, size, &mut data)?;
+    let mut state = match resumed {
+        Some(state) => {
+            log::info!("Resuming ROM build at chunk {} of {}", state.current_chunk_index, state.total_chunks);
+            state
+        }
+        None => rom::new_mixing_state(key, pre_size, mixing_numbers, size, progress),
+    };
+
+    let data_path = data_path(path);
+    let mut data_file = File::options().create(true).append(true).open(&data_path)?;
+    // Drop chunks appended after the last saved state (interrupted mid-checkpoint)
+    data_file.set_len((state.current_chunk_index * DATASET_ACCESS_SIZE) as u64)?;
+
+    let total_chunks = state.total_chunks;
+    report(RomPhase::Mixing, state.current_chunk_index, total_chunks);
+    while !state.is_complete() {
+        let start = state.current_chunk_index * DATASET_ACCESS_SIZE;
+        state.advance_with_progress(&mut data, interval_chunks.max(1), progress);
+        let end = state.current_chunk_index * DATASET_ACCESS_SIZE;
+
+        if !state.is_complete() {
+            data_file.write_all(&data[start..end])?;
+            data_file.sync_data()?;
+            save_state(path, &state)?;
+        }
+    }
+    drop(data_file);
+
+    // The digest was computed incrementally while mixing
+    report(RomPhase::Digest, 0, 1);
+    let rom = state.finish(data);
+    report(RomPhase::Digest, 1, 1);
+
+    remove(path);
+    Ok(rom)
+}
+
+/// Remove a checkpoint, if any
+pub fn remove(path: &Path) {
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(data_path(path));
+}
+
+fn data_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".data");
+    PathBuf::from(name)
+}
+
+fn save_state(path: &Path, state: &RomMixingState) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(format!(".tmp{}", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let result = (|| {
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        state.write_to(&mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Load a checkpoint for exactly this ROM into `data`. Returns `Ok(None)` (after removing
+/// the files) when there is no usable checkpoint.
+fn load(path: &Path, key: &[u8], pre_size: usize, mixing_numbers: usize, size: usize, data: &mut [u8]) -> io::Result<Option<RomMixingState>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let result = (|| {
+        let mut state = RomMixingState::read_from(&mut BufReader::new(file))?;
+        if state.seed != rom::rom_seed(key, size)
+            || state.total_chunks != size / DATASET_ACCESS_SIZE
+            || state.mixing_buffer.len() != pre_size
+            || state.mixing_numbers != mixing_numbers
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "checkpoint is for another ROM"));
+        }
+        let done = state.current_chunk_index * DATASET_ACCESS_SIZE;
+        File::open(data_path(path))?.read_exact(&mut data[..done])?;
+        state.restore_digest(&data[..done])?;
+        Ok(state)
+    })();
+
+    match result {
+        Ok(state) => Ok(Some(state)),
+        Err(e) if matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof | io::ErrorKind::NotFound) => {
+            log::warn!("Discarding ROM checkpoint {}: {}", path.display(), e);
+            remove(path);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupted_build_resumes_to_identical_rom() {
+        let dir = std::env::temp_dir().join(format!("hashengine-rom-checkpoint-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rom.ckpt");
+
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let size = 256 * 1024;
+        let total_chunks = size / DATASET_ACCESS_SIZE;
+        let expected = Rom::new(b"checkpoint", gen_type, size).unwrap();
+
+        // Simulate a build killed after two checkpoints: state and data written by hand
+        let mut state = rom::new_debug(b"checkpoint", gen_type, size);
+        let mut data = vec![0; size];
+        state.advance(&mut data, 1000);
+        state.advance(&mut data, 1000);
+        let done = state.current_chunk_index * DATASET_ACCESS_SIZE;
+        save_state(&path, &state).unwrap();
+        // Extra chunks past the saved state, as if killed between append and save
+        fs::write(data_path(&path), &data[..done + 640]).unwrap();
+
+        let reports = std::sync::Mutex::new(Vec::new());
+        let rom = generate_resumable(b"checkpoint", gen_type, size, RomBacking::Heap, &path, 1000, Some(&|p| {
+            reports.lock().unwrap().push(p)
+        }))
+        .unwrap();
+        assert!(rom.digest == expected.digest);
+        assert!(rom.data() == expected.data());
+        assert!(!path.exists() && !data_path(&path).exists());
+
+        // Progress picks up where the checkpoint left off
+        let first_mixing = reports.lock().unwrap().iter().find(|p| p.phase == RomPhase::Mixing).copied().unwrap();
+        assert_eq!(first_mixing, RomProgress { phase: RomPhase::Mixing, done: 2000, total: total_chunks });
+
+        // A fresh build reports the same phases and chunk counts as an uncheckpointed one
+        let expected_reports = std::sync::Mutex::new(Vec::new());
+        Rom::with_progress(b"checkpoint", gen_type, size, RomBacking::Heap, &|p| expected_reports.lock().unwrap().push(p)).unwrap();
+        let reports = std::sync::Mutex::new(Vec::new());
+        generate_resumable(b"checkpoint", gen_type, size, RomBacking::Heap, &path, total_chunks, Some(&|p| {
+            reports.lock().unwrap().push(p)
+        }))
+        .unwrap();
+        assert_eq!(reports.into_inner().unwrap(), expected_reports.into_inner().unwrap());
+
+        // A checkpoint for another key is discarded, not resumed
+        save_state(&path, &state).unwrap();
+        fs::write(data_path(&path), &data[..done]).unwrap();
+        let other = generate_resumable(b"other key", gen_type, size, RomBacking::Heap, &path, 1000, None).unwrap();
+        assert!(other.digest == Rom::new(b"other key", gen_type, size).unwrap().digest);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupted_checkpoint_data_is_rebuilt_not_resumed() {
+        let dir = std::env::temp_dir().join(format!("hashengine-rom-checkpoint-corrupt-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rom.ckpt");
+
+        let gen_type = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+        let size = 256 * 1024;
+        let expected = Rom::new(b"checkpoint", gen_type, size).unwrap();
+
+        let mut state = rom::new_debug(b"checkpoint", gen_type, size);
+        let mut data = vec![0; size];
+        state.advance(&mut data, 1000);
+        state.advance(&mut data, 1000);
+        let done = state.current_chunk_index * DATASET_ACCESS_SIZE;
+
+        let mut flipped = data[..done].to_vec();
+        flipped[done / 3] ^= 0x10;
+        let mut padded = data[..done - 640].to_vec();
+        padded.resize(done, 0);
+        for corrupted in [flipped, padded] {
+            save_state(&path, &state).unwrap();
+            fs::write(data_path(&path), &corrupted).unwrap();
+            let rom = generate_resumable(b"checkpoint", gen_type, size, RomBacking::Heap, &path, 1000, None).unwrap();
+            assert!(rom.digest == expected.digest);
+            assert!(rom.data() == expected.data());
+            assert!(!path.exists() && !data_path(&path).exists());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+use crate::rom::{RomGenerationType, RomProgressFn};
+#[cfg(target_os = "linux")]
+use crate::rom::{self, Rom, RomDigest};
+#[cfg(target_os = "linux")]
+use crate::rom_cache::header_params;
+#[cfg(target_os = "linux")]
+use crate::rom_storage::{shm_unlink, RomStorage, SharedRegion};
+
+use std::io;
+use std::time::{Duration, Instant};
+
+#[cfg(not(target_os = "linux"))]
+use crate::rom::Rom;
+#[cfg(target_os = "linux")]
+use std::collections::HashSet;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Segment layout (all integers little endian): a page of header, then the ROM data.
+//   magic          8 bytes  "HESHM\0\0\x01"
+//   state          4 bytes  0 = being generated, 1 = ready (set last)
+//   creator_pid    4 bytes
+//   params         25 bytes gen_type, pre_size, mixing_numbers, size (as in rom_cache)
+//   key_len        4 bytes
+//   key            key_len bytes
+//   digest         64 bytes (RomDigest)
+#[cfg(target_os = "linux")]
+mod layout {
+    pub const MAGIC: &[u8; 8] = b"HESHM\0\0\x01";
+    pub const HEADER_LEN: usize = 4096;
+    pub const STATE: usize = 8;
+    pub const PID: usize = 12;
+    pub const PARAMS: usize = 16;
+    pub const KEY_LEN: usize = PARAMS + 25;
+    pub const KEY: usize = KEY_LEN + 4;
+    pub const MAX_KEY_LEN: usize = HEADER_LEN - KEY - 64;
+    pub const STATE_READY: u32 = 1;
+}
+#[cfg(target_os = "linux")]
+use layout::*;
+
+/// Environment variable enabling shared-memory ROMs for the server and the NAPI module
+pub const ENV_VAR: &str = "ROM_SHARED_MEMORY";
+
+/// How long `open_or_publish` waits by default for another process to finish a ROM
+pub const DEFAULT_WAIT: Duration = Duration::from_secs(15 * 60);
+
+/// Whether `ROM_SHARED_MEMORY` is set to 1/true/yes
+pub fn enabled_by_env() -> bool {
+    std::env::var(ENV_VAR).is_ok_and(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+/// Prefix of every segment name (the file name under /dev/shm without the leading /)
+const NAME_PREFIX: &str = "hashengine-rom-";
+
+/// Name of the shared-memory segment holding the ROM for these parameters
+/// (`/dev/shm/hashengine-rom-<hash>` on Linux)
+pub fn segment_name(key: &[u8], gen_type: RomGenerationType, size: usize) -> String {
+    format!("/{}{}", NAME_PREFIX, crate::rom_cache::entry_name(key, gen_type, size))
+}
+
+/// Attach to the ROM for these parameters if a process has published it, otherwise
+/// generate and publish it. While another process is generating it, wait up to `wait`
+/// for it to finish (TimedOut after that). Returns the ROM and whether it was attached
+/// rather than generated here.
+pub fn open_or_publish(
+    key: &[u8],
+    gen_type: RomGenerationType,
+    size: usize,
+    wait: Duration,
+    progress: Option<RomProgressFn>,
+) -> io::Result<(Rom, bool)> {
+    let name = segment_name(key, gen_type, size);
+    let deadline = Instant::now() + wait;
+    loop {
+        match attach(&name, key, gen_type, size) {
+            Ok(rom) => return Ok((rom, true)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => match publish(&name, key, gen_type, size, progress) {
+                Ok(rom) => return Ok((rom, false)),
+                // Lost the race to create it: attach to the winner's segment
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("shared ROM {} was not ready after {}s", name, wait.as_secs()),
+                    ));
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Attach read-only to the published ROM segment `name`.
+///
+/// The header must match the requested parameters and key (InvalidData otherwise).
+/// WouldBlock while the segment is still being generated; a segment abandoned by a
+/// creator that died mid-build is removed and reported as NotFound. The data is not
+/// re-hashed here: use `Rom::verify` for that.
+#[cfg(target_os = "linux")]
+pub fn attach(name: &str, key: &[u8], gen_type: RomGenerationType, size: usize) -> io::Result<Rom> {
+    let region = SharedRegion::open(&c_name(name)?, HEADER_LEN)?;
+    let header = region.header();
+
+    if &header[..8] != MAGIC {
+        return if header[..8].iter().all(|&b| b == 0) {
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "segment header not written yet"))
+        } else {
+            Err(invalid("not a HashEngine ROM segment"))
+        };
+    }
+
+    // The creator stores the state with Release once the digest is written
+    let state = unsafe { &*header.as_ptr().add(STATE).cast::<AtomicU32>() }.load(Ordering::Acquire);
+    if state != STATE_READY {
+        let pid = u32::from_le_bytes(header[PID..PID + 4].try_into().unwrap());
+        if !process_alive(pid) {
+            log::warn!("Removing shared ROM {} abandoned mid-build by process {}", name, pid);
+            remove(name);
+            return Err(io::Error::new(io::ErrorKind::NotFound, "segment was abandoned by its creator"));
+        }
+        return Err(io::Error::new(io::ErrorKind::WouldBlock, "ROM is still being generated"));
+    }
+
+    if header[PARAMS..KEY_LEN] != header_params(gen_type, size) || region.data().len() != size {
+        return Err(invalid("segment was built with different ROM parameters"));
+    }
+    let key_len = u32::from_le_bytes(header[KEY_LEN..KEY].try_into().unwrap()) as usize;
+    if key_len > MAX_KEY_LEN || &header[KEY..KEY + key_len] != key {
+        return Err(invalid("segment was built for a different key"));
+    }
+    let digest = RomDigest(header[KEY + key_len..KEY + key_len + 64].try_into().unwrap());
+
+    Ok(Rom::from_parts(digest, RomStorage::Shared(region)))
+}
+
+/// Generate a ROM into a new segment `name` and publish it for `attach`.
+/// AlreadyExists if the segment exists (another process got there first).
+#[cfg(target_os = "linux")]
+pub fn publish(
+    name: &str,
+    key: &[u8],
+    gen_type: RomGenerationType,
+    size: usize,
+    progress: Option<RomProgressFn>,
+) -> io::Result<Rom> {
+    gen_type
+        .validate(size)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if key.len() > MAX_KEY_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("ROM key of {} bytes is too long for a shared segment (max {})", key.len(), MAX_KEY_LEN),
+        ));
+    }
+
+    let c_name = c_name(name)?;
+    let mut region = SharedRegion::create(&c_name, HEADER_LEN, size)?;
+
+    let (header, data) = region.parts_mut();
+    header[..8].copy_from_slice(MAGIC);
+    header[PID..PID + 4].copy_from_slice(&std::process::id().to_le_bytes());
+    header[PARAMS..KEY_LEN].copy_from_slice(&header_params(gen_type, size));
+    header[KEY_LEN..KEY].copy_from_slice(&(key.len() as u32).to_le_bytes());
+    header[KEY..KEY + key.len()].copy_from_slice(key);
+
+    let digest = rom::generate_into(key, gen_type, data, progress);
+    header[KEY + key.len()..KEY + key.len() + 64].copy_from_slice(&digest.0);
+    unsafe { &*header.as_mut_ptr().add(STATE).cast::<AtomicU32>() }.store(STATE_READY, Ordering::Release);
+
+    if let Err(e) = region.seal() {
+        let _ = shm_unlink(&c_name);
+        return Err(e);
+    }
+    Ok(Rom::from_parts(digest, RomStorage::Shared(region)))
+}
+
+/// Unlink the segment `name`, if any. Processes attached to it keep their mapping; the
+/// memory is freed once the last one drops its ROM.
+#[cfg(target_os = "linux")]
+pub fn remove(name: &str) {
+    if let Ok(c_name) = c_name(name) {
+        let _ = shm_unlink(&c_name);
+    }
+}
+
+/// Unlink the segment `name` if this process published it, once it no longer needs the
+/// ROM. Segments published by other processes are theirs to remove (or `sweep`'s).
+#[cfg(target_os = "linux")]
+pub fn release(name: &str) {
+    let Ok(c_name) = c_name(name) else {
+        return;
+    };
+    let Ok(region) = SharedRegion::open(&c_name, HEADER_LEN) else {
+        return;
+    };
+    let header = region.header();
+    if &header[..8] == MAGIC && header[PID..PID + 4] == std::process::id().to_le_bytes() {
+        let _ = shm_unlink(&c_name);
+    }
+}
+
+/// Unlink the segments left behind by processes that exited: the creator is gone, no
+/// process has the segment mapped any more, and it was written more than `min_age` ago
+/// (younger ones are kept for a restarted server to attach to). Returns the number removed.
+#[cfg(target_os = "linux")]
+pub fn sweep(min_age: Duration) -> io::Result<usize> {
+    // Before opening any segment, since this process maps the ones it opens
+    let mapped = mapped_segments();
+    let mut removed = 0;
+    for entry in std::fs::read_dir("/dev/shm")? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str().filter(|n| n.starts_with(NAME_PREFIX)) else {
+            continue;
+        };
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if mapped.contains(file_name) || age < min_age {
+            continue;
+        }
+        let name = format!("/{}", file_name);
+        let Ok(region) = SharedRegion::open(&c_name(&name)?, HEADER_LEN) else {
+            continue;
+        };
+        let pid = u32::from_le_bytes(region.header()[PID..PID + 4].try_into().unwrap());
+        drop(region);
+        if !process_alive(pid) {
+            log::info!("Removing shared ROM {} left behind by process {}", name, pid);
+            remove(&name);
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Segment file names mapped by any process we can inspect. Processes of other users are
+/// skipped: their maps are unreadable, and the segments are only writable by their owner.
+#[cfg(target_os = "linux")]
+fn mapped_segments() -> HashSet<String> {
+    let mut mapped = HashSet::new();
+    let Ok(procs) = std::fs::read_dir("/proc") else {
+        return mapped;
+    };
+    for entry in procs.flatten() {
+        if !entry.file_name().to_string_lossy().bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        let Ok(maps) = std::fs::read_to_string(entry.path().join("maps")) else {
+            continue;
+        };
+        // Mappings of unlinked segments show up as "<path> (deleted)"
+        for line in maps.lines().filter(|line| !line.ends_with("(deleted)")) {
+            if let Some((_, file_name)) = line.split_once(&format!("/dev/shm/{}", NAME_PREFIX)) {
+                let file_name = file_name.split_whitespace().next().unwrap_or_default();
+                mapped.insert(format!("{}{}", NAME_PREFIX, file_name));
+            }
+        }
+    }
+    mapped
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn attach(_name: &str, _key: &[u8], _gen_type: RomGenerationType, _size: usize) -> io::Result<Rom> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn publish(
+    _name: &str,
+    _key: &[u8],
+    _gen_type: RomGenerationType,
+    _size: usize,
+    _progress: Option<RomProgressFn>,
+) -> io::Result<Rom> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn remove(_name: &str) {}
+
+#[cfg(not(target_os = "linux"))]
+pub fn release(_name: &str) {}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sweep(_min_age: Duration) -> io::Result<usize> {
+    Ok(0)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "shared-memory ROMs are only supported on Linux")
+}
+
+#[cfg(target_os = "linux")]
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "segment name contains a NUL byte"))
+}
+
+#[cfg(target_os = "linux")]
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A pid of 0 (creator crashed before writing it) counts as alive so the caller times out
+/// instead of deleting a segment that may just have been created
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    pid == 0 || unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    const GEN_TYPE: RomGenerationType = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 4 };
+    const SIZE: usize = 256 * 1024;
+
+    #[test]
+    fn published_rom_is_attached_read_only() {
+        let key = format!("shm-publish-{}", std::process::id());
+        let key = key.as_bytes();
+        let name = segment_name(key, GEN_TYPE, SIZE);
+        remove(&name);
+        let expected = Rom::new(key, GEN_TYPE, SIZE).unwrap();
+
+        let (published, attached) = open_or_publish(key, GEN_TYPE, SIZE, DEFAULT_WAIT, None).unwrap();
+        assert!(!attached);
+        assert!(published.digest == expected.digest);
+        assert_eq!(published.backing(), crate::rom_storage::RomBackingKind::SharedMemory);
+
+        let (shared, attached) = open_or_publish(key, GEN_TYPE, SIZE, DEFAULT_WAIT, None).unwrap();
+        assert!(attached);
+        assert!(shared.digest == expected.digest);
+        assert!(shared.data() == expected.data());
+        assert!(shared.verify());
+
+        // The header check rejects a request for other parameters under the same name
+        let other_params = RomGenerationType::TwoStep { pre_size: 16 * 1024, mixing_numbers: 5 };
+        let err = attach(&name, key, other_params, SIZE).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = attach(&name, b"another key", GEN_TYPE, SIZE).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = publish(&name, key, GEN_TYPE, SIZE, None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        // Unlinking leaves existing mappings intact
+        remove(&name);
+        assert_eq!(attach(&name, key, GEN_TYPE, SIZE).err().unwrap().kind(), io::ErrorKind::NotFound);
+        assert!(shared.verify());
+    }
+
+    #[test]
+    fn segment_abandoned_mid_build_is_replaced() {
+        let key = format!("shm-abandoned-{}", std::process::id());
+        let key = key.as_bytes();
+        let name = segment_name(key, GEN_TYPE, SIZE);
+        remove(&name);
+
+        // A creator that exited before marking its segment ready
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+        let mut region = SharedRegion::create(&c_name(&name).unwrap(), HEADER_LEN, SIZE).unwrap();
+        let (header, _) = region.parts_mut();
+        header[..8].copy_from_slice(MAGIC);
+        header[PID..PID + 4].copy_from_slice(&dead_pid.to_le_bytes());
+
+        let (rom, attached) = open_or_publish(key, GEN_TYPE, SIZE, Duration::from_secs(5), None).unwrap();
+        assert!(!attached);
+        assert!(rom.digest == Rom::new(key, GEN_TYPE, SIZE).unwrap().digest);
+        remove(&name);
+    }
+
+    #[test]
+    fn release_and_sweep_only_remove_unused_segments() {
+        let key = format!("shm-release-{}", std::process::id());
+        let key = key.as_bytes();
+        let name = segment_name(key, GEN_TYPE, SIZE);
+        remove(&name);
+
+        // Published by this process: released
+        let (rom, _) = open_or_publish(key, GEN_TYPE, SIZE, DEFAULT_WAIT, None).unwrap();
+        release(&name);
+        assert_eq!(attach(&name, key, GEN_TYPE, SIZE).err().unwrap().kind(), io::ErrorKind::NotFound);
+        assert!(rom.verify());
+
+        // Published by a process that exited: left alone by release, removed by the sweep
+        // once nothing maps it
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+        let mut region = SharedRegion::create(&c_name(&name).unwrap(), HEADER_LEN, SIZE).unwrap();
+        let (header, _) = region.parts_mut();
+        header[..8].copy_from_slice(MAGIC);
+        header[PID..PID + 4].copy_from_slice(&dead_pid.to_le_bytes());
+        release(&name);
+        sweep(Duration::ZERO).unwrap();
+        assert!(SharedRegion::open(&c_name(&name).unwrap(), HEADER_LEN).is_ok());
+        drop(region);
+        sweep(Duration::from_secs(3600)).unwrap();
+        assert!(SharedRegion::open(&c_name(&name).unwrap(), HEADER_LEN).is_ok());
+        sweep(Duration::ZERO).unwrap();
+        assert_eq!(
+            SharedRegion::open(&c_name(&name).unwrap(), HEADER_LEN).err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use std::ops::{Deref, DerefMut};
+
+/// How the ROM bytes should be allocated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RomBacking {
+    /// Regular heap allocation
+    #[default]
+    Heap,
+    /// Anonymous mmap backed by huge pages, to cut TLB misses on random `Rom::at` reads.
+    /// Uses explicit hugetlb pages when enough are reserved (vm.nr_hugepages), otherwise
+    /// transparent huge pages via madvise. Falls back to `Heap` off Linux or on failure.
+    HugePages,
+}
+
+impl std::str::FromStr for RomBacking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "heap" => Ok(Self::Heap),
+            "hugepages" | "hugepage" | "huge" => Ok(Self::HugePages),
+            other => Err(format!("unknown ROM backing {:?} (expected heap or hugepages)", other)),
+        }
+    }
+}
+
+/// The backing actually in use, which can differ from the requested `RomBacking` after fallback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomBackingKind {
+    Heap,
+    TransparentHugePages,
+    HugeTlb,
+    /// Read-only mapping of a POSIX shared-memory segment (see `rom_shm`)
+    SharedMemory,
+}
+
+impl RomBackingKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Heap => "heap",
+            Self::TransparentHugePages => "mmap-thp",
+            Self::HugeTlb => "mmap-hugetlb",
+            Self::SharedMemory => "shm",
+        }
+    }
+}
+
+/// Zero-initialised byte buffer holding the ROM data.
+pub(crate) enum RomStorage {
+    Heap(Vec<u8>),
+    #[cfg(target_os = "linux")]
+    Mmap(mmap::MmapRegion),
+    #[cfg(target_os = "linux")]
+    Shared(mmap::SharedRegion),
+}
+
+impl RomStorage {
+    pub(crate) fn allocate(size: usize, backing: RomBacking) -> Self {
+        match backing {
+            RomBacking::Heap => Self::Heap(vec![0; size]),
+            #[cfg(target_os = "linux")]
+            RomBacking::HugePages => match mmap::MmapRegion::huge_pages(size) {
+                Some(region) => Self::Mmap(region),
+                None => Self::Heap(vec![0; size]),
+            },
+            #[cfg(not(target_os = "linux"))]
+            RomBacking::HugePages => Self::Heap(vec![0; size]),
+        }
+    }
+
+    pub(crate) fn kind(&self) -> RomBackingKind {
+        match self {
+            Self::Heap(_) => RomBackingKind::Heap,
+            #[cfg(target_os = "linux")]
+            Self::Mmap(region) => region.kind(),
+            #[cfg(target_os = "linux")]
+            Self::Shared(_) => RomBackingKind::SharedMemory,
+        }
+    }
+}
+
+impl From<Vec<u8>> for RomStorage {
+    fn from(data: Vec<u8>) -> Self {
+        Self::Heap(data)
+    }
+}
+
+impl Deref for RomStorage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Heap(data) => data,
+            #[cfg(target_os = "linux")]
+            Self::Mmap(region) => region.as_slice(),
+            #[cfg(target_os = "linux")]
+            Self::Shared(region) => region.data(),
+        }
+    }
+}
+
+impl DerefMut for RomStorage {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Self::Heap(data) => data,
+            #[cfg(target_os = "linux")]
+            Self::Mmap(region) => region.as_mut_slice(),
+            // Sealed before it is wrapped in a RomStorage; a write would fault
+            #[cfg(target_os = "linux")]
+            Self::Shared(_) => panic!("shared-memory ROM data is read-only"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) use mmap::{unlink as shm_unlink, SharedRegion};
+
+#[cfg(target_os = "linux")]
+mod mmap {
+    use super::RomBackingKind;
+    use std::ffi::CStr;
+    use std::io;
+    use std::ptr::NonNull;
+
+    const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+    /// Private anonymous mapping, unmapped on drop
+    pub(crate) struct MmapRegion {
+        ptr: NonNull<u8>,
+        len: usize,
+        mapped_len: usize,
+        kind: RomBackingKind,
+    }
+
+    // The region is plain memory exclusively owned by this value
+    unsafe impl Send for MmapRegion {}
+    unsafe impl Sync for MmapRegion {}
+
+    impl MmapRegion {
+        /// Map `len` zeroed bytes on huge pages: hugetlb first, then THP. None if both fail.
+        pub(crate) fn huge_pages(len: usize) -> Option<Self> {
+            if len == 0 {
+                return None;
+            }
+            let mapped_len = len.div_ceil(HUGE_PAGE_SIZE) * HUGE_PAGE_SIZE;
+
+            if let Some(ptr) = map(mapped_len, libc::MAP_HUGETLB) {
+                return Some(Self { ptr, len, mapped_len, kind: RomBackingKind::HugeTlb });
+            }
+
+            let ptr = map(mapped_len, 0)?;
+            let region = Self { ptr, len, mapped_len, kind: RomBackingKind::TransparentHugePages };
+            // Only advisory: if THP is disabled the mapping still works with 4 KiB pages
+            let advised = unsafe { libc::madvise(ptr.as_ptr().cast(), mapped_len, libc::MADV_HUGEPAGE) };
+            if advised != 0 {
+                log::warn!("madvise(MADV_HUGEPAGE) failed: {}", std::io::Error::last_os_error());
+            }
+            Some(region)
+        }
+
+        pub(crate) fn kind(&self) -> RomBackingKind {
+            self.kind
+        }
+
+        pub(crate) fn as_slice(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+        }
+
+        pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+            unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+        }
+    }
+
+    impl Drop for MmapRegion {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr.as_ptr().cast(), self.mapped_len);
+            }
+        }
+    }
+
+    /// Shared mapping of a named POSIX shared-memory segment: `data_offset` bytes of
+    /// header followed by the data. Unmapped on drop; the segment itself stays until unlinked.
+    pub(crate) struct SharedRegion {
+        ptr: NonNull<u8>,
+        mapped_len: usize,
+        data_offset: usize,
+        writable: bool,
+    }
+
+    // Writes only happen through &mut self before `seal`; other processes only read
+    unsafe impl Send for SharedRegion {}
+    unsafe impl Sync for SharedRegion {}
+
+    impl SharedRegion {
+        /// Create the segment `name` (AlreadyExists if it exists) and map it read-write.
+        /// The segment is zero-filled and unlinked again if mapping fails.
+        pub(crate) fn create(name: &CStr, data_offset: usize, data_len: usize) -> io::Result<Self> {
+            let mapped_len = data_offset + data_len;
+            let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, 0o644) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let result = (|| {
+                if unsafe { libc::ftruncate(fd, mapped_len as libc::off_t) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                map_shared(fd, mapped_len, libc::PROT_READ | libc::PROT_WRITE)
+            })();
+            unsafe { libc::close(fd) };
+
+            match result {
+                Ok(ptr) => Ok(Self { ptr, mapped_len, data_offset, writable: true }),
+                Err(e) => {
+                    let _ = unlink(name);
+                    Err(e)
+                }
+            }
+        }
+
+        /// Map an existing segment read-only. WouldBlock if it is still smaller than its
+        /// header, i.e. its creator has not sized it yet.
+        pub(crate) fn open(name: &CStr, data_offset: usize) -> io::Result<Self> {
+            let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDONLY, 0) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let result = (|| {
+                let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+                if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mapped_len = stat.st_size as usize;
+                if mapped_len <= data_offset {
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, "segment is still being created"));
+                }
+                Ok((map_shared(fd, mapped_len, libc::PROT_READ)?, mapped_len))
+            })();
+            unsafe { libc::close(fd) };
+
+            let (ptr, mapped_len) = result?;
+            Ok(Self { ptr, mapped_len, data_offset, writable: false })
+        }
+
+        pub(crate) fn header(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.data_offset) }
+        }
+
+        pub(crate) fn data(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().add(self.data_offset), self.mapped_len - self.data_offset) }
+        }
+
+        /// Header and data, writable until `seal`
+        pub(crate) fn parts_mut(&mut self) -> (&mut [u8], &mut [u8]) {
+            assert!(self.writable, "shared region is read-only");
+            let all = unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.mapped_len) };
+            all.split_at_mut(self.data_offset)
+        }
+
+        /// Make this mapping read-only, so a stray write faults instead of corrupting the
+        /// ROM every attached process is reading
+        pub(crate) fn seal(&mut self) -> io::Result<()> {
+            if unsafe { libc::mprotect(self.ptr.as_ptr().cast(), self.mapped_len, libc::PROT_READ) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.writable = false;
+            Ok(())
+        }
+    }
+
+    impl Drop for SharedRegion {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr.as_ptr().cast(), self.mapped_len);
+            }
+        }
+    }
+
+    /// Remove a segment name; processes that have it mapped keep their mapping
+    pub(crate) fn unlink(name: &CStr) -> io::Result<()> {
+        if unsafe { libc::shm_unlink(name.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn map_shared(fd: libc::c_int, len: usize, prot: libc::c_int) -> io::Result<NonNull<u8>> {
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, prot, libc::MAP_SHARED, fd, 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        NonNull::new(ptr.cast()).ok_or_else(|| io::Error::other("mmap returned null"))
+    }
+
+    // Without MAP_NORESERVE a hugetlb mapping fails up front when too few huge pages are
+    // reserved, instead of faulting (SIGBUS) later while the ROM is being written
+    fn map(len: usize, extra_flags: libc::c_int) -> Option<NonNull<u8>> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | extra_flags,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            None
+        } else {
+            NonNull::new(ptr.cast())
+        }
+    }
+}
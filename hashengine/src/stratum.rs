@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+// Messages of the pool mode (hash-server with POOL_PORT set): stratum-like JSON-RPC over
+// TCP, one JSON object per line in each direction.
+//
+// Miner -> pool requests, answered by a response with the same id:
+//   mining.subscribe        []                         -> SubscribeResult
+//   mining.authorize        [worker, password]         -> true
+//   mining.submit           [worker, job_id, nonce]    -> SubmitResult (share difficulty)
+//   mining.submit_solution  [worker, job_id, nonce]    -> SubmitResult (full difficulty)
+// Pool -> miner notification (id null):
+//   mining.notify           JobNotify
+//
+// A miner owns every nonce whose high 32 bits are its extranonce, so miners never
+// duplicate each other's work. Nonces are sent as 16 hex chars, as in the preimage.
+
+pub const SUBSCRIBE: &str = "mining.subscribe";
+pub const AUTHORIZE: &str = "mining.authorize";
+pub const NOTIFY: &str = "mining.notify";
+pub const SUBMIT: &str = "mining.submit";
+pub const SUBMIT_SOLUTION: &str = "mining.submit_solution";
+
+// Error codes, following the usual stratum numbering where one exists
+pub const ERR_OTHER: i32 = 20;
+pub const ERR_STALE_JOB: i32 = 21;
+pub const ERR_DUPLICATE: i32 = 22;
+pub const ERR_LOW_DIFFICULTY: i32 = 23;
+pub const ERR_UNAUTHORIZED: i32 = 24;
+pub const ERR_NOT_SUBSCRIBED: i32 = 25;
+pub const ERR_PARSE: i32 = -32700;
+pub const ERR_METHOD_NOT_FOUND: i32 = -32601;
+pub const ERR_INVALID_PARAMS: i32 = -32602;
+
+/// Bits of the nonce owned by the miner (the low half); the high half is the extranonce
+pub const MINER_NONCE_BITS: u32 = 32;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub id: Option<u64>,
+    pub method: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub id: Option<u64>,
+    pub result: serde_json::Value,
+    pub error: Option<RpcError>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: Option<u64>,
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+/// Any line sent by the pool
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PoolMessage {
+    Notification(Notification),
+    Response(Response),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscribeResult {
+    pub session_id: String,
+    /// High 32 bits of every nonce this miner tries, as 8 hex chars
+    pub extranonce: String,
+}
+
+/// ROM parameters, so miners can build the same ROM as the pool
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomParams {
+    /// "TwoStep" or "FullRandom"
+    pub generation: String,
+    pub pre_size: usize,
+    pub mixing_numbers: usize,
+    pub rom_size: usize,
+}
+
+/// Work for one miner. Every miner gets the same job_id; the address is spread over the
+/// pool's addresses.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobNotify {
+    pub job_id: String,
+    pub address: String,
+    pub challenge_id: String,
+    /// Network difficulty, for mining.submit_solution (8 hex chars)
+    pub difficulty: String,
+    /// Leading zero bits a share needs (`hash_structure_good`), for mining.submit
+    pub share_zero_bits: usize,
+    pub no_pre_mine: String,
+    pub latest_submission: String,
+    pub no_pre_mine_hour: String,
+    pub rom: RomParams,
+    pub extranonce: String,
+    /// Always true: earlier jobs are stale as soon as this one arrives
+    pub clean_jobs: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubmitResult {
+    pub accepted: bool,
+    /// The hash also meets the network difficulty
+    pub solution: bool,
+}
+
+impl Request {
+    pub fn new(id: u64, method: &str, params: serde_json::Value) -> Self {
+        Self { id: Some(id), method: method.to_string(), params }
+    }
+}
+
+impl Response {
+    pub fn ok(id: Option<u64>, result: impl Serialize) -> Self {
+        Self { id, result: serde_json::to_value(result).unwrap_or_default(), error: None }
+    }
+
+    pub fn error(id: Option<u64>, code: i32, message: impl Into<String>) -> Self {
+        Self { id, result: serde_json::Value::Null, error: Some(RpcError { code, message: message.into() }) }
+    }
+}
+
+impl Notification {
+    pub fn new(method: &str, params: impl Serialize) -> Self {
+        Self { id: None, method: method.to_string(), params: serde_json::to_value(params).unwrap_or_default() }
+    }
+}
+
+/// Serialize a message as one protocol line (without the trailing newline)
+pub fn to_line(message: &impl Serialize) -> String {
+    serde_json::to_string(message).expect("stratum messages serialize")
+}
@@ -7,6 +7,10 @@ interface ConnectionPoolConfig {
   requestTimeout?: number;
   maxRetries?: number;
   retryDelayMs?: number;
+  /** Bearer token for a hash-server started with AUTH_TOKENS (admin scope, as init() loads ROMs) */
+  authToken?: string;
+  /** Largest request body sent; keep within the server's MAX_BODY_MB (default 16 MB) */
+  maxBodyBytes?: number;
 }
 
 export class HashClient {
@@ -16,6 +20,7 @@ export class HashClient {
   private maxRetries: number;
   private retryDelayMs: number;
   private romInitialized = false;
+  private authHeaders: Record<string, string>;
 
   constructor(baseUrl: string = 'http://127.0.0.1:9001', poolConfig?: ConnectionPoolConfig) {
     this.baseUrl = baseUrl;
@@ -25,6 +30,9 @@ export class HashClient {
     const requestTimeout = poolConfig?.requestTimeout || 10000;
     this.maxRetries = poolConfig?.maxRetries || 3;
     this.retryDelayMs = poolConfig?.retryDelayMs || 100;
+    const maxBodyBytes = poolConfig?.maxBodyBytes || 16 * 1024 * 1024;
+    const authToken = poolConfig?.authToken || process.env.HASH_SERVER_TOKEN;
+    this.authHeaders = authToken ? { 'Authorization': `Bearer ${authToken}` } : {};
 
     this.keepAliveAgent = new http.Agent({
       keepAlive: true,
@@ -40,9 +48,10 @@ export class HashClient {
       httpAgent: this.keepAliveAgent,
       headers: {
         'Connection': 'keep-alive',
+        ...this.authHeaders,
       },
-      maxContentLength: 50 * 1024 * 1024, // 50MB for batch requests
-      maxBodyLength: 50 * 1024 * 1024, // 50MB for batch requests
+      maxContentLength: maxBodyBytes,
+      maxBodyLength: maxBodyBytes,
     });
 
     console.log(`[HashClient] Connection pool initialized: ${maxConnectionsPerUrl} connections to ${baseUrl}`);
@@ -67,7 +76,7 @@ export class HashClient {
       const response = await axios.post(`${this.baseUrl}/init`, initPayload, {
         timeout: 120000,
         httpAgent: new http.Agent({ keepAlive: false }),
-        headers: { 'Connection': 'close' }
+        headers: { 'Connection': 'close', ...this.authHeaders }
       });
 
       const workerPid = response.data.worker_pid;
@@ -151,8 +160,8 @@ export class HashClient {
       } catch (err: any) {
         lastError = err;
 
-        // Don't retry on certain errors
-        if (err.response?.status === 400 || err.response?.status === 404) {
+        // Don't retry on certain errors (413: batch over the server's MAX_BATCH_SIZE or MAX_BODY_MB)
+        if (err.response?.status === 400 || err.response?.status === 404 || err.response?.status === 413) {
           throw new Error(`Failed to batch hash: ${err.response.data?.error || err.message}`);
         }
 
@@ -164,14 +173,16 @@ export class HashClient {
           err.code === 'ECONNABORTED' ||
           err.message.includes('socket hang up') ||
           err.response?.status === 503 ||
+          err.response?.status === 429 || // Admission queue full
           err.response?.status === 408; // Request Timeout
 
         if (!isRetriable || attempt === this.maxRetries - 1) {
           break;
         }
 
-        // Exponential backoff with jitter
-        const delay = this.retryDelayMs * Math.pow(2, attempt) + Math.random() * 100;
+        // Exponential backoff with jitter, waiting at least as long as the server's Retry-After
+        const retryAfterMs = (Number(err.response?.headers?.['retry-after']) || 0) * 1000;
+        const delay = Math.max(retryAfterMs, this.retryDelayMs * Math.pow(2, attempt) + Math.random() * 100);
         await this.sleep(delay);
       }
     }
@@ -222,7 +233,7 @@ export class HashClient {
       const response = await this.axiosInstance.post('/kill-workers', {}, {
         timeout: 5000,
         httpAgent: new http.Agent({ keepAlive: false }),
-        headers: { 'Connection': 'close' }
+        headers: { 'Connection': 'close', ...this.authHeaders }
       });
       console.log(`[HashClient] ✓ Workers killed: ${response.data.message || 'Success'}`);
     } catch (err: any) {
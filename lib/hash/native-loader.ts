@@ -5,13 +5,15 @@
 
 // Native binding interface
 interface NativeBinding {
+  // nb_loops and nb_instrs are ignored: hashing always uses 8 loops and 256 instructions
   initRom(
     no_pre_mine_hex: string,
     nb_loops: number,
     nb_instrs: number,
     pre_size: number,
     rom_size: number,
-    mixing_numbers: number
+    mixing_numbers: number,
+    generation?: string // "TwoStep" (default) or "FullRandom"
   ): void;
   hashPreimage(preimage: string): string;
   romReady(): boolean;
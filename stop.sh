@@ -35,7 +35,13 @@ fi
 if pgrep -f "hash-server" > /dev/null; then
     echo "Stopping hash server..."
     pkill -f "hash-server"
-    sleep 2
+
+    # SIGTERM lets in-flight batches and jobs finish (up to SHUTDOWN_TIMEOUT_SECS)
+    WAIT_SECS=$(( ${SHUTDOWN_TIMEOUT_SECS:-30} + 5 ))
+    for _ in $(seq 1 "$WAIT_SECS"); do
+        pgrep -f "hash-server" > /dev/null || break
+        sleep 1
+    done
 
     # Force kill if still running
     if pgrep -f "hash-server" > /dev/null; then